@@ -1,32 +1,58 @@
-use std::env;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::Path;
 
-use crate::options::{BasicMode, Options};
+use crate::dos_names::DosDrives;
+use crate::logging;
+use crate::options::{resolve_redirect_env, BasicMode, Options, ProcessSource, Source};
 use crate::temp_files::TempFiles;
 
-pub fn create_batch_file(options: &Options, temp_files: &TempFiles) -> Result<(), io::Error> {
+/// Creates the batch file that runs the given program, which is either
+/// `options.program` or a listing of it in the batch directory.
+pub fn create_batch_file(
+    options: &Options,
+    temp_files: &TempFiles,
+    program: &Path,
+) -> Result<(), io::Error> {
+    // all paths are mapped first, as they decide which drives to mount
+    let mut drives = DosDrives::new(&temp_files.batch_dir);
+    let stdin_file = drives.file(&temp_files.stdin_file)?;
+    let stdout_file = drives.file(&temp_files.stdout_file)?;
+    let args_file = drives.file(&temp_files.args_file)?;
+    let boot_file = drives.file(&temp_files.boot_file)?;
+    let program_dir = drives.dir(options.program.parent().unwrap())?;
+    let basic = drives.file(&options.basic)?;
+    let program = if program.parent() == options.program.parent() {
+        program
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_ascii_uppercase()
+    } else {
+        drives.file(program)?
+    };
+
     let mut f = File::create(&temp_files.batch_file)?;
-    copy_env(&mut f)?;
-    write!(
-        f,
-        "SET STDIN={}\r\n",
-        from_dos(&temp_files.stdin_file, &temp_files.batch_dir)
+    // MOUNT D "/basic/rest-qb-direct"
+    for (drive, dir) in drives.mounts() {
+        write!(f, "MOUNT {} \"{}\"\r\n", drive, mount_path(dir)?)?;
+    }
+    copy_env(
+        &mut f,
+        &forwarded_env(&ProcessSource, options.redirect_depth),
     )?;
-    write!(f, "C:\r\n")?;
+    write!(f, "SET STDIN={}\r\n", stdin_file)?;
+    write_args(&mut f, options, &args_file)?;
+    // C:
+    write!(f, "{}\r\n", &program_dir[..2])?;
     // CD C:\SRC
-    write!(
-        f,
-        "CD {}\r\n",
-        from_dos(
-            &options.program.parent().unwrap().to_path_buf(),
-            &temp_files.batch_dir
-        )
-    )?;
+    write!(f, "CD {}\r\n", program_dir)?;
+    // ECHO.>C:\K3X9QZ0A.BOT
+    write!(f, "ECHO.>{}\r\n", boot_file)?;
     // C:\BIN\GWBASIC.EXE
-    write!(f, "{}", from_dos(&options.basic, &temp_files.batch_dir))?;
+    write!(f, "{}", basic)?;
     write!(
         f,
         "{}",
@@ -36,65 +62,148 @@ pub fn create_batch_file(options: &Options, temp_files: &TempFiles) -> Result<()
         }
     )?;
     // PROGRAM.BAS
-    write!(
-        f,
-        "{}",
-        options.program.file_name().unwrap().to_str().unwrap()
-    )?;
-    // <C:\STDIN.TXT
-    write!(
-        f,
-        " <{}",
-        from_dos(&temp_files.stdin_file, &temp_files.batch_dir)
-    )?;
-    // >C:\STDOUT.TXT
-    write!(
-        f,
-        " >{}\r\n",
-        from_dos(&temp_files.stdout_file, &temp_files.batch_dir)
-    )
+    write!(f, "{}", program)?;
+    // /CMD ARG1 ARG2
+    if supports_cmd_switch(options) {
+        if let Some(command_tail) = command_tail(&options.program_args) {
+            write!(f, " /CMD {}", command_tail)?;
+        }
+    }
+    // <C:\STDIN.TXT >C:\STDOUT.TXT
+    write!(f, " <{} >{}\r\n", stdin_file, stdout_file)
+}
+
+/// The host directory as given to DOSBox's MOUNT command.
+fn mount_path(dir: &Path) -> Result<String, io::Error> {
+    let path = dir.to_str().unwrap_or_default();
+    let path = path.strip_prefix("\\\\?\\").unwrap_or(path);
+    match escape_batch(path) {
+        Some(escaped) if !escaped.is_empty() && !escaped.contains('"') => Ok(escaped),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} can't be mounted from a batch file", dir.display()),
+        )),
+    }
 }
 
-fn from_dos(f: &PathBuf, batch_dir: &PathBuf) -> String {
-    let mut result: String = String::new();
-    let mut p: PathBuf = f.to_path_buf();
-    while p != *batch_dir {
-        if !result.is_empty() {
-            result.insert(0, '\\');
+/// Makes the program arguments available as environment variables.
+///
+/// ARGS points to a file with one argument per line, ARGC holds the number
+/// of arguments and ARG1..ARGn the arguments themselves. Arguments that can't
+/// be expressed on a batch file line are only available in the ARGS file.
+fn write_args(f: &mut File, options: &Options, args_file: &str) -> Result<(), io::Error> {
+    write!(f, "SET ARGS={}\r\n", args_file)?;
+    write!(f, "SET ARGC={}\r\n", options.program_args.len())?;
+    for (i, arg) in options.program_args.iter().enumerate() {
+        if let Some(escaped) = escape_batch(arg) {
+            if !escaped.is_empty() {
+                write!(f, "SET ARG{}={}\r\n", i + 1, escaped)?;
+            }
         }
+    }
+    Ok(())
+}
+
+/// QuickBASIC (QB.EXE) exposes the `/CMD` switch as `COMMAND$`.
+/// QBasic and GW-Basic have no such switch.
+fn supports_cmd_switch(options: &Options) -> bool {
+    match options.mode {
+        BasicMode::QBasic => options
+            .basic
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(|s| s.eq_ignore_ascii_case("QB"))
+            .unwrap_or(false),
+        BasicMode::GWBasic => false,
+    }
+}
 
-        result.insert_str(0, p.file_name().unwrap().to_str().unwrap());
-        p = p.parent().unwrap().to_path_buf();
+/// Joins the arguments into the command tail of the `/CMD` switch,
+/// quoting the ones that contain spaces.
+/// Returns `None` if there are no arguments or if one of them can't be
+/// expressed on a batch file line.
+fn command_tail(args: &[String]) -> Option<String> {
+    if args.is_empty() {
+        return None;
+    }
+    let mut result: Vec<String> = vec![];
+    for arg in args {
+        let escaped = escape_batch(arg)?;
+        if escaped.is_empty() || escaped.contains(' ') {
+            result.push(format!("\"{}\"", escaped));
+        } else {
+            result.push(escaped);
+        }
     }
+    Some(result.join(" "))
+}
 
-    if !result.starts_with("\\") {
-        result.insert(0, '\\');
+/// Escapes a value so that it survives being written in a batch file.
+///
+/// Percent signs are doubled, otherwise they would be expanded as variables.
+/// COMMAND.COM has no way of escaping redirection and pipe characters,
+/// so values containing them (or non-printable ASCII) give `None`.
+fn escape_batch(value: &str) -> Option<String> {
+    let mut result = String::new();
+    for ch in value.chars() {
+        match ch {
+            '%' => result.push_str("%%"),
+            '<' | '>' | '|' => return None,
+            ' '..='~' => result.push(ch),
+            _ => return None,
+        }
     }
-    result.insert_str(0, "C:");
-    result
+    Some(result)
 }
 
-fn copy_env(f: &mut File) -> Result<(), io::Error> {
-    for kv in env::vars() {
-        if is_valid_env_key(&kv.0) && is_valid_env_value(&kv.1) {
-            write!(f, "SET {}={}\r\n", kv.0, kv.1)?;
+/// Sets the forwarded variables, escaped like the program arguments.
+/// Values that can't be expressed on a batch file line are left out.
+fn copy_env(f: &mut impl Write, env: &[(String, String)]) -> Result<(), io::Error> {
+    for (key, value) in env {
+        match escape_batch(value) {
+            Some(escaped) => write!(f, "SET {}={}\r\n", key, escaped)?,
+            None => logging::log(
+                logging::Level::Warn,
+                "variable not forwarded",
+                &[
+                    ("key", key.as_str().into()),
+                    ("value", value.as_str().into()),
+                ],
+            ),
         }
     }
     Ok(())
 }
 
+/// The variables of the launcher's environment that are copied into the batch file.
+/// Like the launcher's own options, they are taken from the request that the
+/// client sent, i.e. from their `REDIRECT_` variant if there is one.
+pub fn forwarded_env(source: &dyn Source, redirect_depth: usize) -> Vec<(String, String)> {
+    WHITE_LIST_KEYS
+        .iter()
+        .filter_map(|key| {
+            resolve_redirect_env(source, key, redirect_depth).map(|v| (key.to_string(), v.value))
+        })
+        .filter(|kv| is_valid_env_value(&kv.1))
+        .collect()
+}
+
 /// Environment variables that are allowed to appear in the Batch file.
-const WHITE_LIST_KEYS: &[&str] = &[
-    "CONTENT_TYPE",
-    "QUERY_STRING",
-    "REQUEST_METHOD",
-    "STDIN",
-];
+pub const WHITE_LIST_KEYS: &[&str] = &["CONTENT_TYPE", "QUERY_STRING", "REQUEST_METHOD", "STDIN"];
 
 fn is_valid_env_key(key: &str) -> bool {
-    match WHITE_LIST_KEYS.binary_search(&key) {
-        Ok(_) => true,
-        _ => false
+    WHITE_LIST_KEYS.binary_search(&key).is_ok()
+}
+
+/// Checks if the batch file sets the given environment variable, either by
+/// copying it from the launcher's environment or by setting it itself.
+pub fn is_forwarded_env_key(key: &str) -> bool {
+    match key {
+        "ARGS" | "ARGC" => true,
+        _ if is_valid_env_key(key) => true,
+        _ => key
+            .strip_prefix("ARG")
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())),
     }
 }
 
@@ -105,20 +214,132 @@ fn is_valid_env_value(val: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::options::tests::FakeSource;
+
+    #[test]
+    fn test_mount_path() {
+        assert_eq!(
+            mount_path(Path::new("/basic/my programs")).unwrap(),
+            "/basic/my programs"
+        );
+        assert_eq!(
+            mount_path(Path::new("\\\\?\\C:\\100%")).unwrap(),
+            "C:\\100%%"
+        );
+        assert!(mount_path(Path::new("/basic/a>b")).is_err());
+    }
+
+    #[test]
+    fn test_escape_batch_plain() {
+        assert_eq!(escape_batch("hello world"), Some("hello world".to_string()));
+    }
+
+    #[test]
+    fn test_escape_batch_percent() {
+        assert_eq!(escape_batch("100%"), Some("100%%".to_string()));
+    }
+
+    #[test]
+    fn test_escape_batch_redirection() {
+        assert_eq!(escape_batch("a>b"), None);
+        assert_eq!(escape_batch("a<b"), None);
+        assert_eq!(escape_batch("a|b"), None);
+    }
+
+    #[test]
+    fn test_escape_batch_non_printable() {
+        assert_eq!(escape_batch("a\tb"), None);
+        assert_eq!(escape_batch("caf\u{e9}"), None);
+    }
+
+    #[test]
+    fn test_command_tail() {
+        let args = vec!["a".to_string(), "b c".to_string(), "".to_string()];
+        assert_eq!(command_tail(&args), Some("a \"b c\" \"\"".to_string()));
+    }
+
+    #[test]
+    fn test_command_tail_no_args() {
+        assert_eq!(command_tail(&[]), None);
+    }
+
+    #[test]
+    fn test_command_tail_unsupported_arg() {
+        let args = vec!["a".to_string(), "b|c".to_string()];
+        assert_eq!(command_tail(&args), None);
+    }
+
+    #[test]
+    fn test_forwarded_env() {
+        let source = FakeSource::new(&[
+            ("REQUEST_METHOD", "GET"),
+            ("REDIRECT_REQUEST_METHOD", "POST"),
+            ("QUERY_STRING", "page=404"),
+            ("REDIRECT_REDIRECT_REDIRECT_QUERY_STRING", "id=1"),
+            ("CONTENT_TYPE", ""),
+            ("HTTP_HOST", "example.com"),
+        ]);
+        let env = |depth| forwarded_env(&source, depth);
+        assert_eq!(
+            env(2),
+            vec![
+                ("QUERY_STRING".to_string(), "page=404".to_string()),
+                ("REQUEST_METHOD".to_string(), "POST".to_string()),
+            ]
+        );
+        assert_eq!(
+            env(3),
+            vec![
+                ("QUERY_STRING".to_string(), "id=1".to_string()),
+                ("REQUEST_METHOD".to_string(), "POST".to_string()),
+            ]
+        );
+        assert_eq!(
+            env(0),
+            vec![
+                ("QUERY_STRING".to_string(), "page=404".to_string()),
+                ("REQUEST_METHOD".to_string(), "GET".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_copy_env() {
+        let env = vec![
+            ("QUERY_STRING".to_string(), "q=100%25".to_string()),
+            ("REQUEST_METHOD".to_string(), "GET".to_string()),
+        ];
+        let mut batch = vec![];
+        copy_env(&mut batch, &env).unwrap();
+        assert_eq!(
+            String::from_utf8(batch).unwrap(),
+            "SET QUERY_STRING=q=100%%25\r\nSET REQUEST_METHOD=GET\r\n"
+        );
+    }
 
     #[test]
-    fn test_from_dos_same_level() {
-        let f = PathBuf::from("/home/test/PROGRAM.BAS");
-        let dir = PathBuf::from("/home/test");
-        let dos = from_dos(&f, &dir);
-        assert_eq!(dos, "C:\\PROGRAM.BAS");
+    fn test_copy_env_unsupported_value() {
+        let env = vec![
+            ("CONTENT_TYPE".to_string(), "text/<plain>".to_string()),
+            ("QUERY_STRING".to_string(), "a|b".to_string()),
+            ("REQUEST_METHOD".to_string(), "GET".to_string()),
+        ];
+        let mut batch = vec![];
+        copy_env(&mut batch, &env).unwrap();
+        assert_eq!(
+            String::from_utf8(batch).unwrap(),
+            "SET REQUEST_METHOD=GET\r\n"
+        );
     }
 
     #[test]
-    fn test_from_dos_one_level() {
-        let f = PathBuf::from("/home/test/PROGRAM.BAS");
-        let dir = PathBuf::from("/home");
-        let dos = from_dos(&f, &dir);
-        assert_eq!(dos, "C:\\test\\PROGRAM.BAS");
+    fn test_is_forwarded_env_key() {
+        assert!(is_forwarded_env_key("QUERY_STRING"));
+        assert!(is_forwarded_env_key("ARGS"));
+        assert!(is_forwarded_env_key("ARGC"));
+        assert!(is_forwarded_env_key("ARG12"));
+        assert!(!is_forwarded_env_key("ARG"));
+        assert!(!is_forwarded_env_key("ARGX"));
+        assert!(!is_forwarded_env_key("HTTP_HOST"));
     }
 }
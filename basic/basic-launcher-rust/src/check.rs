@@ -0,0 +1,540 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::batch_file::is_forwarded_env_key;
+use crate::dialect;
+use crate::dos_names::{is_valid_8_3, is_valid_dos_path};
+use crate::lexer::{lex, Line, Token};
+use crate::options::BasicMode;
+use crate::tokenized;
+
+/// Statements whose string literals are file names.
+const FILE_STATEMENTS: &[&str] = &[
+    "BLOAD", "BSAVE", "CHAIN", "KILL", "LOAD", "MERGE", "NAME", "OPEN", "RUN", "SAVE",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a program.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The BASIC line number, or the line in the file for lines without one.
+    /// Zero for problems that concern the whole file.
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(line: usize, message: String) -> Self {
+        Self {
+            line,
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(line: usize, message: String) -> Self {
+        Self {
+            line,
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        if self.line > 0 {
+            write!(f, "{}: {}: {}", self.line, severity, self.message)
+        } else {
+            write!(f, "{}: {}", severity, self.message)
+        }
+    }
+}
+
+/// Checks a program file for problems that make it fail or hang under the launcher.
+pub fn check_file(program: &Path, mode: BasicMode) -> io::Result<Vec<Diagnostic>> {
+    let mut result = check_program_name(program);
+    result.extend(check_program(&fs::read(program)?, mode)?);
+    Ok(result)
+}
+
+/// The program is run by name from its own directory, so the name must be valid in DOS.
+fn check_program_name(program: &Path) -> Vec<Diagnostic> {
+    match program.file_name().and_then(|f| f.to_str()) {
+        Some(name) if is_valid_8_3(name) => vec![],
+        _ => vec![Diagnostic::error(
+            0,
+            format!(
+                "the program file name {:?} is not a valid DOS 8.3 name",
+                program.file_name().unwrap_or_default()
+            ),
+        )],
+    }
+}
+
+/// Checks the contents of a program, either a listing or a tokenized GW-Basic file.
+pub fn check_program(contents: &[u8], mode: BasicMode) -> io::Result<Vec<Diagnostic>> {
+    let listing = if tokenized::is_tokenized(contents) {
+        tokenized::detokenize(contents)?
+    } else {
+        contents.to_vec()
+    };
+    let lines = lex(&String::from_utf8_lossy(&listing));
+    let mut result: Vec<Diagnostic> = vec![];
+    let all_numbered = !lines.is_empty() && lines.iter().all(|line| line.number.is_some());
+    match mode {
+        BasicMode::GWBasic if all_numbered => result.extend(check_flow(&lines)),
+        _ => result.extend(check_exit(&lines, mode)),
+    }
+    result.extend(check_environ(&lines));
+    result.extend(check_file_names(&lines));
+    result.sort_by_key(|d| d.line);
+    Ok(result)
+}
+
+/// How control leaves a GW-Basic line.
+#[derive(Debug, Default)]
+struct Flow {
+    /// Lines jumped to with GOTO, GOSUB, THEN, ELSE, RESUME etc.
+    targets: Vec<u32>,
+    /// Error handlers installed with ON ERROR GOTO.
+    handlers: Vec<u32>,
+    /// True if execution can continue with the next line.
+    falls_through: bool,
+    /// END or STOP, which return to the interpreter prompt.
+    stops: Option<String>,
+    has_system: bool,
+    /// ON ERROR GOTO 0
+    disables_trapping: bool,
+}
+
+fn line_flow(tokens: &[Token]) -> Flow {
+    let mut flow = Flow {
+        falls_through: true,
+        ..Flow::default()
+    };
+    let mut in_if = false;
+    let mut in_on = false;
+    let mut on_error = false;
+    for (i, token) in tokens.iter().enumerate() {
+        let word = match token {
+            Token::Word(word) => word.as_str(),
+            Token::Colon => {
+                if !in_if {
+                    in_on = false;
+                    on_error = false;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let unconditional = !in_if && !in_on;
+        match word {
+            "IF" => in_if = true,
+            "ON" => {
+                in_on = true;
+                on_error = matches!(tokens.get(i + 1), Some(Token::Word(w)) if w == "ERROR");
+            }
+            "GOTO" if on_error => {
+                let numbers = line_numbers_after(&tokens[i + 1..]);
+                if numbers == [0] {
+                    flow.disables_trapping = true;
+                } else {
+                    flow.handlers.extend(numbers);
+                }
+            }
+            "GOTO" | "GOSUB" | "THEN" | "ELSE" | "RESUME" | "RETURN" => {
+                let numbers = line_numbers_after(&tokens[i + 1..]);
+                flow.targets.extend(numbers.into_iter().filter(|n| *n > 0));
+                if unconditional && word != "GOSUB" {
+                    flow.falls_through = false;
+                }
+            }
+            "END" | "STOP" if is_statement_end(tokens.get(i + 1)) => {
+                flow.stops.get_or_insert_with(|| word.to_string());
+                if unconditional {
+                    flow.falls_through = false;
+                }
+            }
+            "SYSTEM" | "RUN" | "CHAIN" => {
+                flow.has_system |= word == "SYSTEM";
+                if unconditional {
+                    flow.falls_through = false;
+                }
+            }
+            _ => {}
+        }
+    }
+    flow
+}
+
+/// The line numbers following GOTO and friends, e.g. `100` or `100, 200, 300`.
+fn line_numbers_after(tokens: &[Token]) -> Vec<u32> {
+    let mut result: Vec<u32> = vec![];
+    for token in tokens {
+        match token {
+            Token::Number(n) => match n.parse() {
+                Ok(n) => result.push(n),
+                Err(_) => break,
+            },
+            Token::Symbol(',') => {}
+            _ => break,
+        }
+    }
+    result
+}
+
+/// Tells END apart from END IF, END SUB and friends.
+fn is_statement_end(token: Option<&Token>) -> bool {
+    match token {
+        None | Some(Token::Colon) => true,
+        Some(Token::Word(word)) => word == "ELSE",
+        _ => false,
+    }
+}
+
+/// Follows the line numbers of a GW-Basic program from the first line and
+/// from the error handlers it installs.
+fn check_flow(lines: &[Line]) -> Vec<Diagnostic> {
+    let flows: Vec<Flow> = lines.iter().map(|line| line_flow(&line.tokens)).collect();
+    let by_number: HashMap<u32, usize> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| (line.number.unwrap(), i))
+        .collect();
+    let mut result: Vec<Diagnostic> = vec![];
+    for (line, flow) in lines.iter().zip(flows.iter()) {
+        for target in flow.targets.iter().chain(flow.handlers.iter()) {
+            if !by_number.contains_key(target) {
+                result.push(Diagnostic::error(
+                    line.display_number(),
+                    format!("jump to undefined line {}", target),
+                ));
+            }
+        }
+    }
+
+    let main = reachable(&[0], &flows, &by_number);
+    let handler_entries: Vec<usize> = main
+        .iter()
+        .flat_map(|i| flows[*i].handlers.iter())
+        .filter_map(|n| by_number.get(n).copied())
+        .collect();
+    let handlers = reachable(&handler_entries, &flows, &by_number);
+    let all: BTreeSet<usize> = main.union(&handlers).copied().collect();
+
+    if !all.iter().any(|i| flows[*i].has_system) {
+        result.push(Diagnostic::error(
+            0,
+            "no reachable SYSTEM statement, DOSBox will never exit".to_string(),
+        ));
+    }
+    for i in all {
+        let line = lines[i].display_number();
+        let flow = &flows[i];
+        if let Some(stops) = &flow.stops {
+            result.push(Diagnostic::error(
+                line,
+                format!("{} returns to the interpreter prompt, use SYSTEM", stops),
+            ));
+        }
+        if flow.disables_trapping && handlers.contains(&i) {
+            result.push(Diagnostic::error(
+                line,
+                "ON ERROR GOTO 0 in an error handler stops at the interpreter prompt".to_string(),
+            ));
+        }
+        if flow.falls_through && i + 1 == lines.len() {
+            result.push(Diagnostic::error(
+                line,
+                "execution continues past the last line and stops at the interpreter prompt"
+                    .to_string(),
+            ));
+        }
+    }
+    result
+}
+
+fn reachable(
+    entries: &[usize],
+    flows: &[Flow],
+    by_number: &HashMap<u32, usize>,
+) -> BTreeSet<usize> {
+    let mut visited: BTreeSet<usize> = BTreeSet::new();
+    let mut pending: Vec<usize> = entries.to_vec();
+    while let Some(i) = pending.pop() {
+        if i >= flows.len() || !visited.insert(i) {
+            continue;
+        }
+        if flows[i].falls_through {
+            pending.push(i + 1);
+        }
+        pending.extend(flows[i].targets.iter().filter_map(|n| by_number.get(n)));
+    }
+    visited
+}
+
+/// Without line numbers there is no cheap way to follow the flow,
+/// so only look for SYSTEM and for END statements.
+fn check_exit(lines: &[Line], mode: BasicMode) -> Vec<Diagnostic> {
+    let mut result: Vec<Diagnostic> = vec![];
+    let mut has_system = false;
+    for line in lines {
+        let flow = line_flow(&line.tokens);
+        has_system |= flow.has_system;
+        if let Some(stops) = flow.stops {
+            let prompt = match mode {
+                BasicMode::GWBasic => "the interpreter prompt",
+                BasicMode::QBasic => "the QBasic editor",
+            };
+            result.push(Diagnostic::warning(
+                line.display_number(),
+                format!("{} returns to {}, use SYSTEM", stops, prompt),
+            ));
+        }
+    }
+    if !has_system {
+        result.push(Diagnostic::error(
+            0,
+            "the program never calls SYSTEM, DOSBox will never exit".to_string(),
+        ));
+    }
+    result
+}
+
+/// `ENVIRON$("KEY")` is always empty for keys the batch file doesn't set.
+fn check_environ(lines: &[Line]) -> Vec<Diagnostic> {
+    let mut result: Vec<Diagnostic> = vec![];
+    for line in lines {
+        for window in line.tokens.windows(4) {
+            if let [Token::Word(word), Token::Symbol('('), Token::Str(key), Token::Symbol(')')] =
+                window
+            {
+                if word == "ENVIRON$" && !is_forwarded_env_key(&key.to_ascii_uppercase()) {
+                    result.push(Diagnostic::warning(
+                        line.display_number(),
+                        format!(
+                            "ENVIRON$(\"{}\") is always empty, the launcher does not forward {}",
+                            key, key
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+    result
+}
+
+/// String literals of file statements must be valid DOS paths.
+fn check_file_names(lines: &[Line]) -> Vec<Diagnostic> {
+    let mut result: Vec<Diagnostic> = vec![];
+    for line in lines {
+        let mut in_file_statement = false;
+        for token in &line.tokens {
+            match token {
+                Token::Word(word) if FILE_STATEMENTS.contains(&word.as_str()) => {
+                    in_file_statement = true
+                }
+                Token::Colon => in_file_statement = false,
+                Token::Str(name) if in_file_statement && !is_valid_dos_path(name) => {
+                    result.push(Diagnostic::error(
+                        line.display_number(),
+                        format!("{:?} is not a valid DOS 8.3 file name", name),
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+    result
+}
+
+/// Implements the `check` command: `check PROGRAM.BAS...`.
+///
+/// Prints the problems of each program and fails if any of them has errors.
+pub fn check_command(args: &[String]) -> io::Result<()> {
+    if args.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Usage: basic-launcher-rust check PROGRAM.BAS...",
+        ));
+    }
+    let mut errors = 0;
+    for arg in args {
+        let program = Path::new(arg);
+        let mode = dialect::detect(&fs::read(program)?)
+            .map(|d| d.mode)
+            .unwrap_or(BasicMode::GWBasic);
+        for diagnostic in check_file(program, mode)? {
+            if diagnostic.severity == Severity::Error {
+                errors += 1;
+            }
+            print_diagnostic(program, &diagnostic);
+        }
+    }
+    if errors > 0 {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} error(s) found", errors),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Prints a diagnostic on stderr, prefixed with the program.
+pub fn print_diagnostic(program: &Path, diagnostic: &Diagnostic) {
+    eprintln!("{}:{}", program.display(), diagnostic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_gw(text: &str) -> Vec<String> {
+        check_program(text.as_bytes(), BasicMode::GWBasic)
+            .unwrap()
+            .iter()
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    fn check_qb(text: &str) -> Vec<String> {
+        check_program(text.as_bytes(), BasicMode::QBasic)
+            .unwrap()
+            .iter()
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_hello_is_clean() {
+        assert!(check_gw(include_str!("../../basic/src/HELLO.BAS")).is_empty());
+        assert!(check_gw(include_str!("../../basic/src/ECHO.BAS")).is_empty());
+        assert!(check_qb(include_str!("../../basic/src/HELLOQB.BAS")).is_empty());
+    }
+
+    #[test]
+    fn test_rest_qbasic_is_clean() {
+        assert!(check_qb(include_str!("../../basic/rest-qb/TODO.BAS")).is_empty());
+    }
+
+    #[test]
+    fn test_create_error_handler() {
+        assert_eq!(
+            check_gw(include_str!("../../basic/rest/CREATE.BAS")),
+            vec![
+                "2020: error: ON ERROR GOTO 0 in an error handler stops at the interpreter prompt",
+                "2020: error: execution continues past the last line and stops at the interpreter prompt",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenized_program() {
+        let contents = include_bytes!("../fixtures/tokenized/HELLO.BAS");
+        assert!(check_program(contents, BasicMode::GWBasic)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_missing_system() {
+        assert_eq!(
+            check_gw("10 PRINT \"hi\"\r\n20 END\r\n"),
+            vec![
+                "error: no reachable SYSTEM statement, DOSBox will never exit",
+                "20: error: END returns to the interpreter prompt, use SYSTEM",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_system() {
+        assert_eq!(
+            check_gw("10 GOTO 10\r\n20 SYSTEM\r\n"),
+            vec!["error: no reachable SYSTEM statement, DOSBox will never exit"]
+        );
+    }
+
+    #[test]
+    fn test_conditional_jumps() {
+        assert!(
+            check_gw("10 IF X THEN 30\r\n20 STOP\r\n30 SYSTEM\r\n").contains(
+                &"20: error: STOP returns to the interpreter prompt, use SYSTEM".to_string()
+            )
+        );
+        assert!(
+            check_gw("10 ON X GOTO 30, 40\r\n20 SYSTEM\r\n30 SYSTEM\r\n40 SYSTEM\r\n").is_empty()
+        );
+        assert!(check_gw("10 GOSUB 30\r\n20 SYSTEM\r\n30 RETURN\r\n").is_empty());
+    }
+
+    #[test]
+    fn test_undefined_line() {
+        assert_eq!(
+            check_gw("10 GOTO 30\r\n20 SYSTEM\r\n"),
+            vec![
+                "error: no reachable SYSTEM statement, DOSBox will never exit",
+                "10: error: jump to undefined line 30",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_on_error_goto_0_outside_handler() {
+        assert!(check_gw(
+            "10 ON ERROR GOTO 100\r\n20 ON ERROR GOTO 0\r\n30 SYSTEM\r\n100 RESUME 30\r\n"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_qbasic_end() {
+        assert_eq!(
+            check_qb("PRINT \"hi\"\r\nIF X THEN\r\nEND\r\nEND IF\r\n"),
+            vec![
+                "error: the program never calls SYSTEM, DOSBox will never exit",
+                "3: warning: END returns to the QBasic editor, use SYSTEM",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_environ_keys() {
+        assert_eq!(
+            check_gw("10 A$ = ENVIRON$(\"HTTP_HOST\") + ENVIRON$(\"args\") + ENVIRON$(\"ARG2\")\r\n20 SYSTEM\r\n"),
+            vec!["10: warning: ENVIRON$(\"HTTP_HOST\") is always empty, the launcher does not forward HTTP_HOST"]
+        );
+    }
+
+    #[test]
+    fn test_file_names() {
+        assert_eq!(
+            check_gw("10 OPEN \"O\", #1, \"TODO.DATA\"\r\n20 KILL \"C:\\DATA\\TODO.DAT\": PRINT \"MY FILE.TXT\"\r\n30 SYSTEM\r\n"),
+            vec!["10: error: \"TODO.DATA\" is not a valid DOS 8.3 file name"]
+        );
+    }
+
+    #[test]
+    fn test_program_name() {
+        assert!(check_program_name(Path::new("/src/HELLO.BAS")).is_empty());
+        assert_eq!(
+            check_program_name(Path::new("/src/hello world.bas"))[0].to_string(),
+            "error: the program file name \"hello world.bas\" is not a valid DOS 8.3 name"
+        );
+    }
+}
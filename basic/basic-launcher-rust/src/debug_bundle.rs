@@ -0,0 +1,194 @@
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::batch_file::is_forwarded_env_key;
+use crate::options::Options;
+use crate::temp_files::TempFiles;
+use crate::timings::Timings;
+
+/// Writes everything about one launch into its own directory under `dir`,
+/// so that a failing request can be examined after its temporary files are
+/// gone. Returns the directory of the bundle.
+///
+/// The directory is named after the start time and the request ID, e.g.
+/// `1700000000-K3X9QZ0AB1C2D3E4`, so that bundles sort chronologically.
+pub fn write_bundle(
+    dir: &Path,
+    options: &Options,
+    temp_files: &TempFiles,
+    timings: &Timings,
+    exit_code: Option<i32>,
+) -> io::Result<PathBuf> {
+    let bundle = dir.join(bundle_name(&options.request_id, SystemTime::now()));
+    fs::create_dir_all(&bundle)?;
+    fs::write(bundle.join("options.txt"), format!("{:#?}\n", options))?;
+    fs::write(bundle.join("environment.txt"), environment(env::vars()))?;
+    copy_if_exists(&temp_files.batch_file, &bundle.join("batch.bat"))?;
+    copy_if_exists(&temp_files.stdin_file, &bundle.join("stdin.txt"))?;
+    copy_if_exists(&temp_files.stdout_file, &bundle.join("stdout.txt"))?;
+    copy_if_exists(&temp_files.args_file, &bundle.join("args.txt"))?;
+    copy_if_exists(&temp_files.listing_file, &bundle.join("listing.bas"))?;
+    copy_if_exists(&temp_files.dosbox_log_file, &bundle.join("dosbox.log"))?;
+    copy_if_exists(&temp_files.dosbox_err_file, &bundle.join("dosbox.err"))?;
+    fs::write(
+        bundle.join("summary.txt"),
+        summary(&options.request_id, &options.program, timings, exit_code),
+    )?;
+    Ok(bundle)
+}
+
+pub fn bundle_name(request_id: &str, time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    format!("{}-{}", seconds, request_id)
+}
+
+/// The standard CGI metadata variables (RFC 3875), which tell how Apache
+/// launched the program.
+const CGI_KEYS: &[&str] = &[
+    "AUTH_TYPE",
+    "CONTENT_LENGTH",
+    "CONTENT_TYPE",
+    "GATEWAY_INTERFACE",
+    "PATH_INFO",
+    "PATH_TRANSLATED",
+    "QUERY_STRING",
+    "REMOTE_ADDR",
+    "REMOTE_HOST",
+    "REMOTE_IDENT",
+    "REMOTE_USER",
+    "REQUEST_METHOD",
+    "SCRIPT_NAME",
+    "SERVER_NAME",
+    "SERVER_PORT",
+    "SERVER_PROTOCOL",
+    "SERVER_SOFTWARE",
+];
+
+/// Lists the variables, but only the values of the launcher's own, the
+/// forwarded and the CGI metadata ones. The others can hold credentials,
+/// e.g. `HTTP_COOKIE` or `HTTP_AUTHORIZATION`, so their values are redacted.
+fn environment(vars: impl Iterator<Item = (String, String)>) -> String {
+    let mut vars: Vec<(String, String)> = vars.collect();
+    vars.sort();
+    vars.iter()
+        .map(|(key, value)| {
+            if is_shown_key(key) {
+                format!("{}={}\n", key, value)
+            } else {
+                format!("{}=<redacted>\n", key)
+            }
+        })
+        .collect()
+}
+
+fn is_shown_key(key: &str) -> bool {
+    let mut key = key;
+    while let Some(redirected) = key.strip_prefix("REDIRECT_") {
+        key = redirected;
+    }
+    let secret = ["PASSWORD", "SECRET", "TOKEN"]
+        .iter()
+        .any(|word| key.contains(word));
+    !secret && (key.starts_with("BLR_") || is_forwarded_env_key(key) || CGI_KEYS.contains(&key))
+}
+
+/// Copies a temporary file. Empty files are skipped, as most launches
+/// have no arguments and no listing.
+fn copy_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::metadata(from) {
+        Ok(metadata) if metadata.len() > 0 => fs::copy(from, to).map(|_| ()),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn summary(request_id: &str, program: &Path, timings: &Timings, exit_code: Option<i32>) -> String {
+    let mut result = String::new();
+    writeln!(result, "request_id: {}", request_id).unwrap();
+    writeln!(result, "program: {}", program.display()).unwrap();
+    match exit_code {
+        Some(code) => writeln!(result, "exit_status: {}", code).unwrap(),
+        None => writeln!(result, "exit_status: killed by a signal").unwrap(),
+    }
+    for (name, duration) in timings.phases() {
+        writeln!(
+            result,
+            "{}_ms: {:.3}",
+            name,
+            duration.as_secs_f64() * 1000.0
+        )
+        .unwrap();
+    }
+    writeln!(
+        result,
+        "total_ms: {:.3}",
+        timings.total().as_secs_f64() * 1000.0
+    )
+    .unwrap();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bundle_name() {
+        let time = UNIX_EPOCH + Duration::from_secs(1700000000);
+        assert_eq!(bundle_name("ABC", time), "1700000000-ABC");
+    }
+
+    #[test]
+    fn test_environment_redacts_credentials() {
+        let vars = [
+            ("HTTP_COOKIE", "session=s3cr3t"),
+            ("HTTP_AUTHORIZATION", "Basic dXNlcjpwYXNz"),
+            ("BLR_API_TOKEN", "t0k3n"),
+            ("DB_PASSWORD", "hunter2"),
+            ("BLR_BASIC_MODE", "qbasic"),
+            ("REDIRECT_QUERY_STRING", "id=1"),
+            ("SERVER_PORT", "80"),
+        ];
+        let environment = environment(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        for (_, secret) in &vars[..4] {
+            assert!(!environment.contains(secret), "{}", environment);
+        }
+        assert!(environment.contains("HTTP_COOKIE=<redacted>\n"));
+        assert!(environment.contains("BLR_BASIC_MODE=qbasic\n"));
+        assert!(environment.contains("REDIRECT_QUERY_STRING=id=1\n"));
+        assert!(environment.contains("SERVER_PORT=80\n"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut timings = Timings::new();
+        timings.phase("prepare", || {});
+        timings.phase("dosbox", || {});
+        let summary = summary("ABC", Path::new("/basic/HELLO.BAS"), &timings, Some(0));
+        let keys: Vec<&str> = summary
+            .lines()
+            .map(|line| line.split(": ").next().unwrap())
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                "request_id",
+                "program",
+                "exit_status",
+                "prepare_ms",
+                "dosbox_ms",
+                "total_ms"
+            ]
+        );
+        assert!(summary.starts_with("request_id: ABC\nprogram: /basic/HELLO.BAS\nexit_status: 0\n"));
+    }
+}
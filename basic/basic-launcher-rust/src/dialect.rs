@@ -0,0 +1,289 @@
+use crate::options::BasicMode;
+
+/// First byte of a GW-Basic program saved in tokenized format.
+pub const TOKENIZED_SIGNATURE: u8 = 0xFF;
+
+/// First byte of a GW-Basic program saved with `SAVE "FILE",P`.
+pub const PROTECTED_SIGNATURE: u8 = 0xFE;
+
+/// The dialect of a BASIC program and how it was decided.
+#[derive(Debug)]
+pub struct Dialect {
+    pub mode: BasicMode,
+    pub reason: String,
+    /// True if the dialect was requested by the user, false if it was detected.
+    pub explicit: bool,
+}
+
+impl Dialect {
+    pub fn explicit(mode: BasicMode, reason: String) -> Self {
+        Self {
+            mode,
+            reason,
+            explicit: true,
+        }
+    }
+
+    fn detected(mode: BasicMode, reason: String) -> Self {
+        Self {
+            mode,
+            reason,
+            explicit: false,
+        }
+    }
+}
+
+/// Parses the value of a `blr:` header or of `BLR_BASIC_MODE`.
+pub fn parse_mode(value: &str) -> Option<BasicMode> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "qbasic" => Some(BasicMode::QBasic),
+        "gwbasic" => Some(BasicMode::GWBasic),
+        _ => None,
+    }
+}
+
+/// Decides the dialect of the given program contents.
+///
+/// A header comment like `' blr: qbasic` in the leading comment lines wins.
+/// Otherwise, tokenized files are GW-Basic, programs with QBasic only blocks
+/// (`SUB`, `FUNCTION`, `DECLARE`, `SELECT CASE`, `END IF`) are QBasic, and
+/// programs with a line number on every line are GW-Basic.
+/// Returns `None` for empty programs.
+pub fn detect(contents: &[u8]) -> Option<Dialect> {
+    match contents.first() {
+        Some(&TOKENIZED_SIGNATURE) => {
+            return Some(Dialect::detected(
+                BasicMode::GWBasic,
+                "tokenized GW-Basic file".to_string(),
+            ))
+        }
+        Some(&PROTECTED_SIGNATURE) => {
+            return Some(Dialect::detected(
+                BasicMode::GWBasic,
+                "protected GW-Basic file".to_string(),
+            ))
+        }
+        _ => {}
+    }
+
+    let text = String::from_utf8_lossy(contents);
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+    header_mode(&lines)
+        .or_else(|| qbasic_block(&lines))
+        .or_else(|| line_numbers(&lines))
+}
+
+fn header_mode(lines: &[(usize, &str)]) -> Option<Dialect> {
+    for (number, line) in lines {
+        let comment = comment_text(line)?;
+        let trimmed = comment.trim();
+        let is_header = trimmed
+            .get(..4)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("blr:"));
+        if is_header {
+            let mode = parse_mode(&trimmed[4..])?;
+            return Some(Dialect::explicit(
+                mode,
+                format!("header comment at line {}", number),
+            ));
+        }
+    }
+    None
+}
+
+/// Returns the text of a comment line (with or without a line number),
+/// or `None` if the line is not a comment.
+fn comment_text(line: &str) -> Option<&str> {
+    let line = strip_line_number(line).trim_start();
+    if let Some(stripped) = line.strip_prefix('\'') {
+        Some(stripped)
+    } else if line.get(..3).is_some_and(|p| p.eq_ignore_ascii_case("REM")) {
+        Some(&line[3..])
+    } else {
+        None
+    }
+}
+
+fn qbasic_block(lines: &[(usize, &str)]) -> Option<Dialect> {
+    for (number, line) in lines {
+        let words = code_words(line);
+        let found = words.iter().enumerate().find_map(|(i, word)| {
+            let next = words.get(i + 1).map(String::as_str).unwrap_or_default();
+            match (word.as_str(), next) {
+                ("SUB", _) | ("FUNCTION", _) | ("DECLARE", _) => Some(word.to_string()),
+                ("SELECT", "CASE") | ("END", "IF") | ("END", "SELECT") => {
+                    Some(format!("{} {}", word, next))
+                }
+                _ => None,
+            }
+        });
+        if let Some(keyword) = found {
+            return Some(Dialect::detected(
+                BasicMode::QBasic,
+                format!("{} at line {}", keyword, number),
+            ));
+        }
+    }
+    None
+}
+
+fn line_numbers(lines: &[(usize, &str)]) -> Option<Dialect> {
+    if lines.is_empty() {
+        return None;
+    }
+    match lines.iter().find(|(_, line)| !starts_with_digit(line)) {
+        Some((number, _)) => Some(Dialect::detected(
+            BasicMode::QBasic,
+            format!("line {} has no line number", number),
+        )),
+        None => Some(Dialect::detected(
+            BasicMode::GWBasic,
+            "every line has a line number".to_string(),
+        )),
+    }
+}
+
+fn starts_with_digit(line: &str) -> bool {
+    line.chars().next().is_some_and(|c| c.is_ascii_digit())
+}
+
+fn strip_line_number(line: &str) -> &str {
+    line.trim_start_matches(|c: char| c.is_ascii_digit())
+}
+
+/// Splits a line into upper case words, ignoring string literals and comments.
+fn code_words(line: &str) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+    let mut word = String::new();
+    let mut in_string = false;
+    for ch in line.chars() {
+        if in_string {
+            in_string = ch != '"';
+            continue;
+        }
+        if ch.is_ascii_alphanumeric() || ch == '$' || ch == '%' || ch == '.' {
+            word.push(ch.to_ascii_uppercase());
+            continue;
+        }
+        if !word.is_empty() {
+            words.push(word);
+            word = String::new();
+        }
+        if ch == '"' {
+            in_string = true;
+        } else if ch == '\'' {
+            return words;
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    match words.iter().position(|w| w == "REM") {
+        Some(idx) => words[..idx].to_vec(),
+        None => words,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_str(s: &str) -> Option<Dialect> {
+        detect(s.as_bytes())
+    }
+
+    #[test]
+    fn test_empty_program() {
+        assert!(detect_str("").is_none());
+        assert!(detect_str("\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_hello_gwbasic() {
+        let d = detect_str(include_str!("../../basic/src/HELLO.BAS")).unwrap();
+        assert!(matches!(d.mode, BasicMode::GWBasic));
+        assert!(!d.explicit);
+    }
+
+    #[test]
+    fn test_hello_qbasic() {
+        let d = detect_str(include_str!("../../basic/src/HELLOQB.BAS")).unwrap();
+        assert!(matches!(d.mode, BasicMode::QBasic));
+        assert_eq!(d.reason, "line 1 has no line number");
+    }
+
+    #[test]
+    fn test_rest_gwbasic() {
+        let d = detect_str(include_str!("../../basic/rest/CREATE.BAS")).unwrap();
+        assert!(matches!(d.mode, BasicMode::GWBasic));
+    }
+
+    #[test]
+    fn test_rest_qbasic() {
+        let d = detect_str(include_str!("../../basic/rest-qb/TODO.BAS")).unwrap();
+        assert!(matches!(d.mode, BasicMode::QBasic));
+        assert_eq!(d.reason, "DECLARE at line 3");
+    }
+
+    #[test]
+    fn test_numbered_qbasic_block() {
+        let d = detect_str("10 IF X THEN\r\n20 PRINT X\r\n30 END IF\r\n").unwrap();
+        assert!(matches!(d.mode, BasicMode::QBasic));
+        assert_eq!(d.reason, "END IF at line 3");
+    }
+
+    #[test]
+    fn test_keywords_in_strings_and_comments_are_ignored() {
+        let d = detect_str("10 PRINT \"SUB\" ' END IF\r\n20 REM SELECT CASE\r\n").unwrap();
+        assert!(matches!(d.mode, BasicMode::GWBasic));
+    }
+
+    #[test]
+    fn test_tokenized() {
+        let d = detect(&[TOKENIZED_SIGNATURE, 0, 0]).unwrap();
+        assert!(matches!(d.mode, BasicMode::GWBasic));
+        assert_eq!(d.reason, "tokenized GW-Basic file");
+    }
+
+    #[test]
+    fn test_protected() {
+        let d = detect(&[PROTECTED_SIGNATURE, 0, 0]).unwrap();
+        assert!(matches!(d.mode, BasicMode::GWBasic));
+        assert_eq!(d.reason, "protected GW-Basic file");
+    }
+
+    #[test]
+    fn test_header_overrides_detection() {
+        let d = detect_str("' blr: gwbasic\r\nPRINT \"hi\"\r\n").unwrap();
+        assert!(matches!(d.mode, BasicMode::GWBasic));
+        assert!(d.explicit);
+        assert_eq!(d.reason, "header comment at line 1");
+    }
+
+    #[test]
+    fn test_header_with_line_number() {
+        let d = detect_str("10 REM BLR: QBasic\r\n20 PRINT \"hi\"\r\n").unwrap();
+        assert!(matches!(d.mode, BasicMode::QBasic));
+        assert!(d.explicit);
+    }
+
+    #[test]
+    fn test_header_after_code_is_ignored() {
+        let d = detect_str("10 PRINT \"hi\"\r\n20 ' blr: qbasic\r\n").unwrap();
+        assert!(matches!(d.mode, BasicMode::GWBasic));
+        assert!(!d.explicit);
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert!(matches!(parse_mode("qbasic"), Some(BasicMode::QBasic)));
+        assert!(matches!(parse_mode(" GWBasic "), Some(BasicMode::GWBasic)));
+        assert!(parse_mode("").is_none());
+        assert!(parse_mode("basic").is_none());
+    }
+}
@@ -0,0 +1,67 @@
+/// Compares two texts line by line, ignoring trailing whitespace so that
+/// DOS and Unix line endings compare equal.
+///
+/// Returns `None` if they are the same, otherwise the differences with
+/// `-` for lines only in `expected` and `+` for lines only in `actual`.
+pub fn diff_lines(expected: &str, actual: &str) -> Option<String> {
+    let left: Vec<&str> = expected.lines().map(str::trim_end).collect();
+    let right: Vec<&str> = actual.lines().map(str::trim_end).collect();
+    if left == right {
+        return None;
+    }
+
+    // lengths of the longest common subsequences of the suffixes
+    let mut lcs = vec![vec![0usize; right.len() + 1]; left.len() + 1];
+    for i in (0..left.len()).rev() {
+        for j in (0..right.len()).rev() {
+            lcs[i][j] = if left[i] == right[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() || j < right.len() {
+        if i < left.len() && j < right.len() && left[i] == right[j] {
+            result.push_str(&format!(" {}\n", left[i]));
+            i += 1;
+            j += 1;
+        } else if i < left.len() && (j == right.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            result.push_str(&format!("-{}\n", left[i]));
+            i += 1;
+        } else {
+            result.push_str(&format!("+{}\n", right[j]));
+            j += 1;
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same() {
+        assert_eq!(diff_lines("a\r\nb\r\n", "a\nb\n"), None);
+    }
+
+    #[test]
+    fn test_changed_line() {
+        assert_eq!(
+            diff_lines("Status: 200 OK\n\nhi\n", "Status: 500 Oops\n\nhi\n"),
+            Some("-Status: 200 OK\n+Status: 500 Oops\n \n hi\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_added_and_removed_lines() {
+        assert_eq!(
+            diff_lines("a\nb\nc\n", "a\nc\nd\n"),
+            Some(" a\n-b\n c\n+d\n".to_string())
+        );
+    }
+}
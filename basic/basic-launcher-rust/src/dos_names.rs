@@ -0,0 +1,232 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Characters that DOS allows in file names, besides letters and digits.
+const SPECIAL_CHARS: &str = "!#$%&'()-@^_`{}~";
+
+/// Checks if a file name (not a path) is a valid DOS 8.3 name:
+/// up to 8 characters, optionally followed by a dot and up to 3 characters.
+/// Lower case letters are accepted, as DOS converts them to upper case.
+pub fn is_valid_8_3(name: &str) -> bool {
+    let (stem, extension) = match name.split_once('.') {
+        Some((stem, extension)) => (stem, extension),
+        None => (name, ""),
+    };
+    !stem.is_empty()
+        && stem.len() <= 8
+        && extension.len() <= 3
+        && stem.chars().all(is_valid_char)
+        && extension.chars().all(is_valid_char)
+}
+
+/// Checks if a DOS path, e.g. `C:\DATA\TODO.DAT`, consists of valid 8.3 names.
+pub fn is_valid_dos_path(path: &str) -> bool {
+    let path = match path.get(1..2) {
+        Some(":") => &path[2..],
+        _ => path,
+    };
+    path.split('\\')
+        .filter(|component| !component.is_empty())
+        .all(|component| component == "." || component == ".." || is_valid_8_3(component))
+}
+
+fn is_valid_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || SPECIAL_CHARS.contains(ch)
+}
+
+/// Suggests a valid 8.3 name for the given file name, by dropping the
+/// characters DOS doesn't allow and truncating the name and the extension.
+pub fn suggest_8_3(name: &str) -> String {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) => (stem, extension),
+        None => (name, ""),
+    };
+    let clean = |s: &str, len: usize| -> String {
+        s.chars()
+            .filter(|ch| is_valid_char(*ch))
+            .take(len)
+            .collect::<String>()
+            .to_ascii_uppercase()
+    };
+    let stem = match clean(stem, 8) {
+        s if s.is_empty() => "PROGRAM".to_string(),
+        s => s,
+    };
+    match clean(extension, 3) {
+        e if e.is_empty() => stem,
+        e => format!("{}.{}", stem, e),
+    }
+}
+
+/// Maps host paths to DOS paths.
+///
+/// DOSBox mounts the directory of the batch file as drive `C:`. Directories
+/// below it are reached through `C:` as long as every component is a valid
+/// 8.3 name. Any other directory gets mounted as a drive of its own, so that
+/// long names, spaces and case never reach a DOS command line.
+#[derive(Debug)]
+pub struct DosDrives {
+    batch_dir: PathBuf,
+    mounts: Vec<(char, PathBuf)>,
+}
+
+impl DosDrives {
+    pub fn new(batch_dir: &Path) -> Self {
+        Self {
+            batch_dir: batch_dir.to_path_buf(),
+            mounts: vec![],
+        }
+    }
+
+    /// The drives that need to be mounted, with their host directories.
+    pub fn mounts(&self) -> &[(char, PathBuf)] {
+        &self.mounts
+    }
+
+    /// Returns the DOS path of a host directory, mounting it if needed.
+    pub fn dir(&mut self, dir: &Path) -> io::Result<String> {
+        if let Some(path) = self.relative_to_c(dir) {
+            return Ok(path);
+        }
+        if let Some((drive, _)) = self.mounts.iter().find(|(_, d)| d == dir) {
+            return Ok(format!("{}:\\", drive));
+        }
+        let drive = (b'D' + self.mounts.len() as u8) as char;
+        if drive > 'Y' {
+            return Err(io::Error::other("Ran out of DOS drive letters"));
+        }
+        self.mounts.push((drive, dir.to_path_buf()));
+        Ok(format!("{}:\\", drive))
+    }
+
+    /// Returns the DOS path of a host file. The file name itself must be a
+    /// valid 8.3 name, as there is nothing to mount for it.
+    pub fn file(&mut self, file: &Path) -> io::Result<String> {
+        let name = file
+            .file_name()
+            .and_then(|f| f.to_str())
+            .unwrap_or_default();
+        if !is_valid_8_3(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is not a valid DOS 8.3 name, rename it e.g. to {}",
+                    file.display(),
+                    suggest_8_3(name)
+                ),
+            ));
+        }
+        let dir = self.dir(file.parent().unwrap())?;
+        if dir.ends_with('\\') {
+            Ok(format!("{}{}", dir, name.to_ascii_uppercase()))
+        } else {
+            Ok(format!("{}\\{}", dir, name.to_ascii_uppercase()))
+        }
+    }
+
+    fn relative_to_c(&self, dir: &Path) -> Option<String> {
+        let relative = dir.strip_prefix(&self.batch_dir).ok()?;
+        let mut result = String::from("C:");
+        for component in relative.iter() {
+            let name = component.to_str()?;
+            if !is_valid_8_3(name) {
+                return None;
+            }
+            result.push('\\');
+            result.push_str(&name.to_ascii_uppercase());
+        }
+        if result.len() == 2 {
+            result.push('\\');
+        }
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_8_3() {
+        assert!(is_valid_8_3("TODO.DAT"));
+        assert!(is_valid_8_3("HELLOQB.BAS"));
+        assert!(is_valid_8_3("ABCDEFGH"));
+        assert!(is_valid_8_3("todo.dat"));
+        assert!(is_valid_8_3("A_1~$.X"));
+    }
+
+    #[test]
+    fn test_is_valid_8_3_invalid() {
+        assert!(!is_valid_8_3(""));
+        assert!(!is_valid_8_3(".DAT"));
+        assert!(!is_valid_8_3("ABCDEFGHI.DAT"));
+        assert!(!is_valid_8_3("TODO.DATA"));
+        assert!(!is_valid_8_3("MY TODO.DAT"));
+        assert!(!is_valid_8_3("TODO.OLD.DAT"));
+        assert!(!is_valid_8_3("TODO+.DAT"));
+    }
+
+    #[test]
+    fn test_is_valid_dos_path() {
+        assert!(is_valid_dos_path("TODO.DAT"));
+        assert!(is_valid_dos_path("C:\\DATA\\TODO.DAT"));
+        assert!(is_valid_dos_path("..\\DATA\\TODO.DAT"));
+        assert!(!is_valid_dos_path("C:\\MY DATA\\TODO.DAT"));
+        assert!(!is_valid_dos_path("DATABASE1\\TODO.DAT"));
+    }
+
+    #[test]
+    fn test_suggest_8_3() {
+        assert_eq!(suggest_8_3("hello world.bas"), "HELLOWOR.BAS");
+        assert_eq!(suggest_8_3("todo.old.data"), "TODOOLD.DAT");
+        assert_eq!(suggest_8_3("+++"), "PROGRAM");
+    }
+
+    #[test]
+    fn test_file_same_level() {
+        let mut drives = DosDrives::new(Path::new("/home/test"));
+        let dos = drives.file(Path::new("/home/test/PROGRAM.BAS")).unwrap();
+        assert_eq!(dos, "C:\\PROGRAM.BAS");
+        assert!(drives.mounts().is_empty());
+    }
+
+    #[test]
+    fn test_file_one_level() {
+        let mut drives = DosDrives::new(Path::new("/home"));
+        let dos = drives.file(Path::new("/home/test/program.bas")).unwrap();
+        assert_eq!(dos, "C:\\TEST\\PROGRAM.BAS");
+        assert!(drives.mounts().is_empty());
+    }
+
+    #[test]
+    fn test_file_long_dir_is_mounted() {
+        let mut drives = DosDrives::new(Path::new("/basic"));
+        let dos = drives
+            .file(Path::new("/basic/rest-qb-direct/TODO.BAS"))
+            .unwrap();
+        assert_eq!(dos, "D:\\TODO.BAS");
+        let dos = drives.dir(Path::new("/basic/rest-qb-direct")).unwrap();
+        assert_eq!(dos, "D:\\");
+        let dos = drives.file(Path::new("/basic/my bin/QBASIC.EXE")).unwrap();
+        assert_eq!(dos, "E:\\QBASIC.EXE");
+        assert_eq!(
+            drives.mounts(),
+            &[
+                ('D', PathBuf::from("/basic/rest-qb-direct")),
+                ('E', PathBuf::from("/basic/my bin"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_invalid_name() {
+        let mut drives = DosDrives::new(Path::new("/basic"));
+        let err = drives
+            .file(Path::new("/basic/src/hello world.bas"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "/basic/src/hello world.bas is not a valid DOS 8.3 name, rename it e.g. to HELLOWOR.BAS"
+        );
+    }
+}
@@ -0,0 +1,874 @@
+//! A tiny stand-in for GW-Basic, just enough for the sample programs in this
+//! repository and the tests of the launcher. It is not meant to be a BASIC
+//! interpreter: anything outside of the supported subset is reported as a
+//! syntax error, the way the real interpreter reports its errors.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+
+use crate::fake_dosbox::{DosFs, Ending};
+use crate::lexer::{lex, Token};
+
+/// Width of the print zones used by `PRINT a, b`.
+const ZONE_WIDTH: usize = 14;
+
+const SYNTAX_ERROR: u8 = 2;
+const UNDEFINED_LINE_NUMBER: u8 = 8;
+const TYPE_MISMATCH: u8 = 13;
+const RESUME_WITHOUT_ERROR: u8 = 20;
+const WHILE_WITHOUT_WEND: u8 = 29;
+const WEND_WITHOUT_WHILE: u8 = 30;
+const BAD_FILE_NUMBER: u8 = 52;
+const FILE_NOT_FOUND: u8 = 53;
+const BAD_FILE_MODE: u8 = 54;
+const FILE_ALREADY_OPEN: u8 = 55;
+const DEVICE_IO_ERROR: u8 = 57;
+const INPUT_PAST_END: u8 = 62;
+const PATH_NOT_FOUND: u8 = 76;
+
+fn error_message(code: u8) -> &'static str {
+    match code {
+        SYNTAX_ERROR => "Syntax error",
+        UNDEFINED_LINE_NUMBER => "Undefined line number",
+        TYPE_MISMATCH => "Type mismatch",
+        RESUME_WITHOUT_ERROR => "RESUME without error",
+        WHILE_WITHOUT_WEND => "WHILE without WEND",
+        WEND_WITHOUT_WHILE => "WEND without WHILE",
+        BAD_FILE_NUMBER => "Bad file number",
+        FILE_NOT_FOUND => "File not found",
+        BAD_FILE_MODE => "Bad file mode",
+        FILE_ALREADY_OPEN => "File already open",
+        DEVICE_IO_ERROR => "Device I/O Error",
+        INPUT_PAST_END => "Input past end",
+        PATH_NOT_FOUND => "Path not found",
+        _ => "Unprintable error",
+    }
+}
+
+/// A BASIC run time error. The line is the one to report, if it isn't the
+/// line of the statement that failed.
+#[derive(Debug)]
+struct BasicError {
+    code: u8,
+    line: Option<u32>,
+}
+
+fn error<T>(code: u8) -> Result<T, BasicError> {
+    Err(BasicError { code, line: None })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Num(f64),
+    Str(String),
+}
+
+impl Value {
+    fn num(self) -> Result<f64, BasicError> {
+        match self {
+            Value::Num(n) => Ok(n),
+            Value::Str(_) => error(TYPE_MISMATCH),
+        }
+    }
+
+    fn str(self) -> Result<String, BasicError> {
+        match self {
+            Value::Str(s) => Ok(s),
+            Value::Num(_) => error(TYPE_MISMATCH),
+        }
+    }
+}
+
+fn bool_value(b: bool) -> Value {
+    Value::Num(if b { -1.0 } else { 0.0 })
+}
+
+/// Formats a number like `PRINT` does: a leading space for positive numbers,
+/// no leading zero before the decimal point.
+fn format_number(n: f64) -> String {
+    let sign = if n < 0.0 { "-" } else { " " };
+    let digits = if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n.abs() as i64)
+    } else {
+        let s = format!("{}", n.abs() as f32);
+        s.strip_prefix('0').unwrap_or(&s).to_string()
+    };
+    format!("{}{}", sign, digits)
+}
+
+/// Where PRINT writes, keeping track of the column for the print zones.
+struct Output {
+    writer: Box<dyn Write>,
+    column: usize,
+}
+
+impl Output {
+    fn new(writer: Box<dyn Write>) -> Self {
+        Self { writer, column: 0 }
+    }
+
+    fn write_str(&mut self, s: &str) -> Result<(), BasicError> {
+        if self.writer.write_all(s.as_bytes()).is_err() {
+            return error(DEVICE_IO_ERROR);
+        }
+        match s.rfind('\n') {
+            Some(i) => self.column = s.len() - i - 1,
+            None => self.column += s.len(),
+        }
+        Ok(())
+    }
+}
+
+enum Channel {
+    Input(Box<dyn BufRead>),
+    Output(Output),
+}
+
+/// A statement, i.e. the tokens between colons. `IF` takes the rest of its
+/// line, as its branch can have more than one statement.
+struct Statement {
+    line: Option<u32>,
+    tokens: Vec<Token>,
+}
+
+fn split_statements(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut result = vec![];
+    let mut start = 0;
+    while start < tokens.len() {
+        if tokens[start] == Token::Word("IF".to_string()) {
+            result.push(&tokens[start..]);
+            break;
+        }
+        let end = tokens[start..]
+            .iter()
+            .position(|t| *t == Token::Colon)
+            .map_or(tokens.len(), |i| start + i);
+        result.push(&tokens[start..end]);
+        start = end + 1;
+    }
+    result
+}
+
+enum Flow {
+    Next,
+    Jump(usize),
+    End(Ending),
+}
+
+/// The tokens of a statement, consumed from left to right.
+struct Cursor<'t> {
+    tokens: &'t [Token],
+    pos: usize,
+}
+
+impl<'t> Cursor<'t> {
+    fn new(tokens: &'t [Token]) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&'t Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'t Token> {
+        let result = self.tokens.get(self.pos);
+        self.pos += 1;
+        result
+    }
+
+    fn rest(&self) -> &'t [Token] {
+        &self.tokens[self.pos.min(self.tokens.len())..]
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w == word => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), BasicError> {
+        if self.eat_word(word) {
+            Ok(())
+        } else {
+            error(SYNTAX_ERROR)
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), BasicError> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            error(SYNTAX_ERROR)
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), BasicError> {
+        if self.is_at_end() {
+            Ok(())
+        } else {
+            error(SYNTAX_ERROR)
+        }
+    }
+
+    fn variable(&mut self) -> Result<String, BasicError> {
+        match self.next() {
+            Some(Token::Word(name)) => Ok(name.clone()),
+            _ => error(SYNTAX_ERROR),
+        }
+    }
+}
+
+struct Machine<'a> {
+    statements: Vec<Statement>,
+    lines: HashMap<u32, usize>,
+    dos: &'a DosFs,
+    env: &'a [(String, String)],
+    vars: HashMap<String, Value>,
+    stdin: Box<dyn BufRead>,
+    stdout: Output,
+    files: HashMap<i64, Channel>,
+    error_handler: Option<usize>,
+    /// The error being handled: its code, line and statement.
+    trapped: Option<(u8, u32, usize)>,
+}
+
+/// Runs a BASIC program with the given DOS environment, reading `LINE INPUT`
+/// from `stdin` and printing to `stdout`.
+pub fn run(
+    text: &str,
+    dos: &DosFs,
+    env: &[(String, String)],
+    stdin: Box<dyn BufRead>,
+    stdout: Box<dyn Write>,
+) -> io::Result<Ending> {
+    let mut machine = Machine {
+        statements: vec![],
+        lines: HashMap::new(),
+        dos,
+        env,
+        vars: HashMap::new(),
+        stdin,
+        stdout: Output::new(stdout),
+        files: HashMap::new(),
+        error_handler: None,
+        trapped: None,
+    };
+    machine.load(text);
+    let ending = machine.run();
+    machine.stdout.writer.flush()?;
+    Ok(ending)
+}
+
+impl<'a> Machine<'a> {
+    fn load(&mut self, text: &str) {
+        for line in lex(text) {
+            if let Some(number) = line.number {
+                self.lines.insert(number, self.statements.len());
+            }
+            let statements = split_statements(&line.tokens);
+            if statements.is_empty() {
+                self.statements.push(Statement {
+                    line: line.number,
+                    tokens: vec![],
+                });
+            }
+            for statement in statements {
+                self.statements.push(Statement {
+                    line: line.number,
+                    tokens: statement.to_vec(),
+                });
+            }
+        }
+    }
+
+    fn run(&mut self) -> Ending {
+        let mut pc = 0;
+        while pc < self.statements.len() {
+            let tokens = self.statements[pc].tokens.clone();
+            match self.execute(&tokens, pc) {
+                Ok(Flow::Next) => pc += 1,
+                Ok(Flow::Jump(target)) => pc = target,
+                Ok(Flow::End(ending)) => return ending,
+                Err(e) => match (self.error_handler, self.trapped) {
+                    (Some(handler), None) => {
+                        let line = self.statements[pc].line.unwrap_or_default();
+                        self.trapped = Some((e.code, line, pc));
+                        pc = handler;
+                    }
+                    _ => {
+                        let line = e.line.or(self.statements[pc].line);
+                        let message = match line {
+                            Some(line) => format!("{} in {}\r\n", error_message(e.code), line),
+                            None => format!("{}\r\n", error_message(e.code)),
+                        };
+                        let _ = self.stdout.write_str(&message);
+                        return Ending::Prompt;
+                    }
+                },
+            }
+        }
+        // the real interpreter waits at its prompt after the last line
+        Ending::Prompt
+    }
+
+    fn execute(&mut self, tokens: &[Token], pc: usize) -> Result<Flow, BasicError> {
+        let mut c = Cursor::new(tokens);
+        let keyword = match c.next() {
+            None => return Ok(Flow::Next),
+            Some(Token::Word(w)) => w.clone(),
+            Some(_) => return error(SYNTAX_ERROR),
+        };
+        match keyword.as_str() {
+            "PRINT" => self.print(&mut c)?,
+            "LINE" => {
+                c.expect_word("INPUT")?;
+                self.line_input(&mut c)?
+            }
+            "OPEN" => self.open(&mut c)?,
+            "CLOSE" => self.close(&mut c)?,
+            "IF" => return self.if_statement(&mut c, pc),
+            "GOTO" => return Ok(Flow::Jump(self.target(&mut c)?)),
+            "WHILE" => return self.while_statement(&mut c, pc),
+            "WEND" => return self.wend_statement(pc),
+            "ON" => self.on_error(&mut c)?,
+            "RESUME" => return self.resume(&mut c),
+            "SYSTEM" => return Ok(Flow::End(Ending::System)),
+            "END" => return Ok(Flow::End(Ending::Prompt)),
+            _ => self.assign(keyword, &mut c)?,
+        }
+        Ok(Flow::Next)
+    }
+
+    fn assign(&mut self, name: String, c: &mut Cursor) -> Result<(), BasicError> {
+        c.expect_symbol('=')?;
+        let value = self.expression(c)?;
+        c.expect_end()?;
+        let value = if name.ends_with('$') {
+            Value::Str(value.str()?)
+        } else {
+            Value::Num(value.num()?)
+        };
+        self.vars.insert(name, value);
+        Ok(())
+    }
+
+    fn print(&mut self, c: &mut Cursor) -> Result<(), BasicError> {
+        let file = self.file_number_prefix(c)?;
+        let mut newline = true;
+        while let Some(token) = c.peek() {
+            let text = match token {
+                Token::Symbol(';') => {
+                    c.next();
+                    newline = false;
+                    continue;
+                }
+                Token::Symbol(',') => {
+                    c.next();
+                    newline = false;
+                    let column = self.output(file)?.column;
+                    " ".repeat(ZONE_WIDTH - column % ZONE_WIDTH)
+                }
+                _ => {
+                    newline = true;
+                    match self.expression(c)? {
+                        Value::Num(n) => format!("{} ", format_number(n)),
+                        Value::Str(s) => s,
+                    }
+                }
+            };
+            self.output(file)?.write_str(&text)?;
+        }
+        if newline {
+            self.output(file)?.write_str("\r\n")?;
+        }
+        Ok(())
+    }
+
+    fn line_input(&mut self, c: &mut Cursor) -> Result<(), BasicError> {
+        let file = self.file_number_prefix(c)?;
+        let name = c.variable()?;
+        c.expect_end()?;
+        if !name.ends_with('$') {
+            return error(TYPE_MISMATCH);
+        }
+        let reader: &mut dyn BufRead = match file {
+            None => &mut self.stdin,
+            Some(n) => match self.files.get_mut(&n) {
+                Some(Channel::Input(reader)) => reader,
+                Some(Channel::Output(_)) => return error(BAD_FILE_MODE),
+                None => return error(BAD_FILE_NUMBER),
+            },
+        };
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => return error(INPUT_PAST_END),
+            Ok(_) => {}
+            Err(_) => return error(DEVICE_IO_ERROR),
+        }
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        self.vars.insert(name, Value::Str(line));
+        Ok(())
+    }
+
+    /// Parses the `#n,` of `PRINT #n, ...` and `LINE INPUT #n, ...`.
+    fn file_number_prefix(&mut self, c: &mut Cursor) -> Result<Option<i64>, BasicError> {
+        if !c.eat_symbol('#') {
+            return Ok(None);
+        }
+        let n = self.expression(c)?.num()? as i64;
+        c.expect_symbol(',')?;
+        Ok(Some(n))
+    }
+
+    fn output(&mut self, file: Option<i64>) -> Result<&mut Output, BasicError> {
+        match file {
+            None => Ok(&mut self.stdout),
+            Some(n) => match self.files.get_mut(&n) {
+                Some(Channel::Output(output)) => Ok(output),
+                Some(Channel::Input(_)) => error(BAD_FILE_MODE),
+                None => error(BAD_FILE_NUMBER),
+            },
+        }
+    }
+
+    /// `OPEN file FOR INPUT|APPEND [ACCESS ...] AS [#]n`.
+    fn open(&mut self, c: &mut Cursor) -> Result<(), BasicError> {
+        let name = self.expression(c)?.str()?;
+        c.expect_word("FOR")?;
+        let append = match c.next() {
+            Some(Token::Word(w)) if w == "INPUT" => false,
+            Some(Token::Word(w)) if w == "APPEND" => true,
+            _ => return error(SYNTAX_ERROR),
+        };
+        while !c.eat_word("AS") {
+            if c.next().is_none() {
+                return error(SYNTAX_ERROR);
+            }
+        }
+        c.eat_symbol('#');
+        let number = self.expression(c)?.num()? as i64;
+        c.expect_end()?;
+        if self.files.contains_key(&number) {
+            return error(FILE_ALREADY_OPEN);
+        }
+        let path = match self.dos.resolve(&name) {
+            Ok(path) => path,
+            Err(_) => return error(PATH_NOT_FOUND),
+        };
+        let channel = if append {
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .map(|f| Channel::Output(Output::new(Box::new(f))))
+        } else {
+            File::open(path).map(|f| Channel::Input(Box::new(BufReader::new(f))))
+        };
+        match channel {
+            Ok(channel) => {
+                self.files.insert(number, channel);
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => error(FILE_NOT_FOUND),
+            Err(_) => error(DEVICE_IO_ERROR),
+        }
+    }
+
+    fn close(&mut self, c: &mut Cursor) -> Result<(), BasicError> {
+        if c.is_at_end() {
+            self.files.clear();
+            return Ok(());
+        }
+        loop {
+            c.eat_symbol('#');
+            let number = self.expression(c)?.num()? as i64;
+            self.files.remove(&number);
+            if !c.eat_symbol(',') {
+                return c.expect_end();
+            }
+        }
+    }
+
+    /// `IF condition THEN statements` or `IF condition GOTO n`.
+    fn if_statement(&mut self, c: &mut Cursor, pc: usize) -> Result<Flow, BasicError> {
+        let condition = self.expression(c)?.num()? != 0.0;
+        if c.eat_word("GOTO") {
+            let target = self.target(c)?;
+            c.expect_end()?;
+            return Ok(if condition {
+                Flow::Jump(target)
+            } else {
+                Flow::Next
+            });
+        }
+        c.expect_word("THEN")?;
+        if !condition {
+            return Ok(Flow::Next);
+        }
+        for statement in split_statements(c.rest()) {
+            match self.execute(statement, pc)? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn while_statement(&mut self, c: &mut Cursor, pc: usize) -> Result<Flow, BasicError> {
+        if self.expression(c)?.num()? != 0.0 {
+            return Ok(Flow::Next);
+        }
+        let mut depth = 0;
+        for i in pc + 1..self.statements.len() {
+            match self.statements[i].tokens.first() {
+                Some(Token::Word(w)) if w == "WHILE" => depth += 1,
+                Some(Token::Word(w)) if w == "WEND" && depth == 0 => return Ok(Flow::Jump(i + 1)),
+                Some(Token::Word(w)) if w == "WEND" => depth -= 1,
+                _ => {}
+            }
+        }
+        error(WHILE_WITHOUT_WEND)
+    }
+
+    /// Goes back to the matching WHILE, which evaluates its condition again.
+    fn wend_statement(&mut self, pc: usize) -> Result<Flow, BasicError> {
+        let mut depth = 0;
+        for i in (0..pc).rev() {
+            match self.statements[i].tokens.first() {
+                Some(Token::Word(w)) if w == "WEND" => depth += 1,
+                Some(Token::Word(w)) if w == "WHILE" && depth == 0 => return Ok(Flow::Jump(i)),
+                Some(Token::Word(w)) if w == "WHILE" => depth -= 1,
+                _ => {}
+            }
+        }
+        error(WEND_WITHOUT_WHILE)
+    }
+
+    /// `ON ERROR GOTO n`. `ON ERROR GOTO 0` inside an error handler stops the
+    /// program with the message of the error being handled.
+    fn on_error(&mut self, c: &mut Cursor) -> Result<(), BasicError> {
+        c.expect_word("ERROR")?;
+        c.expect_word("GOTO")?;
+        if c.peek() == Some(&Token::Number("0".to_string())) {
+            self.error_handler = None;
+            if let Some((code, line, _)) = self.trapped {
+                return Err(BasicError {
+                    code,
+                    line: Some(line),
+                });
+            }
+            return Ok(());
+        }
+        self.error_handler = Some(self.target(c)?);
+        Ok(())
+    }
+
+    /// `RESUME n`, or `RESUME` to run the failed statement again.
+    fn resume(&mut self, c: &mut Cursor) -> Result<Flow, BasicError> {
+        let (_, _, pc) = match self.trapped.take() {
+            Some(trapped) => trapped,
+            None => return error(RESUME_WITHOUT_ERROR),
+        };
+        if c.is_at_end() {
+            Ok(Flow::Jump(pc))
+        } else {
+            Ok(Flow::Jump(self.target(c)?))
+        }
+    }
+
+    /// The statement of a line number.
+    fn target(&self, c: &mut Cursor) -> Result<usize, BasicError> {
+        let target = match c.next() {
+            Some(Token::Number(n)) => n.parse().ok().and_then(|n: u32| self.lines.get(&n)),
+            _ => return error(SYNTAX_ERROR),
+        };
+        match target {
+            Some(target) => Ok(*target),
+            None => error(UNDEFINED_LINE_NUMBER),
+        }
+    }
+
+    fn expression(&mut self, c: &mut Cursor) -> Result<Value, BasicError> {
+        if c.eat_word("NOT") {
+            let value = self.expression(c)?.num()?;
+            Ok(Value::Num(!(value as i64) as f64))
+        } else {
+            self.comparison(c)
+        }
+    }
+
+    fn comparison(&mut self, c: &mut Cursor) -> Result<Value, BasicError> {
+        let left = self.additive(c)?;
+        let operator = match c.peek() {
+            Some(Token::Symbol(ch @ ('=' | '<' | '>'))) => {
+                c.next();
+                match (ch, c.peek()) {
+                    ('<', Some(Token::Symbol('>'))) | ('>', Some(Token::Symbol('<'))) => "<>",
+                    ('<', Some(Token::Symbol('='))) | ('=', Some(Token::Symbol('<'))) => "<=",
+                    ('>', Some(Token::Symbol('='))) | ('=', Some(Token::Symbol('>'))) => ">=",
+                    ('=', _) => return self.compare(left, "=", c),
+                    ('<', _) => return self.compare(left, "<", c),
+                    _ => return self.compare(left, ">", c),
+                }
+            }
+            _ => return Ok(left),
+        };
+        c.next();
+        self.compare(left, operator, c)
+    }
+
+    fn compare(
+        &mut self,
+        left: Value,
+        operator: &str,
+        c: &mut Cursor,
+    ) -> Result<Value, BasicError> {
+        let right = self.additive(c)?;
+        let ordering = match (left, right) {
+            (Value::Num(a), Value::Num(b)) => {
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            }
+            (Value::Str(a), Value::Str(b)) => a.cmp(&b),
+            _ => return error(TYPE_MISMATCH),
+        };
+        Ok(bool_value(match operator {
+            "=" => ordering.is_eq(),
+            "<>" => ordering.is_ne(),
+            "<" => ordering.is_lt(),
+            ">" => ordering.is_gt(),
+            "<=" => ordering.is_le(),
+            _ => ordering.is_ge(),
+        }))
+    }
+
+    fn additive(&mut self, c: &mut Cursor) -> Result<Value, BasicError> {
+        let mut left = self.primary(c)?;
+        while c.eat_symbol('+') {
+            left = match (left, self.primary(c)?) {
+                (Value::Num(a), Value::Num(b)) => Value::Num(a + b),
+                (Value::Str(a), Value::Str(b)) => Value::Str(a + &b),
+                _ => return error(TYPE_MISMATCH),
+            };
+        }
+        Ok(left)
+    }
+
+    fn primary(&mut self, c: &mut Cursor) -> Result<Value, BasicError> {
+        match c.next() {
+            Some(Token::Number(n)) => Ok(Value::Num(n.parse().unwrap_or_default())),
+            Some(Token::Str(s)) => Ok(Value::Str(s.clone())),
+            Some(Token::Symbol('(')) => {
+                let value = self.expression(c)?;
+                c.expect_symbol(')')?;
+                Ok(value)
+            }
+            Some(Token::Word(name)) => self.function_or_variable(name, c),
+            _ => error(SYNTAX_ERROR),
+        }
+    }
+
+    fn function_or_variable(&mut self, name: &str, c: &mut Cursor) -> Result<Value, BasicError> {
+        if name == "ERR" {
+            return Ok(Value::Num(self.trapped.map_or(0, |t| t.0) as f64));
+        }
+        if !matches!(name, "CHR$" | "ENVIRON$" | "EOF" | "LEN") {
+            return Ok(self.vars.get(name).cloned().unwrap_or_else(|| {
+                if name.ends_with('$') {
+                    Value::Str(String::new())
+                } else {
+                    Value::Num(0.0)
+                }
+            }));
+        }
+        c.expect_symbol('(')?;
+        let arg = self.expression(c)?;
+        c.expect_symbol(')')?;
+        let result = match name {
+            "CHR$" => Value::Str(((arg.num()? as u8) as char).to_string()),
+            "ENVIRON$" => {
+                let key = arg.str()?;
+                Value::Str(
+                    self.env
+                        .iter()
+                        .find(|(k, _)| k.eq_ignore_ascii_case(&key))
+                        .map(|(_, v)| v.clone())
+                        .unwrap_or_default(),
+                )
+            }
+            "EOF" => match self.files.get_mut(&(arg.num()? as i64)) {
+                Some(Channel::Input(reader)) => {
+                    bool_value(reader.fill_buf().map_or(true, |b| b.is_empty()))
+                }
+                Some(Channel::Output(_)) => return error(BAD_FILE_MODE),
+                None => return error(BAD_FILE_NUMBER),
+            },
+            _ => Value::Num(arg.str()?.len() as f64), // LEN
+        };
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+
+    /// Collects the output of a program.
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run_in(dir: &Path, program: &str, env: &[(&str, &str)]) -> (Ending, String) {
+        let dos = DosFs::new(dir);
+        let env: Vec<(String, String)> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let captured = Captured::default();
+        let ending = run(
+            program,
+            &dos,
+            &env,
+            Box::new(io::empty()),
+            Box::new(captured.clone()),
+        )
+        .unwrap();
+        let output = String::from_utf8(captured.0.borrow().clone()).unwrap();
+        (ending, output)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_print() {
+        let dir = temp_dir("blr-fake-basic-print");
+        let (ending, output) = run_in(
+            &dir,
+            "10 A = 3: B$ = \"x\"\n20 PRINT \"a\"; A, B$ + CHR$(34)\n30 PRINT LEN(B$);\n40 SYSTEM\n",
+            &[],
+        );
+        assert_eq!(ending, Ending::System);
+        assert_eq!(output, "a 3           x\"\r\n 1 ");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let dir = temp_dir("blr-fake-basic-flow");
+        let program = "\
+10 I = 0
+20 WHILE I < 3
+30 I = I + 1
+40 IF I <> 2 THEN PRINT I;
+50 WEND
+60 IF I >= 3 GOTO 80
+70 PRINT \"unreachable\"
+80 SYSTEM
+";
+        let (ending, output) = run_in(&dir, program, &[]);
+        assert_eq!(ending, Ending::System);
+        assert_eq!(output, " 1  3 ");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_and_error_handling() {
+        let dir = temp_dir("blr-fake-basic-files");
+        let program = "\
+10 ON ERROR GOTO 100
+20 OPEN \"TODO.DAT\" FOR INPUT AS #1
+30 OPEN \"todo.dat\" FOR APPEND AS #1
+40 PRINT #1, ENVIRON$(\"ITEM\")
+50 CLOSE #1
+60 OPEN \"TODO.DAT\" FOR INPUT AS #1
+70 LINE INPUT #1, A$
+80 PRINT A$; EOF(1)
+90 SYSTEM
+100 IF ERR = 53 THEN RESUME 30
+";
+        let (ending, output) = run_in(&dir, program, &[("ITEM", "buy milk")]);
+        assert_eq!(ending, Ending::System);
+        assert_eq!(output, "buy milk-1 \r\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("TODO.DAT")).unwrap(),
+            "buy milk\r\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_untrapped_error_stops_at_prompt() {
+        let dir = temp_dir("blr-fake-basic-error");
+        let (ending, output) = run_in(
+            &dir,
+            "10 OPEN \"NOPE.DAT\" FOR INPUT AS #1\n20 SYSTEM\n",
+            &[],
+        );
+        assert_eq!(ending, Ending::Prompt);
+        assert_eq!(output, "File not found in 10\r\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_error_handler_disabled_inside_handler() {
+        let dir = temp_dir("blr-fake-basic-handler");
+        let program = "10 ON ERROR GOTO 100\n20 OPEN \"NOPE.DAT\" FOR INPUT AS #1\n30 SYSTEM\n100 PRINT \"oops\"\n110 ON ERROR GOTO 0\n";
+        let (ending, output) = run_in(&dir, program, &[]);
+        assert_eq!(ending, Ending::Prompt);
+        assert_eq!(output, "oops\r\nFile not found in 20\r\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_statement() {
+        let dir = temp_dir("blr-fake-basic-unsupported");
+        let (ending, output) = run_in(&dir, "10 GOSUB 20\n20 END\n", &[]);
+        assert_eq!(ending, Ending::Prompt);
+        assert_eq!(output, "Syntax error in 10\r\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(42.0), " 42");
+        assert_eq!(format_number(-0.5), "-.5");
+        assert_eq!(format_number(1.25), " 1.25");
+    }
+}
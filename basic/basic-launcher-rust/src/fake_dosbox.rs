@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crate::fake_basic;
+use crate::tokenized;
+
+/// Environment variable with a command that replaces the BASIC stand-in.
+/// It gets the host path of the program as its argument, the variables set by
+/// the batch file as its environment, and the redirected stdin and stdout.
+const EV_FAKE_SCRIPT: &str = "BLR_FAKE_SCRIPT";
+
+/// The value of `BLR_DOSBOX` that selects the fake.
+pub const FAKE_DOSBOX: &str = "fake";
+
+/// How a BASIC program ended.
+#[derive(Debug, PartialEq)]
+pub enum Ending {
+    /// With SYSTEM, returning to DOS.
+    System,
+    /// At the interpreter prompt (END, STOP, an error or the end of the
+    /// program), where the real interpreter waits for the keyboard forever.
+    Prompt,
+}
+
+/// The DOS drives and current directories, mapped to host directories.
+#[derive(Debug)]
+pub struct DosFs {
+    drives: HashMap<char, PathBuf>,
+    current_dirs: HashMap<char, Vec<String>>,
+    drive: char,
+}
+
+impl DosFs {
+    /// DOSBox mounts the directory of the batch file as `C:`.
+    pub fn new(c_drive: &Path) -> Self {
+        let mut drives = HashMap::new();
+        drives.insert('C', c_drive.to_path_buf());
+        Self {
+            drives,
+            current_dirs: HashMap::new(),
+            drive: 'C',
+        }
+    }
+
+    pub fn mount(&mut self, drive: char, dir: &Path) {
+        self.drives
+            .insert(drive.to_ascii_uppercase(), dir.to_path_buf());
+    }
+
+    pub fn set_drive(&mut self, drive: char) -> io::Result<()> {
+        let drive = drive.to_ascii_uppercase();
+        if !self.drives.contains_key(&drive) {
+            return Err(invalid_data(format!("Invalid drive {}:", drive)));
+        }
+        self.drive = drive;
+        Ok(())
+    }
+
+    pub fn change_dir(&mut self, path: &str) -> io::Result<()> {
+        let (drive, components) = self.dos_components(path)?;
+        if !self.host_path(drive, &components).is_dir() {
+            return Err(invalid_data(format!("Invalid directory {}", path)));
+        }
+        self.current_dirs.insert(drive, components);
+        Ok(())
+    }
+
+    /// The host directory of the current drive and directory.
+    pub fn current_dir(&self) -> PathBuf {
+        let components = self
+            .current_dirs
+            .get(&self.drive)
+            .cloned()
+            .unwrap_or_default();
+        self.host_path(self.drive, &components)
+    }
+
+    /// Maps a DOS path, absolute or relative to the current directory, to a
+    /// host path. Names are matched without regard to case, like DOSBox does.
+    pub fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let (drive, components) = self.dos_components(path)?;
+        Ok(self.host_path(drive, &components))
+    }
+
+    fn dos_components(&self, path: &str) -> io::Result<(char, Vec<String>)> {
+        let (drive, rest) = match path.as_bytes() {
+            [d, b':', ..] => ((*d as char).to_ascii_uppercase(), &path[2..]),
+            _ => (self.drive, path),
+        };
+        if !self.drives.contains_key(&drive) {
+            return Err(invalid_data(format!("Invalid drive {}:", drive)));
+        }
+        let mut components: Vec<String> = if rest.starts_with('\\') {
+            vec![]
+        } else {
+            self.current_dirs.get(&drive).cloned().unwrap_or_default()
+        };
+        for component in rest.split('\\').filter(|c| !c.is_empty()) {
+            match component {
+                "." => {}
+                ".." => {
+                    components.pop();
+                }
+                _ => components.push(component.to_string()),
+            }
+        }
+        Ok((drive, components))
+    }
+
+    fn host_path(&self, drive: char, components: &[String]) -> PathBuf {
+        let mut result = self.drives[&drive].clone();
+        for component in components {
+            result = find_ignoring_case(&result, component);
+        }
+        result
+    }
+}
+
+/// Finds an entry of a host directory regardless of case. New files get
+/// upper case names, like in DOS.
+fn find_ignoring_case(dir: &Path, name: &str) -> PathBuf {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .eq_ignore_ascii_case(name)
+            {
+                return entry.path();
+            }
+        }
+    }
+    dir.join(name.to_ascii_uppercase())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The interpreter line of the batch file, e.g.
+/// `C:\BIN\QBASIC.EXE /RUN TODO.BAS /CMD a b <C:\X.INP >C:\X.OUT`.
+/// The command tail of `/CMD` is left out, as the stand-in has no `COMMAND$`.
+#[derive(Debug, Default, PartialEq)]
+struct InterpreterLine {
+    interpreter: String,
+    program: String,
+    stdin: Option<String>,
+    stdout: Option<String>,
+}
+
+fn parse_interpreter_line(line: &str) -> io::Result<InterpreterLine> {
+    let mut result = InterpreterLine::default();
+    let mut words: Vec<&str> = vec![];
+    for word in line.split_whitespace() {
+        if let Some(stdin) = word.strip_prefix('<') {
+            result.stdin = Some(stdin.to_string());
+        } else if let Some(stdout) = word.strip_prefix('>') {
+            result.stdout = Some(stdout.to_string());
+        } else {
+            words.push(word);
+        }
+    }
+    let mut iter = words.into_iter().peekable();
+    result.interpreter = iter.next().unwrap_or_default().to_string();
+    if iter.peek().is_some_and(|w| w.eq_ignore_ascii_case("/RUN")) {
+        iter.next();
+    }
+    result.program = iter
+        .next()
+        .ok_or_else(|| invalid_data(format!("No program in {}", line)))?
+        .to_string();
+    Ok(result)
+}
+
+/// Expands a batch file line like COMMAND.COM: `%%` is a percent sign, `%0`
+/// to `%9` are the batch file's parameters and `%NAME%` is a variable.
+/// Unknown variables and missing parameters expand to nothing, and a `%`
+/// without a closing one is kept.
+fn expand(line: &str, params: &[&str], env: &[(String, String)]) -> String {
+    let mut result = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('%') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.chars().next() {
+            Some('%') => {
+                result.push('%');
+                rest = &after[1..];
+            }
+            Some(digit @ '0'..='9') => {
+                let n = digit.to_digit(10).unwrap() as usize;
+                result.push_str(params.get(n).copied().unwrap_or_default());
+                rest = &after[1..];
+            }
+            _ => match after.find('%') {
+                Some(end) => {
+                    let name = &after[..end];
+                    if let Some((_, value)) = env.iter().find(|(k, _)| k.eq_ignore_ascii_case(name))
+                    {
+                        result.push_str(value);
+                    }
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    result.push('%');
+                    rest = after;
+                }
+            },
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Implements the hidden `fake-dosbox` command, which takes the place of
+/// DOSBox when `BLR_DOSBOX` is `fake`: `fake-dosbox BATCH [DOSBOX FLAGS...]`.
+///
+/// It runs the batch file like DOSBox would, expanding `%` on every line
+/// first: `MOUNT`, `SET`, drive changes, `CD`, `ECHO.>FILE`, and the
+/// interpreter line with its `<` and `>` redirections. The program runs in a
+/// tiny stand-in for BASIC, or in `BLR_FAKE_SCRIPT` if set.
+/// A program that doesn't end with SYSTEM leaves the fake waiting forever,
+/// like the real interpreter at its prompt.
+pub fn fake_dosbox_command(args: &[String]) -> io::Result<()> {
+    let batch_file = PathBuf::from(
+        args.first()
+            .ok_or_else(|| invalid_data("Usage: fake-dosbox BATCH".to_string()))?,
+    );
+    let batch_dir = batch_file.parent().unwrap_or_else(|| Path::new("."));
+    let mut dos = DosFs::new(batch_dir);
+    // DOSBox runs the batch file without arguments
+    let batch_name = batch_file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_ascii_uppercase())
+        .unwrap_or_default();
+    let params = [batch_name.as_str()];
+    let mut env: Vec<(String, String)> = vec![];
+    for line in fs::read_to_string(&batch_file)?.lines() {
+        let line = expand(line.trim(), &params, &env);
+        let line = line.as_str();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match command.to_ascii_uppercase().as_str() {
+            "" => {}
+            "MOUNT" => {
+                let (drive, dir) = rest
+                    .trim()
+                    .split_once(' ')
+                    .ok_or_else(|| invalid_data(format!("Bad MOUNT: {}", line)))?;
+                let drive = drive.chars().next().unwrap_or_default();
+                dos.mount(drive, Path::new(dir.trim().trim_matches('"')));
+            }
+            "SET" => {
+                let (key, value) = rest
+                    .split_once('=')
+                    .ok_or_else(|| invalid_data(format!("Bad SET: {}", line)))?;
+                env.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+                env.push((key.to_ascii_uppercase(), value.to_string()));
+            }
+            "CD" => dos.change_dir(rest.trim())?,
+            // ECHO.>C:\K3X9QZ0A.BOT, an empty line into a file
+            echo if echo.starts_with("ECHO.>") => {
+                fs::write(dos.resolve(&line["ECHO.>".len()..])?, "\r\n")?
+            }
+            drive if drive.len() == 2 && drive.ends_with(':') => {
+                dos.set_drive(drive.chars().next().unwrap())?
+            }
+            _ => {
+                if run_interpreter(&dos, &env, &parse_interpreter_line(line)?)? == Ending::Prompt {
+                    wait_forever();
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_interpreter(
+    dos: &DosFs,
+    env: &[(String, String)],
+    line: &InterpreterLine,
+) -> io::Result<Ending> {
+    if !dos.resolve(&line.interpreter)?.is_file() {
+        eprintln!("Illegal command: {}", line.interpreter);
+        return Ok(Ending::System);
+    }
+    let program = dos.resolve(&line.program)?;
+    if let Ok(script) = env::var(EV_FAKE_SCRIPT) {
+        return run_script(&script, dos, env, line, &program);
+    }
+    let stdin: Box<dyn io::BufRead> = match &line.stdin {
+        Some(stdin) => Box::new(BufReader::new(File::open(dos.resolve(stdin)?)?)),
+        None => Box::new(io::empty()),
+    };
+    let stdout: Box<dyn io::Write> = match &line.stdout {
+        Some(stdout) => Box::new(File::create(dos.resolve(stdout)?)?),
+        None => Box::new(io::sink()),
+    };
+    let contents = fs::read(&program)?;
+    let listing = if tokenized::is_tokenized(&contents) {
+        tokenized::detokenize(&contents)?
+    } else {
+        contents
+    };
+    let text = String::from_utf8_lossy(&listing);
+    fake_basic::run(&text, dos, env, stdin, stdout)
+}
+
+fn run_script(
+    script: &str,
+    dos: &DosFs,
+    env: &[(String, String)],
+    line: &InterpreterLine,
+    program: &Path,
+) -> io::Result<Ending> {
+    let stdin = match &line.stdin {
+        Some(stdin) => Stdio::from(File::open(dos.resolve(stdin)?)?),
+        None => Stdio::null(),
+    };
+    let stdout = match &line.stdout {
+        Some(stdout) => Stdio::from(File::create(dos.resolve(stdout)?)?),
+        None => Stdio::null(),
+    };
+    let status = Command::new(script)
+        .arg(program)
+        .current_dir(dos.current_dir())
+        .env_clear()
+        .env("PATH", env::var_os("PATH").unwrap_or_default())
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(stdin)
+        .stdout(stdout)
+        .status()?;
+    Ok(if status.success() {
+        Ending::System
+    } else {
+        Ending::Prompt
+    })
+}
+
+fn wait_forever() -> ! {
+    loop {
+        thread::sleep(Duration::from_secs(3600));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interpreter_line() {
+        let line = parse_interpreter_line(
+            "C:\\BIN\\QB.EXE /RUN TODO.BAS /CMD a \"b c\" <C:\\X.INP >C:\\X.OUT",
+        )
+        .unwrap();
+        assert_eq!(
+            line,
+            InterpreterLine {
+                interpreter: "C:\\BIN\\QB.EXE".to_string(),
+                program: "TODO.BAS".to_string(),
+                stdin: Some("C:\\X.INP".to_string()),
+                stdout: Some("C:\\X.OUT".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_interpreter_line_gwbasic() {
+        let line =
+            parse_interpreter_line("C:\\GWBASIC.EXE HELLO.BAS <C:\\X.INP >C:\\X.OUT").unwrap();
+        assert_eq!(line.program, "HELLO.BAS");
+    }
+
+    #[test]
+    fn test_expand() {
+        let env = vec![("QUERY_STRING".to_string(), "id=1".to_string())];
+        let expand = |line| expand(line, &["RUN.BAT"], &env);
+        assert_eq!(expand("SET X=100%%25"), "SET X=100%25");
+        assert_eq!(expand("SET X=100%25"), "SET X=1005");
+        assert_eq!(
+            expand("ECHO %query_string%|%PATH%|%0"),
+            "ECHO id=1||RUN.BAT"
+        );
+        assert_eq!(expand("ECHO 100%"), "ECHO 100%");
+    }
+
+    #[test]
+    fn test_dos_fs() {
+        let root = env::temp_dir().join("blr-fake-dosbox-fs");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("long name")).unwrap();
+        fs::write(root.join("src").join("hello.bas"), "").unwrap();
+
+        let mut dos = DosFs::new(&root);
+        dos.mount('d', &root.join("long name"));
+        assert_eq!(
+            dos.resolve("C:\\SRC\\HELLO.BAS").unwrap(),
+            root.join("src").join("hello.bas")
+        );
+        dos.change_dir("C:\\SRC").unwrap();
+        assert_eq!(dos.current_dir(), root.join("src"));
+        assert_eq!(
+            dos.resolve("TODO.DAT").unwrap(),
+            root.join("src").join("TODO.DAT")
+        );
+        assert_eq!(dos.resolve("..\\X.INP").unwrap(), root.join("X.INP"));
+        dos.set_drive('D').unwrap();
+        assert_eq!(dos.current_dir(), root.join("long name"));
+        assert!(dos.set_drive('E').is_err());
+        assert!(dos.change_dir("C:\\NOPE").is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
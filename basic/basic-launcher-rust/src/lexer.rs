@@ -0,0 +1,215 @@
+/// A token of a BASIC program line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    /// A number, e.g. `10`, `1.5` or `&H1F`.
+    Number(String),
+    /// The contents of a string literal, without the quotes.
+    Str(String),
+    /// A keyword or an identifier in upper case, including its type suffix.
+    Word(String),
+    /// Statement separator.
+    Colon,
+    /// Any other character, e.g. operators and parentheses.
+    Symbol(char),
+}
+
+/// A lexed line of a BASIC program.
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    /// The line in the file, starting at 1.
+    pub file_line: usize,
+    /// The line number of GW-Basic style programs.
+    pub number: Option<u32>,
+    /// The tokens of the line. Comments and the contents of DATA are left out.
+    pub tokens: Vec<Token>,
+}
+
+impl Line {
+    /// The number to use when reporting a problem with this line:
+    /// the BASIC line number if there is one, the line in the file otherwise.
+    pub fn display_number(&self) -> usize {
+        self.number.map(|n| n as usize).unwrap_or(self.file_line)
+    }
+}
+
+/// Splits a program into lines of tokens. Blank lines are skipped.
+pub fn lex(text: &str) -> Vec<Line> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| lex_line(i + 1, line))
+        .collect()
+}
+
+fn lex_line(file_line: usize, line: &str) -> Line {
+    let chars: Vec<char> = line.trim().chars().collect();
+    let mut pos = chars.iter().take_while(|c| c.is_ascii_digit()).count();
+    let number: Option<u32> = if pos > 0 {
+        chars[..pos].iter().collect::<String>().parse().ok()
+    } else {
+        None
+    };
+    let mut tokens: Vec<Token> = vec![];
+    while pos < chars.len() {
+        let ch = chars[pos];
+        if ch.is_whitespace() {
+            pos += 1;
+        } else if ch == '\'' {
+            break;
+        } else if ch == '"' {
+            let len = chars[pos + 1..].iter().take_while(|c| **c != '"').count();
+            tokens.push(Token::Str(chars[pos + 1..pos + 1 + len].iter().collect()));
+            pos += len + 2;
+        } else if ch == ':' {
+            tokens.push(Token::Colon);
+            pos += 1;
+        } else if ch.is_ascii_digit() || (ch == '.' && next_is_digit(&chars, pos)) {
+            let len = chars[pos..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit() || **c == '.')
+                .count();
+            tokens.push(Token::Number(chars[pos..pos + len].iter().collect()));
+            pos += len;
+        } else if ch == '&' && pos + 1 < chars.len() && chars[pos + 1].is_ascii_alphanumeric() {
+            let len = 1 + chars[pos + 1..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric())
+                .count();
+            tokens.push(Token::Number(chars[pos..pos + len].iter().collect()));
+            pos += len;
+        } else if ch.is_ascii_alphabetic() {
+            let mut len = chars[pos..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == '.')
+                .count();
+            if let Some('$') | Some('%') | Some('!') | Some('#') | Some('&') = chars.get(pos + len)
+            {
+                len += 1;
+            }
+            let word: String = chars[pos..pos + len]
+                .iter()
+                .collect::<String>()
+                .to_ascii_uppercase();
+            pos += len;
+            if word == "REM" {
+                break;
+            }
+            let is_data = word == "DATA";
+            tokens.push(Token::Word(word));
+            if is_data {
+                pos += data_length(&chars[pos..]);
+            }
+        } else {
+            tokens.push(Token::Symbol(ch));
+            pos += 1;
+        }
+    }
+    Line {
+        file_line,
+        number,
+        tokens,
+    }
+}
+
+fn next_is_digit(chars: &[char], pos: usize) -> bool {
+    chars.get(pos + 1).is_some_and(|c| c.is_ascii_digit())
+}
+
+/// The length of the contents of a DATA statement, up to the next colon
+/// that is not inside a string.
+fn data_length(chars: &[char]) -> usize {
+    let mut in_string = false;
+    for (i, ch) in chars.iter().enumerate() {
+        if *ch == '"' {
+            in_string = !in_string;
+        } else if *ch == ':' && !in_string {
+            return i;
+        }
+    }
+    chars.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(s: &str) -> Token {
+        Token::Word(s.to_string())
+    }
+
+    #[test]
+    fn test_lex_numbered_line() {
+        let lines = lex("10 PRINT \"Hello\": SYSTEM\r\n");
+        assert_eq!(
+            lines,
+            vec![Line {
+                file_line: 1,
+                number: Some(10),
+                tokens: vec![
+                    word("PRINT"),
+                    Token::Str("Hello".to_string()),
+                    Token::Colon,
+                    word("SYSTEM")
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_lex_skips_blank_lines_and_comments() {
+        let lines = lex("\r\n10 REM hello\r\n\r\n20 X = 1 ' one\r\n");
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].tokens, vec![]);
+        assert_eq!(lines[1].file_line, 4);
+        assert_eq!(
+            lines[1].tokens,
+            vec![
+                word("X"),
+                Token::Symbol('='),
+                Token::Number("1".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_unnumbered_line() {
+        let lines = lex("a$ = environ$(\"STDIN\")");
+        assert_eq!(lines[0].number, None);
+        assert_eq!(lines[0].display_number(), 1);
+        assert_eq!(
+            lines[0].tokens,
+            vec![
+                word("A$"),
+                Token::Symbol('='),
+                word("ENVIRON$"),
+                Token::Symbol('('),
+                Token::Str("STDIN".to_string()),
+                Token::Symbol(')')
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_data() {
+        let lines = lex("10 DATA 1, \"a:b\", c: SYSTEM");
+        assert_eq!(
+            lines[0].tokens,
+            vec![word("DATA"), Token::Colon, word("SYSTEM")]
+        );
+    }
+
+    #[test]
+    fn test_lex_numbers() {
+        let lines = lex("10 X = .5 + &H1F");
+        assert_eq!(
+            lines[0].tokens,
+            vec![
+                word("X"),
+                Token::Symbol('='),
+                Token::Number(".5".to_string()),
+                Token::Symbol('+'),
+                Token::Number("&H1F".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How much is logged, from nothing to everything.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+/// Parses the value of `BLR_LOG`. An empty value turns logging off.
+pub fn parse_level(value: &str) -> Option<Level> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "off" => Some(Level::Off),
+        "error" => Some(Level::Error),
+        "warn" | "warning" => Some(Level::Warn),
+        "info" => Some(Level::Info),
+        "debug" => Some(Level::Debug),
+        _ => None,
+    }
+}
+
+/// A value of a log entry field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Object(Vec<(String, Value)>),
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Value::Int(n as i64)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Self {
+        Value::Int(n as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Float(n)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(o: Option<T>) -> Self {
+        o.map_or(Value::Null, Into::into)
+    }
+}
+
+impl Value {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => write!(out, "{}", b).unwrap(),
+            Value::Int(n) => write!(out, "{}", n).unwrap(),
+            Value::Float(n) if n.is_finite() => write!(out, "{:.3}", n).unwrap(),
+            Value::Float(_) => out.push_str("null"),
+            Value::Str(s) => write_json_string(s, out),
+            Value::Object(fields) => write_json_object(fields, out),
+        }
+    }
+}
+
+fn write_json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_json_object(fields: &[(String, Value)], out: &mut String) {
+    out.push('{');
+    for (i, (key, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_json_string(key, out);
+        out.push(':');
+        value.write_json(out);
+    }
+    out.push('}');
+}
+
+/// Formats an entry as one line of JSON: the time, the level, the message,
+/// the context of the launch and the given fields, in this order.
+pub fn format_entry(
+    time: SystemTime,
+    level: Level,
+    message: &str,
+    context: &[(String, Value)],
+    fields: &[(&str, Value)],
+) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default();
+    let mut entry: Vec<(String, Value)> = vec![
+        ("time".to_string(), Value::Float(seconds)),
+        ("level".to_string(), level.name().into()),
+        ("message".to_string(), message.into()),
+    ];
+    entry.extend(context.iter().cloned());
+    entry.extend(fields.iter().map(|(k, v)| (k.to_string(), v.clone())));
+    let mut result = String::new();
+    write_json_object(&entry, &mut result);
+    result
+}
+
+struct Logger {
+    level: Level,
+    file: Option<PathBuf>,
+    /// Fields that go into every entry, e.g. the request ID.
+    context: Vec<(String, Value)>,
+}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {
+    level: Level::Off,
+    file: None,
+    context: vec![],
+});
+
+/// Starts logging entries up to the given level, as JSON lines appended to
+/// the given file or written to stderr, which Apache puts in its error log.
+/// Panics are logged as errors from now on.
+pub fn init(level: Level, file: Option<PathBuf>) {
+    {
+        let mut logger = LOGGER.lock().unwrap();
+        logger.level = level;
+        logger.file = file;
+    }
+    if level >= Level::Error {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let message = match info.payload().downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => info
+                    .payload()
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_default(),
+            };
+            log(Level::Error, &message, &[]);
+            default_hook(info);
+        }));
+    }
+}
+
+pub fn enabled(level: Level) -> bool {
+    level != Level::Off && level <= LOGGER.lock().map(|l| l.level).unwrap_or(Level::Off)
+}
+
+/// Adds a field to every following entry.
+pub fn set_context(key: &str, value: Value) {
+    if let Ok(mut logger) = LOGGER.lock() {
+        logger.context.retain(|(k, _)| k != key);
+        logger.context.push((key.to_string(), value));
+    }
+}
+
+pub fn log(level: Level, message: &str, fields: &[(&str, Value)]) {
+    if !enabled(level) {
+        return;
+    }
+    // a panic while logging must not lose the original problem
+    let logger = match LOGGER.lock() {
+        Ok(logger) => logger,
+        Err(_) => return,
+    };
+    let line = format_entry(SystemTime::now(), level, message, &logger.context, fields);
+    let written = match &logger.file {
+        Some(file) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)
+            .and_then(|mut f| f.write_all(format!("{}\n", line).as_bytes())),
+        None => writeln!(std::io::stderr(), "{}", line),
+    };
+    if let Err(e) = written {
+        eprintln!("Could not write log entry: {}: {}", e, line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level(""), Some(Level::Off));
+        assert_eq!(parse_level("INFO"), Some(Level::Info));
+        assert_eq!(parse_level(" warn "), Some(Level::Warn));
+        assert_eq!(parse_level("verbose"), None);
+        assert!(Level::Debug > Level::Info && Level::Error > Level::Off);
+    }
+
+    #[test]
+    fn test_format_entry() {
+        let time = UNIX_EPOCH + Duration::from_millis(1700000000250);
+        let context = vec![("request_id".to_string(), Value::from("ABC"))];
+        let line = format_entry(
+            time,
+            Level::Info,
+            "launch finished",
+            &context,
+            &[
+                ("program", "C:\\\"X\".BAS\n".into()),
+                ("exit_status", Value::from(None::<i32>)),
+                ("stdout_bytes", 12u64.into()),
+                (
+                    "phases",
+                    Value::Object(vec![("dosbox_ms".to_string(), 1.5.into())]),
+                ),
+            ],
+        );
+        assert_eq!(
+            line,
+            "{\"time\":1700000000.250,\"level\":\"info\",\"message\":\"launch finished\",\
+             \"request_id\":\"ABC\",\"program\":\"C:\\\\\\\"X\\\".BAS\\n\",\
+             \"exit_status\":null,\"stdout_bytes\":12,\"phases\":{\"dosbox_ms\":1.500}}"
+        );
+    }
+
+    #[test]
+    fn test_control_characters_are_escaped() {
+        let mut out = String::new();
+        write_json_string("a\u{1}b\tc", &mut out);
+        assert_eq!(out, "\"a\\u0001b\\tc\"");
+    }
+}
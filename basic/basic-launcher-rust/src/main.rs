@@ -1,33 +1,269 @@
+use std::env;
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::stdin;
 use std::io::BufReader;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 mod batch_file;
+mod check;
+mod debug_bundle;
+mod dialect;
+mod diff;
+mod dos_names;
+#[cfg(feature = "fake")]
+mod fake_basic;
+#[cfg(feature = "fake")]
+mod fake_dosbox;
+mod lexer;
+mod logging;
 mod options;
 mod rand_file;
+mod recording;
+mod signals;
 mod temp_files;
+mod test_cases;
+mod timings;
+mod tokenized;
 
 use batch_file::create_batch_file;
-use temp_files::TempFiles;
+use temp_files::{remove_if_exists, TempFiles};
+use timings::Timings;
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("check") => exit_on_error(check::check_command(&args[1..])),
+        #[cfg(feature = "fake")]
+        Some("fake-dosbox") => exit_on_error(fake_dosbox::fake_dosbox_command(&args[1..])),
+        Some("gc") => exit_on_error(temp_files::gc_command(&args[1..])),
+        Some("replay") => exit_on_error(recording::replay_command(&args[1..])),
+        Some("test") => exit_on_error(test_cases::test_command(&args[1..])),
+        Some("tokenize") => exit_on_error(tokenized::tokenize_command(&args[1..])),
+        Some("detokenize") => exit_on_error(tokenized::detokenize_command(&args[1..])),
+        _ => run(),
+    }
+}
+
+fn exit_on_error(result: std::io::Result<()>) {
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+fn run() {
+    let (log_level, log_file) = options::parse_log_options(&options::ProcessSource);
+    logging::init(log_level, log_file);
     let options = options::parse_options();
-    let temp_files = TempFiles::create(&options);
-    create_stdin(&options, &temp_files.stdin_file).expect("Could not create stdin");
-    create_batch_file(&options, &temp_files).expect("Could not create batch file");
-    run_dosbox(&options, &temp_files);
-    print_stdout(&temp_files).expect("Could not read stdout");
-    if options.cleanup {
-        cleanup(&temp_files).expect("Could not cleanup files");
+    log_options(&options);
+    if options.explain {
+        explain(&options);
+    }
+    if options.check {
+        preflight(&options);
+    }
+    signals::install_handler();
+    let mut timings = Timings::new();
+    let temp_files = timings.phase("batch", || {
+        let temp_files = TempFiles::create(&options).expect("Could not create temp files");
+        create_args_file(&options, &temp_files.args_file).expect("Could not create args file");
+        let program = prepare_program(&options, &temp_files).expect("Could not read program");
+        create_batch_file(&options, &temp_files, &program).expect("Could not create batch file");
+        temp_files
+    });
+    let body = timings.phase("stdin", || {
+        create_stdin(&options, &temp_files.stdin_file).expect("Could not create stdin")
+    });
+    if logging::enabled(logging::Level::Debug) {
+        let batch = fs::read_to_string(&temp_files.batch_file).unwrap_or_default();
+        logging::log(
+            logging::Level::Debug,
+            "batch file",
+            &[("batch", batch.into())],
+        );
+    }
+    let dosbox = timings.phase("dosbox", || run_dosbox(&options, &temp_files));
+    timings.record("dosbox_spawn", dosbox.spawn);
+    if let Some(boot) = dosbox.boot {
+        timings.record("dosbox_boot", boot);
+    }
+    timings.record("dosbox_run", dosbox.run);
+    let status = dosbox.status;
+    let success = status.is_some_and(|s| s.success());
+    if success {
+        let headers = response_headers(&options, &timings);
+        timings.phase("stdout", || {
+            print_stdout(&temp_files, &headers).expect("Could not read stdout")
+        });
+    }
+    if let Some(record_dir) = options.record_dir.as_ref().filter(|_| success) {
+        if let Err(e) = recording::record(record_dir, &options, &temp_files, &body) {
+            eprintln!("Could not record launch: {}", e);
+        }
+    }
+    if let Some(debug_dir) = &options.debug_dir {
+        match debug_bundle::write_bundle(
+            debug_dir,
+            &options,
+            &temp_files,
+            &timings,
+            status.and_then(|s| s.code()),
+        ) {
+            Ok(bundle) => eprintln!("Debug bundle written to {}", bundle.display()),
+            Err(e) => eprintln!("Could not write debug bundle: {}", e),
+        }
+    }
+    log_launch(&temp_files, &timings, status, success);
+    match (status, options.timeout) {
+        (None, Some(timeout)) => panic!("DOSBox did not finish within {:?}", timeout),
+        _ if !success => panic!("DOSBox did not return a success error code"),
+        _ => {}
+    }
+}
+
+/// Puts what identifies the launch in every log entry.
+fn log_options(options: &options::Options) {
+    let backend = if is_fake_dosbox(options) {
+        "fake"
+    } else {
+        "dosbox"
+    };
+    logging::set_context("request_id", options.request_id.as_str().into());
+    logging::set_context("program", options.program.display().to_string().into());
+    logging::set_context("mode", format!("{:?}", options.mode).into());
+    logging::set_context("backend", backend.into());
+    logging::log(
+        logging::Level::Debug,
+        "options",
+        &[
+            ("dosbox", options.dosbox.as_str().into()),
+            ("dosbox_conf", options.dosbox_conf.as_str().into()),
+            ("interpreter", options.basic.display().to_string().into()),
+            ("mode_reason", options.mode_reason.as_str().into()),
+            ("arg_count", (options.program_args.len() as u64).into()),
+            (
+                "timeout_ms",
+                options.timeout.map(|t| t.as_secs_f64() * 1000.0).into(),
+            ),
+        ],
+    );
+}
+
+/// Logs the outcome of the launch. When DOSBox fails, its own output is
+/// logged as well, as it is otherwise lost with the temporary files.
+fn log_launch(
+    temp_files: &TempFiles,
+    timings: &Timings,
+    status: Option<ExitStatus>,
+    success: bool,
+) {
+    let file_size = |path: &Path| fs::metadata(path).map(|m| m.len()).ok();
+    let phases: Vec<(String, logging::Value)> = timings
+        .phases()
+        .iter()
+        .map(|(name, d)| (format!("{}_ms", name), (d.as_secs_f64() * 1000.0).into()))
+        .collect();
+    logging::log(
+        logging::Level::Info,
+        "launch finished",
+        &[
+            ("success", success.into()),
+            ("exit_status", status.and_then(|s| s.code()).into()),
+            ("timed_out", status.is_none().into()),
+            ("phases", logging::Value::Object(phases)),
+            ("total_ms", (timings.total().as_secs_f64() * 1000.0).into()),
+            ("stdin_bytes", file_size(&temp_files.stdin_file).into()),
+            ("stdout_bytes", file_size(&temp_files.stdout_file).into()),
+        ],
+    );
+    if !success {
+        let read = |path: &Path| fs::read_to_string(path).unwrap_or_default();
+        logging::log(
+            logging::Level::Warn,
+            "DOSBox output",
+            &[
+                ("dosbox_log", read(&temp_files.dosbox_log_file).into()),
+                ("dosbox_err", read(&temp_files.dosbox_err_file).into()),
+            ],
+        );
+    }
+}
+
+/// Extra headers for the program's CGI response. In debug mode, the request ID
+/// goes into the response so that it can be matched with its debug bundle.
+/// The phases so far go into a `Server-Timing` header if it is enabled.
+fn response_headers(options: &options::Options, timings: &Timings) -> Vec<String> {
+    let mut result = vec![];
+    if env::var_os("GATEWAY_INTERFACE").is_none() {
+        return result;
+    }
+    if options.debug_dir.is_some() {
+        result.push(format!("X-Request-Id: {}", options.request_id));
+    }
+    if options.server_timing {
+        result.push(format!(
+            "Server-Timing: {}",
+            timings::server_timing(timings)
+        ));
+    }
+    result
+}
+
+/// Reports on stderr the decisions taken while parsing the options.
+fn explain(options: &options::Options) {
+    eprintln!("program: {}", options.program.display());
+    eprintln!("mode: {:?} ({})", options.mode, options.mode_reason);
+    eprintln!("interpreter: {}", options.basic.display());
+    eprintln!("redirect depth: {}", options.redirect_depth);
+    let keys = options::OPTION_KEYS
+        .iter()
+        .chain(batch_file::WHITE_LIST_KEYS);
+    for key in keys {
+        let source = &options::ProcessSource;
+        if let Some(description) = options::describe_variable(source, key, options.redirect_depth) {
+            eprintln!("variable: {}", description);
+        }
+    }
+}
+
+/// Refuses to launch DOSBox for a program with errors that would make it
+/// fail or hang. Warnings are reported but don't stop the program.
+fn preflight(options: &options::Options) {
+    let diagnostics =
+        check::check_file(&options.program, options.mode).expect("Could not check program");
+    for diagnostic in &diagnostics {
+        check::print_diagnostic(&options.program, diagnostic);
+    }
+    if diagnostics
+        .iter()
+        .any(|d| d.severity == check::Severity::Error)
+    {
+        exit(1);
+    }
+}
+
+/// GW-Basic loads tokenized programs on its own, QBasic needs a listing.
+fn prepare_program(options: &options::Options, temp_files: &TempFiles) -> std::io::Result<PathBuf> {
+    if let options::BasicMode::QBasic = options.mode {
+        let contents = fs::read(&options.program)?;
+        if tokenized::is_tokenized(&contents) {
+            fs::write(&temp_files.listing_file, tokenized::detokenize(&contents)?)?;
+            return Ok(temp_files.listing_file.clone());
+        }
     }
+    Ok(options.program.clone())
 }
 
-fn create_stdin(options: &options::Options, stdin_file: &PathBuf) -> std::io::Result<()> {
+/// Returns the body as it was read, before the conversion to DOS lines,
+/// if the launch is recorded.
+fn create_stdin(options: &options::Options, stdin_file: &Path) -> std::io::Result<Vec<u8>> {
     let mut f = File::create(stdin_file)?;
+    let mut body: Vec<u8> = vec![];
     if options.needs_stdin {
         let stdin = stdin();
         loop {
@@ -36,13 +272,52 @@ fn create_stdin(options: &options::Options, stdin_file: &PathBuf) -> std::io::Re
             if num_bytes == 0 {
                 break;
             }
+            if options.record_dir.is_some() {
+                body.extend_from_slice(line.as_bytes());
+            }
             write!(f, "{}\r\n", line.trim_end())?;
         }
     }
+    Ok(body)
+}
+
+/// Writes the program arguments one per line, so that they can be read
+/// with LINE INPUT without any quoting rules getting in the way.
+fn create_args_file(options: &options::Options, args_file: &Path) -> std::io::Result<()> {
+    let mut f = File::create(args_file)?;
+    for arg in &options.program_args {
+        write!(f, "{}\r\n", arg)?;
+    }
     Ok(())
 }
 
-fn run_dosbox(options: &options::Options, temp_files: &TempFiles) {
+/// How DOSBox ran. Its time is split into starting the process, booting
+/// until the batch file reaches the interpreter line (seen by the marker file
+/// it writes just before), and the rest: loading the interpreter, running the
+/// program and shutting down.
+struct DosboxRun {
+    /// `None` if DOSBox was killed because it ran out of time.
+    status: Option<ExitStatus>,
+    spawn: Duration,
+    /// `None` if the interpreter line was never reached.
+    boot: Option<Duration>,
+    run: Duration,
+}
+
+/// Whether `BLR_DOSBOX` selects the built-in fake, which only exists in a
+/// launcher built with the `fake` feature.
+#[cfg(feature = "fake")]
+fn is_fake_dosbox(options: &options::Options) -> bool {
+    options.dosbox == fake_dosbox::FAKE_DOSBOX
+}
+
+#[cfg(not(feature = "fake"))]
+fn is_fake_dosbox(_options: &options::Options) -> bool {
+    false
+}
+
+/// Runs DOSBox, or the built-in fake if `BLR_DOSBOX` is `fake`.
+fn run_dosbox(options: &options::Options, temp_files: &TempFiles) -> DosboxRun {
     let mut batch_file = format!("{}", temp_files.batch_file.display());
     let win_prefix = "\\\\?\\";
     if batch_file.starts_with(win_prefix) {
@@ -53,23 +328,79 @@ fn run_dosbox(options: &options::Options, temp_files: &TempFiles) {
 
     let log_file = File::create(&temp_files.dosbox_log_file).unwrap();
     let err_file = File::create(&temp_files.dosbox_err_file).unwrap();
-    let out = Command::new(&options.dosbox)
-        .args(&[&batch_file, "-exit", "-noautoexec", "-conf", &options.dosbox_conf])
+    // the batch file creates it again when it reaches the interpreter line
+    remove_if_exists(&temp_files.boot_file).expect("Could not remove boot marker");
+    let mut command = if is_fake_dosbox(options) {
+        let mut command = Command::new(env::current_exe().unwrap());
+        command.arg("fake-dosbox");
+        command
+    } else {
+        Command::new(&options.dosbox)
+    };
+    let start = Instant::now();
+    let started_at = SystemTime::now();
+    command
+        .args([
+            &batch_file,
+            "-exit",
+            "-noautoexec",
+            "-conf",
+            &options.dosbox_conf,
+        ])
         .env("SDL_VIDEODRIVER", "dummy")
         .env("TERM", "dumb")
         .stdout(log_file)
-        .stderr(err_file)
-        .output()
-        .unwrap();
+        .stderr(err_file);
+    let mut child = signals::spawn_child(&mut command).unwrap();
+    let spawn = start.elapsed();
+    let status = wait_with_timeout(&mut child, options.timeout).expect("Could not wait for DOSBox");
+    let total = start.elapsed();
+    // with a coarse file time the marker can seem older than DOSBox
+    let boot = fs::metadata(&temp_files.boot_file)
+        .and_then(|m| m.modified())
+        .ok()
+        .map(|booted| booted.duration_since(started_at).unwrap_or_default())
+        .map(|since_start| since_start.saturating_sub(spawn).min(total - spawn));
+    DosboxRun {
+        status,
+        spawn,
+        boot,
+        run: total - spawn - boot.unwrap_or_default(),
+    }
+}
 
-    if !out.status.success() {
-        panic!("DOSBox did not return a success error code");
+/// Waits for DOSBox, killing it if it is still running after the timeout.
+fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+) -> std::io::Result<Option<ExitStatus>> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return signals::wait_child(child).map(Some),
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = signals::try_wait_child(child)? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            signals::kill_child(child)?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
     }
 }
 
-fn print_stdout(temp_files: &TempFiles) -> std::io::Result<()> {
+/// Prints the output of the program, preceded by the given headers
+/// unless the program printed nothing.
+fn print_stdout(temp_files: &TempFiles, headers: &[String]) -> std::io::Result<()> {
     let f = File::open(&temp_files.stdout_file)?;
     let mut reader = BufReader::new(f);
+    if !reader.fill_buf()?.is_empty() {
+        for header in headers {
+            println!("{}", header);
+        }
+    }
     loop {
         let mut line = String::new();
         let len = reader.read_line(&mut line)?;
@@ -81,19 +412,3 @@ fn print_stdout(temp_files: &TempFiles) -> std::io::Result<()> {
     }
     Ok(())
 }
-
-fn cleanup(temp_files: &TempFiles) -> std::io::Result<()> {
-    remove_if_exists(&temp_files.batch_file)?;
-    remove_if_exists(&temp_files.dosbox_log_file)?;
-    remove_if_exists(&temp_files.dosbox_err_file)?;
-    remove_if_exists(&temp_files.stdin_file)?;
-    remove_if_exists(&temp_files.stdout_file)
-}
-
-fn remove_if_exists(p: &PathBuf) -> std::io::Result<()> {
-    if p.exists() {
-        fs::remove_file(p)
-    } else {
-        Ok(())
-    }
-}
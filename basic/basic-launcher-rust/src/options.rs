@@ -1,6 +1,15 @@
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::dialect;
+use crate::dialect::Dialect;
+use crate::dos_names;
+use crate::logging;
+use crate::logging::Level;
+use crate::rand_file;
 
 const DEFAULT_DOSBOX: &str = "C:\\Program Files (x86)\\DOSBox-0.74\\DOSBox.exe";
 const DEFAULT_DOSBOX_CONF: &str = "dosbox.conf";
@@ -13,8 +22,48 @@ const EV_NO_CLEANUP: &str = "BLR_NO_CLEANUP";
 const EV_BASIC_MODE: &str = "BLR_BASIC_MODE";
 const EV_PROGRAM: &str = "BLR_PROGRAM";
 const EV_DOSBOX_CONF: &str = "BLR_DOSBOX_CONF";
-
-#[derive(Debug)]
+const EV_CHECK: &str = "BLR_CHECK";
+const EV_DEBUG_DIR: &str = "BLR_DEBUG_DIR";
+const EV_RECORD_DIR: &str = "BLR_RECORD_DIR";
+const EV_TIMEOUT: &str = "BLR_TIMEOUT";
+const EV_REDIRECT_DEPTH: &str = "BLR_REDIRECT_DEPTH";
+const EV_EXPLAIN: &str = "BLR_EXPLAIN";
+const EV_LOG: &str = "BLR_LOG";
+const EV_LOG_FILE: &str = "BLR_LOG_FILE";
+const EV_SERVER_TIMING: &str = "BLR_SERVER_TIMING";
+
+/// The variables that configure the launcher, reported by `--explain`.
+pub const OPTION_KEYS: &[&str] = &[
+    EV_DOSBOX,
+    EV_DOSBOX_CONF,
+    EV_GWBASIC,
+    EV_QBASIC,
+    EV_BASIC_MODE,
+    EV_PROGRAM,
+    EV_NO_CLEANUP,
+    EV_CHECK,
+    EV_DEBUG_DIR,
+    EV_RECORD_DIR,
+    EV_TIMEOUT,
+    EV_REDIRECT_DEPTH,
+    EV_EXPLAIN,
+    EV_LOG,
+    EV_LOG_FILE,
+    EV_SERVER_TIMING,
+    "UNIQUE_ID",
+];
+
+/// How many `REDIRECT_` prefixes are considered, unless `BLR_REDIRECT_DEPTH`
+/// says otherwise: the original request and one internal redirect.
+const DEFAULT_REDIRECT_DEPTH: usize = 2;
+
+/// Apache gives up after 10 internal redirects (`LimitInternalRecursion`).
+const MAX_REDIRECT_DEPTH: usize = 10;
+
+/// Length of the request ID generated when the web server doesn't provide one.
+const REQUEST_ID_LENGTH: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BasicMode {
     GWBasic,
     QBasic,
@@ -26,27 +75,136 @@ pub struct Options {
     pub dosbox_conf: String,
     pub basic: PathBuf,
     pub mode: BasicMode,
+    /// Explains why the mode was chosen, printed with `--explain`.
+    pub mode_reason: String,
+    pub explain: bool,
     pub needs_stdin: bool,
     pub program: PathBuf,
+    pub program_args: Vec<String>,
     pub cleanup: bool,
+    /// Run the static checks before launching DOSBox.
+    pub check: bool,
+    /// Where to write a debug bundle of the launch, if anywhere.
+    pub debug_dir: Option<PathBuf>,
+    /// Where to record the launch for `replay`, if anywhere.
+    pub record_dir: Option<PathBuf>,
+    /// Identifies the launch in debug bundles and response headers.
+    pub request_id: String,
+    /// How long DOSBox may run before it is killed, if there is a limit.
+    pub timeout: Option<Duration>,
+    /// How many `REDIRECT_` prefixes are considered when reading variables.
+    pub redirect_depth: usize,
+    /// Add a `Server-Timing` header with the phases of the launch to CGI responses.
+    pub server_timing: bool,
+}
+
+/// The variant of an environment variable that was used, e.g.
+/// `REDIRECT_QUERY_STRING` for `QUERY_STRING`.
+#[derive(Debug, PartialEq)]
+pub struct RedirectVar {
+    pub key: String,
+    pub value: String,
+}
+
+/// The command line of the launcher, split into the launcher's own flags,
+/// the BASIC program and the arguments that are passed on to the program.
+///
+/// The accepted syntax is `[-i] [--explain] PROGRAM.BAS [-i] [--] [ARGS...]`. The `-i`
+/// flag is accepted right after the program for backwards compatibility,
+/// `--` can be used to pass a literal `-i` to the program.
+#[derive(Debug, Default, PartialEq)]
+struct CommandLine {
+    stdin_flag: bool,
+    explain_flag: bool,
+    program: Option<String>,
+    program_args: Vec<String>,
+}
+
+/// Where the options come from: the process environment and the file system
+/// when launching, fixed values in tests.
+pub trait Source {
+    /// The value of an environment variable, if it is set.
+    fn var(&self, key: &str) -> Option<String>;
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+}
+
+pub struct ProcessSource;
+
+impl Source for ProcessSource {
+    fn var(&self, key: &str) -> Option<String> {
+        env::var(key).ok()
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
 }
 
 pub fn parse_options() -> Options {
     let args: Vec<String> = env::args().skip(1).collect();
-    let x = parse_basic();
+    parse_options_from(&ProcessSource, &args)
+}
+
+pub fn parse_options_from(source: &dyn Source, args: &[String]) -> Options {
+    // first, as it decides how every other option is read
+    let depth = parse_redirect_depth(source);
+    let command_line = parse_command_line(args);
+    let program = parse_program(source, depth, &command_line);
+    let x = parse_basic(source, depth, parse_dialect(source, depth, &program));
     Options {
-        dosbox: parse_dosbox(),
-        dosbox_conf: parse_dosbox_conf(),
+        dosbox: parse_dosbox(source, depth),
+        dosbox_conf: parse_dosbox_conf(source, depth),
         basic: x.0,
         mode: x.1,
-        needs_stdin: parse_needs_stdin(&args),
-        program: parse_program(&args),
-        cleanup: parse_cleanup(),
+        mode_reason: x.2,
+        explain: command_line.explain_flag
+            || !get_redirect_env(source, EV_EXPLAIN, depth).is_empty(),
+        needs_stdin: parse_needs_stdin(source, &command_line),
+        program,
+        program_args: parse_program_args(&command_line),
+        cleanup: parse_cleanup(source, depth),
+        check: parse_check(source, depth),
+        debug_dir: parse_dir(source, depth, EV_DEBUG_DIR),
+        record_dir: parse_dir(source, depth, EV_RECORD_DIR),
+        request_id: parse_request_id(source, depth),
+        timeout: parse_timeout(source, depth),
+        redirect_depth: depth,
+        server_timing: !get_redirect_env(source, EV_SERVER_TIMING, depth).is_empty(),
     }
 }
 
-fn parse_dosbox() -> String {
-    let v = get_redirect_env(EV_DOSBOX);
+fn parse_command_line(args: &[String]) -> CommandLine {
+    let mut result = CommandLine::default();
+    let mut iterator = args.iter();
+    for arg in iterator.by_ref() {
+        if arg == "-i" {
+            result.stdin_flag = true;
+        } else if arg == "--explain" {
+            result.explain_flag = true;
+        } else {
+            result.program = Some(arg.to_string());
+            break;
+        }
+    }
+    let mut rest: Vec<String> = iterator.cloned().collect();
+    if rest.first().map(String::as_str) == Some("-i") {
+        result.stdin_flag = true;
+        rest.remove(0);
+    }
+    if rest.first().map(String::as_str) == Some("--") {
+        rest.remove(0);
+    }
+    result.program_args = rest;
+    result
+}
+
+fn parse_dosbox(source: &dyn Source, depth: usize) -> String {
+    let v = get_redirect_env(source, EV_DOSBOX, depth);
     if v.is_empty() {
         DEFAULT_DOSBOX.to_string()
     } else {
@@ -54,8 +212,8 @@ fn parse_dosbox() -> String {
     }
 }
 
-fn parse_dosbox_conf() -> String {
-    let v = get_redirect_env(EV_DOSBOX_CONF);
+fn parse_dosbox_conf(source: &dyn Source, depth: usize) -> String {
+    let v = get_redirect_env(source, EV_DOSBOX_CONF, depth);
     if v.is_empty() {
         DEFAULT_DOSBOX_CONF.to_string()
     } else {
@@ -63,32 +221,93 @@ fn parse_dosbox_conf() -> String {
     }
 }
 
-fn parse_basic() -> (PathBuf, BasicMode) {
-    let non_canonic =
-        parse_non_canonic().expect("Please specify the location of the basic interpreter");
-    let exe = match fs::canonicalize(&non_canonic.0) {
+fn parse_basic(
+    source: &dyn Source,
+    depth: usize,
+    dialect: Option<Dialect>,
+) -> (PathBuf, BasicMode, String) {
+    let non_canonic = parse_non_canonic(source, depth, dialect);
+    let exe = match source.canonicalize(Path::new(&non_canonic.0)) {
         Ok(p) => p,
         Err(e) => panic!("Could not find interpreter {}: {}", &non_canonic.0, e),
     };
 
-    (exe, non_canonic.1)
+    (exe, non_canonic.1, non_canonic.2)
 }
 
-fn parse_non_canonic() -> Option<(String, BasicMode)> {
-    if is_explicit_qb() {
-        parse_qbasic()
-    } else {
-        parse_gwbasic().or_else(parse_qbasic)
+/// Picks the interpreter for the given dialect.
+///
+/// An explicitly requested dialect must have its interpreter configured.
+/// A detected dialect falls back to whichever interpreter is configured.
+fn parse_non_canonic(
+    source: &dyn Source,
+    depth: usize,
+    dialect: Option<Dialect>,
+) -> (String, BasicMode, String) {
+    match dialect {
+        Some(d) => match parse_interpreter(source, depth, d.mode) {
+            Some(x) => (x.0, x.1, d.reason),
+            None if d.explicit => panic!(
+                "Please specify the location of the {:?} interpreter ({})",
+                d.mode,
+                interpreter_env(d.mode)
+            ),
+            None => {
+                let x = parse_any_interpreter(source, depth);
+                let reason = format!("{}, but {} is not set", d.reason, interpreter_env(d.mode));
+                (x.0, x.1, reason)
+            }
+        },
+        None => {
+            let x = parse_any_interpreter(source, depth);
+            let reason = "could not detect the dialect, using the configured interpreter";
+            (x.0, x.1, reason.to_string())
+        }
+    }
+}
+
+/// Decides the dialect of the program. `BLR_BASIC_MODE` wins over anything
+/// found in the program itself.
+fn parse_dialect(source: &dyn Source, depth: usize, program: &Path) -> Option<Dialect> {
+    let v = get_redirect_env(source, EV_BASIC_MODE, depth);
+    match dialect::parse_mode(&v) {
+        Some(mode) => Some(Dialect::explicit(
+            mode,
+            format!("{} is {}", EV_BASIC_MODE, v),
+        )),
+        None => source
+            .read(program)
+            .ok()
+            .and_then(|contents| dialect::detect(&contents)),
+    }
+}
+
+fn interpreter_env(mode: BasicMode) -> &'static str {
+    match mode {
+        BasicMode::GWBasic => EV_GWBASIC,
+        BasicMode::QBasic => EV_QBASIC,
     }
 }
 
-fn is_explicit_qb() -> bool {
-    let v = get_redirect_env(EV_BASIC_MODE);
-    v == "qbasic"
+fn parse_interpreter(
+    source: &dyn Source,
+    depth: usize,
+    mode: BasicMode,
+) -> Option<(String, BasicMode)> {
+    match mode {
+        BasicMode::GWBasic => parse_gwbasic(source, depth),
+        BasicMode::QBasic => parse_qbasic(source, depth),
+    }
+}
+
+fn parse_any_interpreter(source: &dyn Source, depth: usize) -> (String, BasicMode) {
+    parse_gwbasic(source, depth)
+        .or_else(|| parse_qbasic(source, depth))
+        .expect("Please specify the location of the basic interpreter")
 }
 
-fn parse_gwbasic() -> Option<(String, BasicMode)> {
-    let gwbasic = get_redirect_env(EV_GWBASIC);
+fn parse_gwbasic(source: &dyn Source, depth: usize) -> Option<(String, BasicMode)> {
+    let gwbasic = get_redirect_env(source, EV_GWBASIC, depth);
     if gwbasic.is_empty() {
         None
     } else {
@@ -96,8 +315,8 @@ fn parse_gwbasic() -> Option<(String, BasicMode)> {
     }
 }
 
-fn parse_qbasic() -> Option<(String, BasicMode)> {
-    let qbasic = get_redirect_env(EV_QBASIC);
+fn parse_qbasic(source: &dyn Source, depth: usize) -> Option<(String, BasicMode)> {
+    let qbasic = get_redirect_env(source, EV_QBASIC, depth);
     if qbasic.is_empty() {
         None
     } else {
@@ -105,137 +324,676 @@ fn parse_qbasic() -> Option<(String, BasicMode)> {
     }
 }
 
-fn parse_needs_stdin(args: &Vec<String>) -> bool {
-    !env::var("CONTENT_LENGTH").unwrap_or_default().is_empty() || args.contains(&"-i".to_owned())
+fn parse_needs_stdin(source: &dyn Source, command_line: &CommandLine) -> bool {
+    !source.var("CONTENT_LENGTH").unwrap_or_default().is_empty() || command_line.stdin_flag
 }
 
-fn parse_program(args: &Vec<String>) -> PathBuf {
-    let program: String = if !args.is_empty() {
-        args[0].to_string()
-    } else {
-        get_redirect_env(EV_PROGRAM)
+fn parse_program(source: &dyn Source, depth: usize, command_line: &CommandLine) -> PathBuf {
+    let program: String = match &command_line.program {
+        Some(p) => p.to_string(),
+        None => get_redirect_env(source, EV_PROGRAM, depth),
     };
     if program.is_empty() {
         panic!("Please specify the basic program to run");
     }
-    match fs::canonicalize(&program) {
+    let program = match source.canonicalize(Path::new(&program)) {
         Ok(p) => p,
         Err(e) => panic!("Could not find BASIC file {}: {}", &program, e),
+    };
+    let name = program.file_name().unwrap().to_string_lossy();
+    if !dos_names::is_valid_8_3(&name) {
+        panic!(
+            "The BASIC file name {} is not a valid DOS name: it needs up to 8 characters, optionally followed by a dot and up to 3 characters, without spaces. Rename it e.g. to {}",
+            name,
+            dos_names::suggest_8_3(&name)
+        );
+    }
+    program
+}
+
+/// The arguments are written one per line in the arguments file,
+/// so they can't contain line breaks.
+fn parse_program_args(command_line: &CommandLine) -> Vec<String> {
+    for arg in &command_line.program_args {
+        if arg.contains(['\r', '\n']) {
+            panic!("Program argument {:?} contains a line break", arg);
+        }
+    }
+    command_line.program_args.clone()
+}
+
+fn parse_cleanup(source: &dyn Source, depth: usize) -> bool {
+    get_redirect_env(source, EV_NO_CLEANUP, depth).is_empty()
+}
+
+fn parse_check(source: &dyn Source, depth: usize) -> bool {
+    !get_redirect_env(source, EV_CHECK, depth).is_empty()
+}
+
+fn parse_dir(source: &dyn Source, depth: usize, key: &str) -> Option<PathBuf> {
+    let dir = get_redirect_env(source, key, depth);
+    if dir.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(dir))
     }
 }
 
-fn parse_cleanup() -> bool {
-    get_redirect_env(EV_NO_CLEANUP).is_empty()
+/// The logging options, parsed before the rest so that problems with the
+/// other options are logged too: the level from `BLR_LOG` and the file from
+/// `BLR_LOG_FILE`, stderr if there is none.
+pub fn parse_log_options(source: &dyn Source) -> (Level, Option<PathBuf>) {
+    let depth = parse_redirect_depth(source);
+    let v = get_redirect_env(source, EV_LOG, depth);
+    let level = match logging::parse_level(&v) {
+        Some(level) => level,
+        None => panic!(
+            "{} must be one of off, error, warn, info, debug, not {}",
+            EV_LOG, v
+        ),
+    };
+    (level, parse_dir(source, depth, EV_LOG_FILE))
+}
+
+/// The timeout in seconds, e.g. `30` or `0.5`.
+fn parse_timeout(source: &dyn Source, depth: usize) -> Option<Duration> {
+    let v = get_redirect_env(source, EV_TIMEOUT, depth);
+    if v.is_empty() {
+        return None;
+    }
+    match v.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+            Some(Duration::from_secs_f64(seconds))
+        }
+        _ => panic!(
+            "{} must be a positive number of seconds, not {}",
+            EV_TIMEOUT, v
+        ),
+    }
+}
+
+/// Uses the ID of Apache's mod_unique_id or the `X-Request-Id` request header
+/// if there is one, so that the launch can be matched with the server logs.
+/// Like the other variables, both are taken from their `REDIRECT_` variant if
+/// there is one. Characters that don't belong in a file name are replaced.
+fn parse_request_id(source: &dyn Source, depth: usize) -> String {
+    let request_id = ["UNIQUE_ID", "HTTP_X_REQUEST_ID"]
+        .iter()
+        .map(|key| get_redirect_env(source, key, depth))
+        .find(|id| !id.is_empty())
+        .unwrap_or_else(|| rand_file::make_random_name(REQUEST_ID_LENGTH));
+    request_id
+        .chars()
+        .map(|ch| match ch {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '@' => ch,
+            _ => '_',
+        })
+        .collect()
 }
 
 /// Gets the value of an environment variable, taking into account
-/// Apache's REDIRECT variable conventions.
-///
-/// When requesting variable ABC, the function will try to find a
-/// redirected variable REDIRECT_ABC as well as its grandparent
-/// REDIRECT_REDIRECT_ABC. The highest defined variable wins (even if empty).
-fn get_redirect_env(key: &str) -> String {
+/// Apache's REDIRECT variable conventions, up to `depth` levels.
+fn get_redirect_env(source: &dyn Source, key: &str, depth: usize) -> String {
     if key.is_empty() {
         panic!("Environment variable name was empty");
     }
 
-    _get_redirect_env(key, 0, 2).unwrap_or_default()
+    resolve_redirect_env(source, key, depth)
+        .map(|v| v.value)
+        .unwrap_or_default()
+}
+
+/// The number of `REDIRECT_` levels, itself resolved with the default depth.
+fn parse_redirect_depth(source: &dyn Source) -> usize {
+    let v = resolve_redirect_env(source, EV_REDIRECT_DEPTH, DEFAULT_REDIRECT_DEPTH)
+        .map(|v| v.value)
+        .unwrap_or_default();
+    if v.is_empty() {
+        return DEFAULT_REDIRECT_DEPTH;
+    }
+    match v.parse::<usize>() {
+        Ok(depth) if depth <= MAX_REDIRECT_DEPTH => depth,
+        _ => panic!(
+            "{} must be a number from 0 to {}, not {}",
+            EV_REDIRECT_DEPTH, MAX_REDIRECT_DEPTH, v
+        ),
+    }
 }
 
-fn _get_redirect_env(key: &str, depth: u8, max_depth: u8) -> Option<String> {
-    if depth < max_depth {
+/// Finds the variant of an environment variable to use.
+///
+/// When requesting variable ABC with a depth of 2, the function will try to
+/// find a redirected variable REDIRECT_ABC as well as its grandparent
+/// REDIRECT_REDIRECT_ABC. The highest defined variable wins (even if empty),
+/// as it belongs to the request that the client sent.
+pub fn resolve_redirect_env(source: &dyn Source, key: &str, depth: usize) -> Option<RedirectVar> {
+    if depth > 0 {
         let parent_key = format!("REDIRECT_{}", key);
-        let parent_result = _get_redirect_env(&parent_key, depth + 1, max_depth);
-        match parent_result {
-            Some(_) => parent_result,
-            _ => _env_var_to_option(key),
+        let parent_result = resolve_redirect_env(source, &parent_key, depth - 1);
+        if parent_result.is_some() {
+            return parent_result;
         }
-    } else {
-        _env_var_to_option(key)
     }
+    _env_var_to_option(source, key).map(|value| RedirectVar {
+        key: key.to_string(),
+        value,
+    })
 }
 
-fn _env_var_to_option(key: &str) -> Option<String> {
-    let e = env::var(key);
-    match e {
-        Ok(v) => Some(v),
-        _ => None,
+/// Describes which variant of a variable is used, for `--explain`, e.g.
+/// `QUERY_STRING=id=1 (from REDIRECT_QUERY_STRING)`. Variants that are
+/// ignored because they are deeper than the redirect depth are mentioned.
+/// Returns `None` if no variant is set.
+pub fn describe_variable(source: &dyn Source, key: &str, depth: usize) -> Option<String> {
+    let used = resolve_redirect_env(source, key, depth);
+    let deepest = resolve_redirect_env(source, key, MAX_REDIRECT_DEPTH);
+    let mut result = match &used {
+        Some(v) if v.key == key => format!("{}={}", key, v.value),
+        Some(v) => format!("{}={} (from {})", key, v.value, v.key),
+        None => format!("{} is not set", key),
+    };
+    match deepest {
+        Some(d) if used.as_ref().map(|v| &v.key) != Some(&d.key) => result.push_str(&format!(
+            ", ignoring {}={} as it is deeper than {}",
+            d.key, d.value, EV_REDIRECT_DEPTH
+        )),
+        _ if used.is_none() => return None,
+        _ => {}
     }
+    Some(result)
+}
+
+fn _env_var_to_option(source: &dyn Source, key: &str) -> Option<String> {
+    source.var(key)
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use std::collections::HashMap;
+    use std::path::Component;
+
+    /// The directory that relative paths are resolved against.
+    const CWD: &str = "/work/basic";
+    const GWBASIC: &str = "/work/bin/GWBASIC.EXE";
+    const QBASIC: &str = "/work/bin/QBASIC.EXE";
+
+    type Vars<'a> = &'a [(&'a str, &'a str)];
+    type Check = fn(&Options) -> bool;
+
+    /// Fixed environment variables and files, so that the tests don't depend
+    /// on the process environment, the platform or each other.
+    #[derive(Default)]
+    pub(crate) struct FakeSource {
+        vars: HashMap<String, String>,
+        files: HashMap<PathBuf, Vec<u8>>,
+    }
+
+    impl FakeSource {
+        pub(crate) fn new(vars: &[(&str, &str)]) -> Self {
+            Self {
+                vars: vars
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                files: HashMap::new(),
+            }
+        }
+
+        fn with_file(mut self, path: &str, contents: &str) -> Self {
+            self.files
+                .insert(PathBuf::from(path), contents.as_bytes().to_vec());
+            self
+        }
+
+        /// The interpreters and a GW-Basic program in the current directory.
+        fn with_installation(self) -> Self {
+            self.with_file(GWBASIC, "")
+                .with_file(QBASIC, "")
+                .with_file("/work/basic/HELLO.BAS", "10 PRINT 1\n20 SYSTEM\n")
+        }
+    }
+
+    impl Source for FakeSource {
+        fn var(&self, key: &str) -> Option<String> {
+            self.vars.get(key).cloned()
+        }
+
+        fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+            let mut result = PathBuf::new();
+            for component in Path::new(CWD).join(path).components() {
+                match component {
+                    Component::ParentDir => {
+                        result.pop();
+                    }
+                    Component::CurDir => {}
+                    c => result.push(c),
+                }
+            }
+            if self.files.contains_key(&result) {
+                Ok(result)
+            } else {
+                Err(io::ErrorKind::NotFound.into())
+            }
+        }
+
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+    }
+
+    fn parse(source: &FakeSource, args: &[&str]) -> Options {
+        parse_options_from(source, &to_args(args))
+    }
 
     #[test]
     #[should_panic]
     fn test_get_redirect_env_with_empty_key_should_panic() {
-        get_redirect_env("");
+        get_redirect_env(&FakeSource::default(), "", DEFAULT_REDIRECT_DEPTH);
+    }
+
+    #[test]
+    fn test_get_redirect_env() {
+        let cases: [(Vars, &str); 8] = [
+            (&[], ""),
+            (&[("ABC", "base")], "base"),
+            (&[("ABC", "base"), ("REDIRECT_ABC", "parent")], "parent"),
+            (&[("REDIRECT_ABC", "parent")], "parent"),
+            (
+                &[
+                    ("ABC", "base"),
+                    ("REDIRECT_ABC", "parent"),
+                    ("REDIRECT_REDIRECT_ABC", "winner"),
+                ],
+                "winner",
+            ),
+            (&[("REDIRECT_REDIRECT_ABC", "winner")], "winner"),
+            // the highest defined variable wins, even if it is empty
+            (&[("ABC", "base"), ("REDIRECT_ABC", "")], ""),
+            // deeper redirects are not considered
+            (
+                &[("ABC", "base"), ("REDIRECT_REDIRECT_REDIRECT_ABC", "deep")],
+                "base",
+            ),
+        ];
+        for (vars, expected) in cases {
+            let result = get_redirect_env(&FakeSource::new(vars), "ABC", DEFAULT_REDIRECT_DEPTH);
+            assert_eq!(result, expected, "{:?}", vars);
+        }
+    }
+
+    #[test]
+    fn test_get_redirect_env_with_depth() {
+        let vars: Vars = &[
+            ("ABC", "base"),
+            ("REDIRECT_ABC", "1"),
+            ("REDIRECT_REDIRECT_ABC", "2"),
+            ("REDIRECT_REDIRECT_REDIRECT_ABC", "3"),
+        ];
+        let cases = [
+            ("0", "base"),
+            ("1", "1"),
+            ("2", "2"),
+            ("3", "3"),
+            ("10", "3"),
+        ];
+        for (depth, expected) in cases {
+            let mut vars = vars.to_vec();
+            vars.push((EV_REDIRECT_DEPTH, depth));
+            let source = FakeSource::new(&vars);
+            let result = get_redirect_env(&source, "ABC", parse_redirect_depth(&source));
+            assert_eq!(result, expected, "depth {}", depth);
+        }
     }
 
     #[test]
-    fn test_get_redirect_env_highest_redirect_wins() {
-        // arrange
-        env::set_var("ABC", "whatever");
-        env::set_var("REDIRECT_ABC", "something");
-        env::set_var("REDIRECT_REDIRECT_ABC", "winner");
+    fn test_redirect_depth() {
+        let cases: [(Vars, usize); 4] = [
+            (&[], DEFAULT_REDIRECT_DEPTH),
+            (&[(EV_REDIRECT_DEPTH, "")], DEFAULT_REDIRECT_DEPTH),
+            (&[(EV_REDIRECT_DEPTH, "5")], 5),
+            (&[("REDIRECT_BLR_REDIRECT_DEPTH", "0")], 0),
+        ];
+        for (vars, expected) in cases {
+            assert_eq!(parse_redirect_depth(&FakeSource::new(vars)), expected);
+        }
+    }
 
-        // act
-        let result = get_redirect_env("ABC");
+    #[test]
+    #[should_panic(expected = "BLR_REDIRECT_DEPTH must be a number from 0 to 10")]
+    fn test_redirect_depth_too_deep_should_panic() {
+        parse_redirect_depth(&FakeSource::new(&[(EV_REDIRECT_DEPTH, "11")]));
+    }
 
-        // cleanup
-        env::remove_var("REDIRECT_REDIRECT_ABC");
-        env::remove_var("REDIRECT_ABC");
-        env::remove_var("ABC");
+    #[test]
+    fn test_describe_variable() {
+        let source = FakeSource::new(&[
+            ("A", "a"),
+            ("REDIRECT_B", "b"),
+            ("C", "c"),
+            ("REDIRECT_REDIRECT_REDIRECT_C", "deep"),
+            ("REDIRECT_REDIRECT_REDIRECT_D", "deep"),
+        ]);
+        let cases = [
+            ("A", Some("A=a")),
+            ("B", Some("B=b (from REDIRECT_B)")),
+            (
+                "C",
+                Some("C=c, ignoring REDIRECT_REDIRECT_REDIRECT_C=deep as it is deeper than BLR_REDIRECT_DEPTH"),
+            ),
+            (
+                "D",
+                Some("D is not set, ignoring REDIRECT_REDIRECT_REDIRECT_D=deep as it is deeper than BLR_REDIRECT_DEPTH"),
+            ),
+            ("E", None),
+        ];
+        for (key, expected) in cases {
+            assert_eq!(
+                describe_variable(&source, key, 2).as_deref(),
+                expected,
+                "{}",
+                key
+            );
+        }
+    }
 
-        // assert
-        assert_eq!(result, "winner");
+    #[test]
+    fn test_defaults() {
+        let source = FakeSource::new(&[(EV_GWBASIC, GWBASIC)]).with_installation();
+        let options = parse(&source, &["HELLO.BAS"]);
+        assert_eq!(options.dosbox, DEFAULT_DOSBOX);
+        assert_eq!(options.dosbox_conf, DEFAULT_DOSBOX_CONF);
+        assert_eq!(options.basic, PathBuf::from(GWBASIC));
+        assert_eq!(options.mode, BasicMode::GWBasic);
+        assert_eq!(options.program, PathBuf::from("/work/basic/HELLO.BAS"));
+        assert!(options.program_args.is_empty());
+        assert!(!options.needs_stdin);
+        assert!(options.cleanup);
+        assert!(!options.check);
+        assert_eq!(options.debug_dir, None);
+        assert_eq!(options.record_dir, None);
+        assert_eq!(options.timeout, None);
+        assert_eq!(options.redirect_depth, DEFAULT_REDIRECT_DEPTH);
+        assert!(!options.explain);
+        assert!(!options.server_timing);
+        assert_eq!(options.request_id.len(), REQUEST_ID_LENGTH);
     }
 
     #[test]
-    fn test_get_redirect_env_no_base_variable() {
-        // arrange
-        env::remove_var("ABC");
-        env::set_var("REDIRECT_ABC", "something");
-        env::remove_var("REDIRECT_REDIRECT_ABC");
+    fn test_settings() {
+        let cases: [(&str, &str, Check); 17] = [
+            (EV_DOSBOX, "dosbox", |o| o.dosbox == "dosbox"),
+            ("REDIRECT_BLR_DOSBOX", "dosbox", |o| o.dosbox == "dosbox"),
+            (EV_DOSBOX_CONF, "my.conf", |o| o.dosbox_conf == "my.conf"),
+            (EV_NO_CLEANUP, "1", |o| !o.cleanup),
+            (EV_CHECK, "1", |o| o.check),
+            (EV_DEBUG_DIR, "/debug", |o| {
+                o.debug_dir == Some(PathBuf::from("/debug"))
+            }),
+            (EV_RECORD_DIR, "/rec", |o| {
+                o.record_dir == Some(PathBuf::from("/rec"))
+            }),
+            (EV_TIMEOUT, "0.5", |o| {
+                o.timeout == Some(Duration::from_millis(500))
+            }),
+            (EV_TIMEOUT, "30", |o| {
+                o.timeout == Some(Duration::from_secs(30))
+            }),
+            ("CONTENT_LENGTH", "5", |o| o.needs_stdin),
+            (EV_REDIRECT_DEPTH, "4", |o| o.redirect_depth == 4),
+            (EV_EXPLAIN, "1", |o| o.explain),
+            (EV_SERVER_TIMING, "1", |o| o.server_timing),
+            ("CONTENT_LENGTH", "", |o| !o.needs_stdin),
+            ("UNIQUE_ID", "Zm9v/YmFy+", |o| o.request_id == "Zm9v_YmFy_"),
+            ("HTTP_X_REQUEST_ID", "abc-123", |o| {
+                o.request_id == "abc-123"
+            }),
+            ("REDIRECT_HTTP_X_REQUEST_ID", "abc-456", |o| {
+                o.request_id == "abc-456"
+            }),
+        ];
+        for (key, value, check) in cases {
+            let source =
+                FakeSource::new(&[(EV_GWBASIC, GWBASIC), (key, value)]).with_installation();
+            let options = parse(&source, &["HELLO.BAS"]);
+            assert!(check(&options), "{}={}: {:?}", key, value, options);
+        }
+    }
 
-        // act
-        let result = get_redirect_env("ABC");
+    #[test]
+    fn test_parse_log_options() {
+        let cases: [(Vars, Level, Option<&str>); 4] = [
+            (&[], Level::Off, None),
+            (&[(EV_LOG, "info")], Level::Info, None),
+            (
+                &[("REDIRECT_BLR_LOG", "debug"), (EV_LOG_FILE, "/log/blr.log")],
+                Level::Debug,
+                Some("/log/blr.log"),
+            ),
+            (&[(EV_LOG, "Warn"), (EV_LOG_FILE, "")], Level::Warn, None),
+        ];
+        for (vars, level, file) in cases {
+            assert_eq!(
+                parse_log_options(&FakeSource::new(vars)),
+                (level, file.map(PathBuf::from)),
+                "{:?}",
+                vars
+            );
+        }
+    }
 
-        // cleanup
-        env::remove_var("REDIRECT_ABC");
+    #[test]
+    #[should_panic(expected = "BLR_LOG must be one of")]
+    fn test_invalid_log_level_should_panic() {
+        parse_log_options(&FakeSource::new(&[(EV_LOG, "loud")]));
+    }
 
-        // assert
-        assert_eq!(result, "something");
+    #[test]
+    fn test_program_from_env() {
+        let source = FakeSource::new(&[
+            (EV_GWBASIC, GWBASIC),
+            ("REDIRECT_BLR_PROGRAM", "../basic/HELLO.BAS"),
+        ])
+        .with_installation();
+        let options = parse(&source, &[]);
+        assert_eq!(options.program, PathBuf::from("/work/basic/HELLO.BAS"));
     }
 
     #[test]
-    fn test_parse_dosbox_without_env() {
-        env::remove_var(EV_DOSBOX);
-        let dos_box = parse_dosbox();
-        assert_eq!(dos_box, DEFAULT_DOSBOX);
+    fn test_program_args_and_stdin_flag() {
+        let source = FakeSource::new(&[(EV_GWBASIC, GWBASIC)]).with_installation();
+        let options = parse(&source, &["-i", "HELLO.BAS", "--", "-i", "b c"]);
+        assert!(options.needs_stdin);
+        assert_eq!(options.program_args, to_args(&["-i", "b c"]));
     }
 
     #[test]
-    fn test_parse_dosbox_with_env() {
-        env::set_var(EV_DOSBOX, "dosbox");
-        let dos_box = parse_dosbox();
-        env::remove_var(EV_DOSBOX);
-        assert_eq!(dos_box, "dosbox");
+    fn test_mode_precedence() {
+        const NUMBERED: &str = "10 PRINT 1\n20 SYSTEM\n";
+        const UNNUMBERED: &str = "PRINT 1\nSYSTEM\n";
+        const EMPTY: &str = "";
+        const QBASIC_ONLY: &str = "SUB Hello\nEND SUB\n";
+        const HEADER: &str = "' blr: qbasic\nPRINT 1\n";
+        let both: Vars = &[(EV_GWBASIC, GWBASIC), (EV_QBASIC, QBASIC)];
+        let cases: [(Vars, &str, BasicMode, &str); 11] = [
+            (both, NUMBERED, BasicMode::GWBasic, GWBASIC),
+            (both, QBASIC_ONLY, BasicMode::QBasic, QBASIC),
+            (both, HEADER, BasicMode::QBasic, QBASIC),
+            (both, UNNUMBERED, BasicMode::QBasic, QBASIC),
+            // undetected dialects use the GW-Basic interpreter first
+            (both, EMPTY, BasicMode::GWBasic, GWBASIC),
+            (&[(EV_QBASIC, QBASIC)], EMPTY, BasicMode::QBasic, QBASIC),
+            // a detected dialect falls back to the configured interpreter
+            (
+                &[(EV_GWBASIC, GWBASIC)],
+                QBASIC_ONLY,
+                BasicMode::GWBasic,
+                GWBASIC,
+            ),
+            (&[(EV_QBASIC, QBASIC)], NUMBERED, BasicMode::QBasic, QBASIC),
+            // BLR_BASIC_MODE wins over the program
+            (
+                &[
+                    (EV_GWBASIC, GWBASIC),
+                    (EV_QBASIC, QBASIC),
+                    (EV_BASIC_MODE, "qbasic"),
+                ],
+                NUMBERED,
+                BasicMode::QBasic,
+                QBASIC,
+            ),
+            (
+                &[
+                    (EV_GWBASIC, GWBASIC),
+                    (EV_QBASIC, QBASIC),
+                    (EV_BASIC_MODE, "GWBasic"),
+                ],
+                HEADER,
+                BasicMode::GWBasic,
+                GWBASIC,
+            ),
+            // and so does its redirected variant
+            (
+                &[
+                    (EV_GWBASIC, GWBASIC),
+                    (EV_QBASIC, QBASIC),
+                    (EV_BASIC_MODE, "gwbasic"),
+                    ("REDIRECT_BLR_BASIC_MODE", "qbasic"),
+                ],
+                NUMBERED,
+                BasicMode::QBasic,
+                QBASIC,
+            ),
+        ];
+        for (vars, program, mode, interpreter) in cases {
+            let source = FakeSource::new(vars)
+                .with_file(GWBASIC, "")
+                .with_file(QBASIC, "")
+                .with_file("/work/basic/PROGRAM.BAS", program);
+            let options = parse(&source, &["PROGRAM.BAS"]);
+            assert_eq!(options.mode, mode, "{:?} {:?}", vars, program);
+            assert_eq!(
+                options.basic,
+                PathBuf::from(interpreter),
+                "{:?} {:?}",
+                vars,
+                program
+            );
+        }
     }
 
     #[test]
     fn test_parse_basic_gwbasic() {
-        env::set_var(EV_GWBASIC, "..\\bin\\GWBASIC.EXE");
-        let b = parse_basic();
-        env::remove_var(EV_GWBASIC);
-        assert_eq!(
-            b.0.display().to_string(),
-            "\\\\?\\C:\\Users\\ngeor\\Projects\\github\\dockerfiles\\basic\\bin\\GWBASIC.EXE"
+        let source = FakeSource::new(&[(EV_GWBASIC, "../bin/GWBASIC.EXE")]).with_file(GWBASIC, "");
+        let b = parse_basic(&source, DEFAULT_REDIRECT_DEPTH, None);
+        assert_eq!(b.0, PathBuf::from(GWBASIC));
+        assert!(matches!(b.1, BasicMode::GWBasic));
+    }
+
+    #[test]
+    #[should_panic(expected = "Please specify the location of the QBasic interpreter")]
+    fn test_explicit_mode_without_interpreter_should_panic() {
+        let source = FakeSource::new(&[(EV_GWBASIC, GWBASIC), (EV_BASIC_MODE, "qbasic")])
+            .with_installation();
+        parse(&source, &["HELLO.BAS"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Please specify the location of the basic interpreter")]
+    fn test_no_interpreter_should_panic() {
+        parse(&FakeSource::new(&[]).with_installation(), &["HELLO.BAS"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not find interpreter")]
+    fn test_missing_interpreter_should_panic() {
+        let source = FakeSource::new(&[(EV_GWBASIC, "/nowhere/GWBASIC.EXE")]).with_installation();
+        parse(&source, &["HELLO.BAS"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "BLR_REDIRECT_DEPTH must be a number from 0 to 10, not x")]
+    fn test_invalid_redirect_depth_is_reported_before_other_options() {
+        // without a program, which would be reported otherwise
+        parse(
+            &FakeSource::new(&[(EV_GWBASIC, GWBASIC), (EV_REDIRECT_DEPTH, "x")])
+                .with_installation(),
+            &[],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Please specify the basic program to run")]
+    fn test_no_program_should_panic() {
+        parse(
+            &FakeSource::new(&[(EV_GWBASIC, GWBASIC)]).with_installation(),
+            &[],
         );
-        assert!(match b.1 {
-            BasicMode::GWBasic => true,
-            _ => false,
-        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Rename it e.g. to HELLOWOR.BAS")]
+    fn test_program_with_long_name_should_panic() {
+        let source = FakeSource::new(&[(EV_GWBASIC, GWBASIC)])
+            .with_installation()
+            .with_file("/work/basic/hello world.bas", "10 SYSTEM\n");
+        parse(&source, &["hello world.bas"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be a positive number of seconds")]
+    fn test_invalid_timeout_should_panic() {
+        let source =
+            FakeSource::new(&[(EV_GWBASIC, GWBASIC), (EV_TIMEOUT, "soon")]).with_installation();
+        parse(&source, &["HELLO.BAS"]);
+    }
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_command_line_program_only() {
+        let command_line = parse_command_line(&to_args(&["HELLO.BAS"]));
+        assert_eq!(command_line.program, Some("HELLO.BAS".to_string()));
+        assert!(!command_line.stdin_flag);
+        assert!(command_line.program_args.is_empty());
+    }
+
+    #[test]
+    fn test_parse_command_line_program_args() {
+        let command_line = parse_command_line(&to_args(&["-i", "HELLO.BAS", "a", "b c"]));
+        assert_eq!(command_line.program, Some("HELLO.BAS".to_string()));
+        assert!(command_line.stdin_flag);
+        assert_eq!(command_line.program_args, to_args(&["a", "b c"]));
+    }
+
+    #[test]
+    fn test_parse_command_line_legacy_stdin_flag() {
+        let command_line = parse_command_line(&to_args(&["HELLO.BAS", "-i", "a"]));
+        assert!(command_line.stdin_flag);
+        assert_eq!(command_line.program_args, to_args(&["a"]));
+    }
+
+    #[test]
+    fn test_parse_command_line_separator() {
+        let command_line = parse_command_line(&to_args(&["HELLO.BAS", "--", "-i"]));
+        assert!(!command_line.stdin_flag);
+        assert_eq!(command_line.program_args, to_args(&["-i"]));
+    }
+
+    #[test]
+    fn test_parse_command_line_explain() {
+        let command_line = parse_command_line(&to_args(&["--explain", "HELLO.BAS", "--explain"]));
+        assert!(command_line.explain_flag);
+        assert_eq!(command_line.program_args, to_args(&["--explain"]));
+    }
+
+    #[test]
+    fn test_parse_command_line_empty() {
+        assert_eq!(parse_command_line(&[]), CommandLine::default());
     }
 }
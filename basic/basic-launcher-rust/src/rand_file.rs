@@ -1,28 +1,125 @@
 use rand::prelude::*;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
 use std::path::Path;
 use std::path::PathBuf;
 
-pub fn make_unique_random_filename(parent: &Path, extension: &str) -> PathBuf {
-    loop {
-        let mut result: PathBuf = parent.to_path_buf();
-        let mut filename: String = make_random_filename();
-        filename.push('.');
-        filename.push_str(extension);
-        result.push(filename);
-        if !result.exists() {
-            return result;
+/// Characters of the random names. Upper case only, as DOS would convert them anyway.
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// Length of the random names, the most that a DOS 8.3 name allows.
+const NAME_LENGTH: usize = 8;
+
+/// How many random names to try before giving up.
+const MAX_ATTEMPTS: u32 = 100;
+
+/// Creates one empty file per extension, all sharing the same random name,
+/// e.g. `K3X9QZ0A.BAT` and `K3X9QZ0A.INP`.
+///
+/// Every file is created with create-new semantics, so two concurrent
+/// launchers never get the same file. If any file of the group already exists,
+/// the files created so far are removed and another name is tried. This way a
+/// group is only handed out when all of its files belong to the caller.
+pub fn create_unique_random_files(parent: &Path, extensions: &[&str]) -> io::Result<Vec<PathBuf>> {
+    for _ in 0..MAX_ATTEMPTS {
+        if let Some(files) = create_group(parent, &make_random_filename(), extensions)? {
+            return Ok(files);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!(
+            "Could not find an unused random file name in {} after {} attempts",
+            parent.display(),
+            MAX_ATTEMPTS
+        ),
+    ))
+}
+
+/// Creates the files of the group, returning `None` if one of them exists.
+fn create_group(
+    parent: &Path,
+    name: &str,
+    extensions: &[&str],
+) -> io::Result<Option<Vec<PathBuf>>> {
+    let mut created: Vec<PathBuf> = vec![];
+    for extension in extensions {
+        let file = parent.join(format!("{}.{}", name, extension));
+        match OpenOptions::new().write(true).create_new(true).open(&file) {
+            Ok(_) => created.push(file),
+            Err(e) => {
+                for f in &created {
+                    fs::remove_file(f)?;
+                }
+                return if e.kind() == io::ErrorKind::AlreadyExists {
+                    Ok(None)
+                } else {
+                    Err(e)
+                };
+            }
         }
     }
+    Ok(Some(created))
 }
 
 fn make_random_filename() -> String {
+    make_random_name(NAME_LENGTH)
+}
+
+/// Makes a random name of upper case letters and digits.
+pub fn make_random_name(length: usize) -> String {
     let mut rng = rand::thread_rng();
-    let letters: Vec<char> = "ABCDEFGHIJKLMNOPQRSTUVWXYZ".chars().collect();
-    let mut result: String = String::new();
-    for _ in 0..8 {
-        let rng_usize: usize = rng.gen::<usize>();
-        let letter_index: usize = rng_usize % letters.len();
-        result.push(letters[letter_index]);
+    (0..length)
+        .map(|_| ALPHABET[rng.gen_range(0, ALPHABET.len())] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dos_names::is_valid_8_3;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("blr-rand-file-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_create_unique_random_files() {
+        let dir = test_dir("group");
+        let files = create_unique_random_files(&dir, &["BAT", "INP", "OUT"]).unwrap();
+        assert_eq!(files.len(), 3);
+        let stem = files[0].file_stem().unwrap();
+        for f in &files {
+            assert!(f.is_file());
+            assert_eq!(f.file_stem().unwrap(), stem);
+            assert!(is_valid_8_3(f.file_name().unwrap().to_str().unwrap()));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_create_group_collision_rolls_back() {
+        let dir = test_dir("collision");
+        fs::write(dir.join("ABCD1234.OUT"), "taken").unwrap();
+        let result = create_group(&dir, "ABCD1234", &["BAT", "INP", "OUT"]).unwrap();
+        assert!(result.is_none());
+        assert!(!dir.join("ABCD1234.BAT").exists());
+        assert!(!dir.join("ABCD1234.INP").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("ABCD1234.OUT")).unwrap(),
+            "taken"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_make_random_filename() {
+        let name = make_random_filename();
+        assert_eq!(name.len(), NAME_LENGTH);
+        assert!(name.bytes().all(|b| ALPHABET.contains(&b)));
     }
-    return result;
 }
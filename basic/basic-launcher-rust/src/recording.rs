@@ -0,0 +1,346 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::SystemTime;
+
+use crate::batch_file::{forwarded_env, is_forwarded_env_key};
+use crate::debug_bundle::bundle_name;
+use crate::diff::diff_lines;
+use crate::options::{Options, ProcessSource};
+use crate::temp_files::TempFiles;
+
+const REQUEST_FILE: &str = "request.txt";
+const STDIN_FILE: &str = "stdin.txt";
+const STDOUT_FILE: &str = "stdout.txt";
+
+/// The launcher decides whether to read stdin based on this variable,
+/// so it is recorded along with the forwarded ones.
+const CONTENT_LENGTH: &str = "CONTENT_LENGTH";
+
+/// Environment variable with the path of the `rusty_basic` interpreter.
+const EV_RUSTY_BASIC: &str = "BLR_RUSTY_BASIC";
+
+/// A launch of a program, as seen by the program: the forwarded environment,
+/// the arguments, stdin and the resulting stdout.
+///
+/// It is saved as a directory with `request.txt` (the program, the arguments
+/// and the environment, one per line), `stdin.txt` and `stdout.txt`.
+#[derive(Debug, PartialEq)]
+pub struct Recording {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub stdin: Vec<u8>,
+    pub stdout: Vec<u8>,
+}
+
+impl Recording {
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let mut request = format!("program: {}\r\n", self.program.display());
+        for arg in &self.args {
+            request.push_str(&format!("arg: {}\r\n", arg));
+        }
+        for (key, value) in &self.env {
+            request.push_str(&format!("env: {}={}\r\n", key, value));
+        }
+        fs::write(dir.join(REQUEST_FILE), request)?;
+        fs::write(dir.join(STDIN_FILE), &self.stdin)?;
+        fs::write(dir.join(STDOUT_FILE), &self.stdout)
+    }
+
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let mut result = Self {
+            program: PathBuf::new(),
+            args: vec![],
+            env: vec![],
+            stdin: fs::read(dir.join(STDIN_FILE))?,
+            stdout: fs::read(dir.join(STDOUT_FILE))?,
+        };
+        for line in fs::read_to_string(dir.join(REQUEST_FILE))?.lines() {
+            match line.split_once(": ") {
+                Some(("program", program)) => result.program = PathBuf::from(program),
+                Some(("arg", arg)) => result.args.push(arg.to_string()),
+                Some(("env", kv)) if kv.contains('=') => {
+                    let (key, value) = kv.split_once('=').unwrap();
+                    result.env.push((key.to_string(), value.to_string()));
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Unexpected line in {}: {}", REQUEST_FILE, line),
+                    ))
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Records the launch into its own directory under `dir`, named like the
+/// debug bundles, with the request body as the client sent it.
+/// Returns the directory of the recording.
+pub fn record(
+    dir: &Path,
+    options: &Options,
+    temp_files: &TempFiles,
+    body: &[u8],
+) -> io::Result<PathBuf> {
+    let mut env = forwarded_env(&ProcessSource, options.redirect_depth);
+    if let Ok(content_length) = env::var(CONTENT_LENGTH) {
+        env.push((CONTENT_LENGTH.to_string(), content_length));
+    }
+    let recording = Recording {
+        program: options.program.clone(),
+        args: options.program_args.clone(),
+        env,
+        stdin: body.to_vec(),
+        stdout: fs::read(&temp_files.stdout_file)?,
+    };
+    let result = dir.join(bundle_name(&options.request_id, SystemTime::now()));
+    recording.save(&result)?;
+    Ok(result)
+}
+
+/// What runs the program during a replay or a test.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// This launcher, i.e. DOSBox with the configured interpreter.
+    Launcher,
+    /// The `rusty_basic` interpreter, which reads stdin directly.
+    RustyBasic,
+}
+
+pub fn parse_backend(value: &str) -> Option<Backend> {
+    match value {
+        "launcher" | "dosbox" => Some(Backend::Launcher),
+        "rusty_basic" => Some(Backend::RustyBasic),
+        _ => None,
+    }
+}
+
+/// Implements the `replay` command:
+/// `replay [--program PROGRAM.BAS] [--backend launcher|rusty_basic] RECORDING...`.
+///
+/// Runs every recording again, with the recorded program unless another one
+/// is given, and prints the differences between the recorded and the new stdout.
+pub fn replay_command(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Usage: basic-launcher-rust replay [--program PROGRAM.BAS] [--backend launcher|rusty_basic] RECORDING...",
+        )
+    };
+    let mut program: Option<PathBuf> = None;
+    let mut backend = Backend::Launcher;
+    let mut recordings: Vec<PathBuf> = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--program" => program = Some(PathBuf::from(iter.next().ok_or_else(usage)?)),
+            "--backend" => {
+                backend = iter
+                    .next()
+                    .and_then(|b| parse_backend(b))
+                    .ok_or_else(usage)?
+            }
+            _ => recordings.push(PathBuf::from(arg)),
+        }
+    }
+    if recordings.is_empty() {
+        return Err(usage());
+    }
+
+    let mut failures = 0;
+    for dir in &recordings {
+        let recording = Recording::load(dir)?;
+        let program = program.clone().unwrap_or_else(|| recording.program.clone());
+        let output = run_backend(
+            backend,
+            &program,
+            &recording.args,
+            &recording.env,
+            &recording.stdin,
+        )?;
+        let diff = diff_lines(
+            &String::from_utf8_lossy(&recording.stdout),
+            &String::from_utf8_lossy(&output.stdout),
+        );
+        match diff {
+            None => println!("ok {}", dir.display()),
+            Some(diff) => {
+                failures += 1;
+                println!("FAILED {}", dir.display());
+                print!("{}", diff);
+                eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            }
+        }
+    }
+    if failures > 0 {
+        Err(io::Error::other(format!(
+            "{} of {} recordings differ",
+            failures,
+            recordings.len()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs a program with the given backend, as if it was a request with the
+/// given environment variables and body. The rest of the current request,
+/// if any, is left out.
+pub fn run_backend(
+    backend: Backend,
+    program: &Path,
+    args: &[String],
+    env: &[(String, String)],
+    stdin: &[u8],
+) -> io::Result<Output> {
+    let command = match backend {
+        Backend::Launcher => launcher_command(&env::current_exe()?, program, args, env),
+        Backend::RustyBasic => {
+            let interpreter = env::var_os(EV_RUSTY_BASIC).unwrap_or_else(|| "rusty_basic".into());
+            rusty_basic_command(&interpreter, program, args, env)
+        }
+    };
+    run_with_stdin(command, stdin)
+}
+
+fn launcher_command(
+    launcher: &Path,
+    program: &Path,
+    args: &[String],
+    env: &[(String, String)],
+) -> Command {
+    let mut command = Command::new(launcher);
+    command.env_clear().envs(replay_env(env)).arg(program);
+    if !args.is_empty() {
+        command.arg("--").args(args);
+    }
+    command
+}
+
+/// `rusty_basic` gets the program and its arguments on the command line,
+/// and the program in `BLR_PROGRAM` as well, like a program started by the
+/// launcher.
+fn rusty_basic_command(
+    interpreter: &OsStr,
+    program: &Path,
+    args: &[String],
+    env: &[(String, String)],
+) -> Command {
+    let mut command = Command::new(interpreter);
+    command
+        .env_clear()
+        .envs(replay_env(env))
+        .env("BLR_PROGRAM", program)
+        .arg(program)
+        .args(args);
+    command
+}
+
+fn run_with_stdin(mut command: Command, stdin: &[u8]) -> io::Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut child_stdin = child.stdin.take().unwrap();
+    let body = stdin.to_vec();
+    let writer = thread::spawn(move || child_stdin.write_all(&body));
+    let output = child.wait_with_output()?;
+    // the program doesn't have to read all of its input
+    let _ = writer.join();
+    Ok(output)
+}
+
+/// The environment of a replay: the recorded request on top of the current
+/// environment, minus anything that belongs to the current request or that
+/// would record the replay again.
+fn replay_env(recorded: &[(String, String)]) -> Vec<(String, String)> {
+    let mut result: Vec<(String, String)> = env::vars()
+        .filter(|(key, _)| !is_request_key(key))
+        .collect();
+    result.extend(recorded.iter().cloned());
+    result
+}
+
+fn is_request_key(key: &str) -> bool {
+    is_forwarded_env_key(key)
+        || key == CONTENT_LENGTH
+        || key == "GATEWAY_INTERFACE"
+        || key == "BLR_PROGRAM"
+        || key == "BLR_RECORD_DIR"
+        || key == "BLR_DEBUG_DIR"
+        || key.starts_with("HTTP_")
+        || key.starts_with("REDIRECT_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = env::temp_dir().join("blr-recording-save-load");
+        let _ = fs::remove_dir_all(&dir);
+        let recording = Recording {
+            program: PathBuf::from("/basic/src/CREATE.BAS"),
+            args: vec!["a b".to_string(), "".to_string()],
+            env: vec![
+                ("REQUEST_METHOD".to_string(), "POST".to_string()),
+                ("QUERY_STRING".to_string(), "id=1&x=a=b".to_string()),
+            ],
+            stdin: b"buy milk\r\n".to_vec(),
+            stdout: b"Status: 201 Created\r\n\r\n".to_vec(),
+        };
+        recording.save(&dir).unwrap();
+        assert_eq!(Recording::load(&dir).unwrap(), recording);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!(parse_backend("launcher"), Some(Backend::Launcher));
+        assert_eq!(parse_backend("dosbox"), Some(Backend::Launcher));
+        assert_eq!(parse_backend("rusty_basic"), Some(Backend::RustyBasic));
+        assert_eq!(parse_backend("qbasic"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rusty_basic_gets_program_and_args() {
+        let dir = env::temp_dir().join("blr-recording-rusty-basic");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // a shell script stands in for the BASIC program
+        let program = dir.join("ARGS.BAS");
+        fs::write(&program, "echo \"$BLR_PROGRAM|$*|$QUERY_STRING\"\n").unwrap();
+        let args = vec!["a b".to_string(), "c".to_string()];
+        let env = vec![("QUERY_STRING".to_string(), "id=1".to_string())];
+        let command = rusty_basic_command(OsStr::new("sh"), &program, &args, &env);
+        let output = run_with_stdin(command, b"").unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("{}|a b c|id=1\n", program.display())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_request_key() {
+        assert!(is_request_key("QUERY_STRING"));
+        assert!(is_request_key("CONTENT_LENGTH"));
+        assert!(is_request_key("HTTP_HOST"));
+        assert!(is_request_key("REDIRECT_BLR_PROGRAM"));
+        assert!(is_request_key("BLR_RECORD_DIR"));
+        assert!(!is_request_key("BLR_DOSBOX"));
+        assert!(!is_request_key("PATH"));
+    }
+}
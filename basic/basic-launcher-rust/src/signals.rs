@@ -0,0 +1,148 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::{exit, Child, Command, ExitStatus};
+use std::sync::Mutex;
+
+use crate::temp_files::remove_if_exists;
+
+/// Exit code after being interrupted, as shells report SIGINT.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// What needs to be undone if the launcher gets interrupted.
+struct State {
+    /// Process id of the running DOSBox.
+    child: Option<u32>,
+    /// Temporary files to remove.
+    files: Vec<PathBuf>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    child: None,
+    files: vec![],
+});
+
+/// Handles SIGINT and SIGTERM (Ctrl+C on Windows), e.g. when Apache gives up
+/// on a CGI request, by stopping DOSBox and removing the temporary files.
+pub fn install_handler() {
+    ctrlc::set_handler(|| {
+        let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(pid) = state.child {
+            kill(pid);
+        }
+        for file in &state.files {
+            let _ = remove_if_exists(file);
+        }
+        exit(INTERRUPTED_EXIT_CODE);
+    })
+    .expect("Could not install signal handler");
+}
+
+/// Sets the temporary files that the handler removes.
+pub fn set_temp_files(files: Vec<PathBuf>) {
+    lock().files = files;
+}
+
+/// Starts DOSBox and sets its process id. The handler waits until then, so
+/// that DOSBox cannot be left running by a signal that arrives while it is
+/// being started.
+pub fn spawn_child(command: &mut Command) -> io::Result<Child> {
+    let mut state = lock();
+    let child = command.spawn()?;
+    state.child = Some(child.id());
+    Ok(child)
+}
+
+/// Checks if DOSBox has exited. Its process id is forgotten before the
+/// handler can run again, as reaping DOSBox frees the id for another process.
+pub fn try_wait_child(child: &mut Child) -> io::Result<Option<ExitStatus>> {
+    let mut state = lock();
+    let status = child.try_wait()?;
+    if status.is_some() {
+        state.child = None;
+    }
+    Ok(status)
+}
+
+/// Waits for DOSBox to exit, without holding off the handler while it runs.
+pub fn wait_child(child: &mut Child) -> io::Result<ExitStatus> {
+    wait_exited(child)?;
+    loop {
+        if let Some(status) = try_wait_child(child)? {
+            return Ok(status);
+        }
+    }
+}
+
+/// Kills DOSBox and forgets its process id.
+pub fn kill_child(child: &mut Child) -> io::Result<()> {
+    let mut state = lock();
+    let result = child.kill().and_then(|_| child.wait());
+    state.child = None;
+    result.map(|_| ())
+}
+
+fn lock() -> std::sync::MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Blocks until the child has exited, leaving it to be reaped.
+#[cfg(unix)]
+fn wait_exited(child: &mut Child) -> io::Result<()> {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                child.id() as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if result == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Blocks until the child has exited. Its handle keeps the process id from
+/// being reused until `child` is dropped.
+#[cfg(not(unix))]
+fn wait_exited(child: &mut Child) -> io::Result<()> {
+    child.wait().map(|_| ())
+}
+
+#[cfg(unix)]
+fn kill(pid: u32) {
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(pid: u32) {
+    let _ = std::process::Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid.to_string()])
+        .output();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_child_is_forgotten_when_reaped() {
+        let mut child = spawn_child(&mut Command::new("true")).unwrap();
+        assert!(wait_child(&mut child).unwrap().success());
+        assert_eq!(lock().child, None);
+
+        let mut child = spawn_child(Command::new("sleep").arg("10")).unwrap();
+        assert_eq!(lock().child, Some(child.id()));
+        assert_eq!(try_wait_child(&mut child).unwrap(), None);
+        kill_child(&mut child).unwrap();
+        assert_eq!(lock().child, None);
+    }
+}
@@ -1,33 +1,102 @@
+use std::fs;
+use std::io;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
 use crate::options::Options;
-use crate::rand_file::make_unique_random_filename;
+use crate::rand_file::create_unique_random_files;
+use crate::signals;
 
+/// Extensions of the temporary files, in the order of the fields of `TempFiles`.
+const EXTENSIONS: &[&str] = &["BAT", "INP", "OUT", "ARG", "LST", "LOG", "ERR", "BOT"];
+
+/// Extension of the copies of a program's directory that the `test` command
+/// runs cases in, e.g. `K3X9QZ0A.TST`.
+pub const ISOLATED_DIR_EXTENSION: &str = "TST";
+
+/// Files younger than this are left alone by `gc`, as they might belong to
+/// a launch that is still running.
+const DEFAULT_MAX_AGE_SECONDS: u64 = 3600;
+
+/// The temporary files of one launch. They are removed when this is dropped,
+/// unless cleanup is disabled with `BLR_NO_CLEANUP`.
 #[derive(Debug)]
 pub struct TempFiles {
     pub batch_dir: PathBuf,
     pub batch_file: PathBuf,
     pub stdin_file: PathBuf,
     pub stdout_file: PathBuf,
+    pub args_file: PathBuf,
+    pub listing_file: PathBuf,
     pub dosbox_log_file: PathBuf,
     pub dosbox_err_file: PathBuf,
+    /// Written by the batch file just before the interpreter line, so that
+    /// its time tells how long DOSBox took to boot.
+    pub boot_file: PathBuf,
+    keep: bool,
 }
 
 impl TempFiles {
-    pub fn create(options: &Options) -> TempFiles {
-        let batch_dir = batch_dir(&options);
-        TempFiles {
+    /// Reserves the temporary files of one launch. They all share the same
+    /// random name, so they can't get mixed up with the files of another launch.
+    pub fn create(options: &Options) -> io::Result<TempFiles> {
+        let batch_dir = batch_dir(options);
+        let mut files = create_unique_random_files(batch_dir, EXTENSIONS)?.into_iter();
+        let mut next = || files.next().unwrap();
+        let result = TempFiles {
             batch_dir: batch_dir.to_path_buf(),
-            batch_file: make_unique_random_filename(batch_dir, "BAT"),
-            stdin_file: make_unique_random_filename(batch_dir, "INP"),
-            stdout_file: make_unique_random_filename(batch_dir, "OUT"),
-            dosbox_log_file: make_unique_random_filename(batch_dir, "LOG"),
-            dosbox_err_file: make_unique_random_filename(batch_dir, "ERR"),
+            batch_file: next(),
+            stdin_file: next(),
+            stdout_file: next(),
+            args_file: next(),
+            listing_file: next(),
+            dosbox_log_file: next(),
+            dosbox_err_file: next(),
+            boot_file: next(),
+            keep: !options.cleanup,
+        };
+        if !result.keep {
+            signals::set_temp_files(result.files());
+        }
+        Ok(result)
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        vec![
+            self.batch_file.clone(),
+            self.stdin_file.clone(),
+            self.stdout_file.clone(),
+            self.args_file.clone(),
+            self.listing_file.clone(),
+            self.dosbox_log_file.clone(),
+            self.dosbox_err_file.clone(),
+            self.boot_file.clone(),
+        ]
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        signals::set_temp_files(vec![]);
+        for file in self.files() {
+            if let Err(e) = remove_if_exists(&file) {
+                eprintln!("Could not remove {}: {}", file.display(), e);
+            }
         }
     }
 }
 
+pub fn remove_if_exists(p: &Path) -> io::Result<()> {
+    match fs::remove_file(p) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
 fn batch_dir(options: &Options) -> &Path {
     let basic_dir: &Path = options.basic.parent().unwrap();
     let program_dir: &Path = options.program.parent().unwrap();
@@ -45,3 +114,124 @@ fn common_ancestor<'a>(left: &'a Path, right: &'a Path) -> &'a Path {
         }
     }
 }
+
+/// Checks if a file name looks like one of the temporary files, e.g. `K3X9QZ0A.BAT`.
+fn is_temp_file_name(name: &str) -> bool {
+    has_random_name(name, EXTENSIONS)
+}
+
+/// Checks if a directory name looks like a copy made by the `test` command.
+fn is_isolated_dir_name(name: &str) -> bool {
+    has_random_name(name, &[ISOLATED_DIR_EXTENSION])
+}
+
+fn has_random_name(name: &str, extensions: &[&str]) -> bool {
+    match name.split_once('.') {
+        Some((stem, extension)) => {
+            stem.len() == 8
+                && stem
+                    .bytes()
+                    .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+                && extensions.contains(&extension)
+        }
+        None => false,
+    }
+}
+
+/// Removes the temporary files and the copies made by the `test` command in
+/// the given directory that are older than `max_age`, returning what was
+/// removed. These are leftovers of launches and test runs that were killed
+/// without a chance to clean up.
+fn collect_garbage(dir: &Path, max_age: Duration) -> io::Result<Vec<PathBuf>> {
+    let now = SystemTime::now();
+    let mut removed: Vec<PathBuf> = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let name = entry.file_name();
+        let name = name.to_str().unwrap_or_default();
+        let is_temp_file = metadata.is_file() && is_temp_file_name(name);
+        let is_isolated_dir = metadata.is_dir() && is_isolated_dir_name(name);
+        if !is_temp_file && !is_isolated_dir {
+            continue;
+        }
+        let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+        if age < max_age {
+            continue;
+        }
+        if is_isolated_dir {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            remove_if_exists(&entry.path())?;
+        }
+        removed.push(entry.path());
+    }
+    removed.sort();
+    Ok(removed)
+}
+
+/// Implements the `gc` command: `gc DIR [--max-age SECONDS]`.
+pub fn gc_command(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Usage: basic-launcher-rust gc DIR [--max-age SECONDS]",
+        )
+    };
+    let (dir, max_age) = match args {
+        [dir] => (dir, DEFAULT_MAX_AGE_SECONDS),
+        [dir, flag, seconds] if flag == "--max-age" => (dir, seconds.parse().map_err(|_| usage())?),
+        _ => return Err(usage()),
+    };
+    for file in collect_garbage(Path::new(dir), Duration::from_secs(max_age))? {
+        println!("removed {}", file.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_temp_file_name() {
+        assert!(is_temp_file_name("K3X9QZ0A.BAT"));
+        assert!(is_temp_file_name("ABCDEFGH.ERR"));
+        assert!(!is_temp_file_name("HELLO.BAS"));
+        assert!(!is_temp_file_name("ABCDEFGH.BAS"));
+        assert!(!is_temp_file_name("abcdefgh.BAT"));
+        assert!(!is_temp_file_name("ABCDEFGHI.BAT"));
+        assert!(!is_temp_file_name("ABCDEFGH"));
+        assert!(is_isolated_dir_name("K3X9QZ0A.TST"));
+        assert!(!is_isolated_dir_name("K3X9QZ0A.BAT"));
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let dir = std::env::temp_dir().join("blr-temp-files-gc");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("K3X9QZ0A.BAT"), "").unwrap();
+        fs::write(dir.join("K3X9QZ0A.OUT"), "").unwrap();
+        fs::write(dir.join("HELLO.BAS"), "").unwrap();
+        fs::create_dir(dir.join("ABCDEFGH.TST")).unwrap();
+        fs::write(dir.join("ABCDEFGH.TST").join("TODO.DAT"), "").unwrap();
+        fs::create_dir(dir.join("DATA")).unwrap();
+
+        let removed = collect_garbage(&dir, Duration::from_secs(3600)).unwrap();
+        assert!(removed.is_empty());
+
+        let removed = collect_garbage(&dir, Duration::from_secs(0)).unwrap();
+        assert_eq!(
+            removed,
+            vec![
+                dir.join("ABCDEFGH.TST"),
+                dir.join("K3X9QZ0A.BAT"),
+                dir.join("K3X9QZ0A.OUT")
+            ]
+        );
+        assert!(dir.join("HELLO.BAS").exists());
+        assert!(dir.join("DATA").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,578 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::diff::diff_lines;
+use crate::rand_file::make_random_name;
+use crate::recording::{parse_backend, run_backend, Backend};
+use crate::temp_files::ISOLATED_DIR_EXTENSION;
+
+/// Case files are found by this suffix, e.g. `CREATE.case.toml` next to `CREATE.BAS`.
+const CASE_FILE_SUFFIX: &str = ".case.toml";
+
+/// A case file, holding one or more `[[case]]` tables.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CaseFile {
+    #[serde(rename = "case")]
+    cases: Vec<Case>,
+}
+
+impl CaseFile {
+    /// The program and the data files are in the isolated copy of the
+    /// program's directory, so their names must not lead out of it.
+    fn validate(&self) -> Result<(), String> {
+        for case in &self.cases {
+            let names = std::iter::once(&case.program)
+                .chain(case.files.keys())
+                .chain(case.expect.files.keys());
+            for name in names {
+                if !is_plain_file_name(name) {
+                    return Err(format!(
+                        "case {}: {} is not a file name in the program's directory",
+                        case.name, name
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A name without a directory, e.g. `TODO.DAT` but not `../TODO.DAT`,
+/// `DATA/TODO.DAT` or `C:TODO.DAT`.
+fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', ':'])
+}
+
+/// A run of a program and what it should produce.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    name: String,
+    /// The program, relative to the case file.
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    /// Environment variables, e.g. `REQUEST_METHOD`.
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    /// The request body. `CONTENT_LENGTH` is set from it, unless it is in `env`.
+    stdin: Option<String>,
+    /// Data files to create next to the program before it runs.
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+    #[serde(default)]
+    expect: Expect,
+}
+
+/// The expected outcome. Only what is given is checked.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expect {
+    /// The whole output, for programs that don't print a CGI response.
+    stdout: Option<String>,
+    /// The status of the CGI response, 200 unless there is a `Status` header.
+    status: Option<u16>,
+    /// Headers of the CGI response. Other headers are allowed.
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// The body of the CGI response.
+    body: Option<String>,
+    /// The contents of data files after the program has run.
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+}
+
+/// The result of running one case.
+#[derive(Debug)]
+struct Outcome {
+    case_file: PathBuf,
+    name: String,
+    duration: Duration,
+    failures: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Tap,
+    JUnit,
+}
+
+/// Implements the `test` command:
+/// `test [--backend launcher|rusty_basic] [--format tap|junit] [PATH...]`.
+///
+/// Runs the cases of the given case files, or of the case files found in
+/// the given directories (the current directory by default), and prints a
+/// TAP or JUnit report.
+pub fn test_command(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Usage: basic-launcher-rust test [--backend launcher|rusty_basic] [--format tap|junit] [PATH...]",
+        )
+    };
+    let mut backend = Backend::Launcher;
+    let mut format = Format::Tap;
+    let mut paths: Vec<PathBuf> = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--backend" => {
+                backend = iter
+                    .next()
+                    .and_then(|b| parse_backend(b))
+                    .ok_or_else(usage)?
+            }
+            "--format" => {
+                format = match iter.next().map(String::as_str) {
+                    Some("tap") => Format::Tap,
+                    Some("junit") => Format::JUnit,
+                    _ => return Err(usage()),
+                }
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+
+    let mut case_files: Vec<PathBuf> = vec![];
+    for path in &paths {
+        find_case_files(path, &mut case_files)?;
+    }
+    let mut outcomes: Vec<Outcome> = vec![];
+    for case_file in &case_files {
+        let contents = fs::read_to_string(case_file)?;
+        let parsed: CaseFile = toml::from_str(&contents)
+            .map_err(|e| e.to_string())
+            .and_then(|parsed: CaseFile| parsed.validate().map(|_| parsed))
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", case_file.display(), e),
+                )
+            })?;
+        for case in &parsed.cases {
+            outcomes.push(run_case(case_file, case, backend));
+        }
+    }
+
+    match format {
+        Format::Tap => print!("{}", tap_report(&outcomes)),
+        Format::JUnit => print!("{}", junit_report(&outcomes)),
+    }
+    let failed = outcomes.iter().filter(|o| !o.failures.is_empty()).count();
+    if failed > 0 {
+        Err(io::Error::other(format!(
+            "{} of {} cases failed",
+            failed,
+            outcomes.len()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn find_case_files(path: &Path, result: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        result.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        let is_case_file = entry
+            .file_name()
+            .and_then(|f| f.to_str())
+            .is_some_and(|f| f.ends_with(CASE_FILE_SUFFIX));
+        if entry.is_dir() {
+            find_case_files(&entry, result)?;
+        } else if is_case_file {
+            result.push(entry);
+        }
+    }
+    Ok(())
+}
+
+fn run_case(case_file: &Path, case: &Case, backend: Backend) -> Outcome {
+    let start = Instant::now();
+    let failures = match check_case(case_file, case, backend) {
+        Ok(failures) => failures,
+        Err(e) => vec![format!("could not run the case: {}", e)],
+    };
+    Outcome {
+        case_file: case_file.to_path_buf(),
+        name: case.name.clone(),
+        duration: start.elapsed(),
+        failures,
+    }
+}
+
+fn check_case(case_file: &Path, case: &Case, backend: Backend) -> io::Result<Vec<String>> {
+    let program_dir = case_file.parent().unwrap_or_else(|| Path::new("."));
+    let data_dir = IsolatedDir::create(program_dir)?;
+    for (name, contents) in &case.files {
+        fs::write(data_dir.path.join(name), contents)?;
+    }
+    let mut env: Vec<(String, String)> = case
+        .env
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    let stdin = case.stdin.clone().unwrap_or_default();
+    if case.stdin.is_some() && !case.env.contains_key("CONTENT_LENGTH") {
+        env.push(("CONTENT_LENGTH".to_string(), stdin.len().to_string()));
+    }
+    let output = run_backend(
+        backend,
+        &data_dir.path.join(&case.program),
+        &case.args,
+        &env,
+        stdin.as_bytes(),
+    )?;
+
+    let mut failures: Vec<String> = vec![];
+    if !output.status.success() {
+        failures.push(format!(
+            "the program failed with {}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    failures.extend(check_output(&case.expect, &stdout));
+    for (name, expected) in &case.expect.files {
+        match fs::read(data_dir.path.join(name)) {
+            Ok(actual) => {
+                if let Some(diff) = diff_lines(expected, &String::from_utf8_lossy(&actual)) {
+                    failures.push(format!("{} differs\n{}", name, diff));
+                }
+            }
+            Err(e) => failures.push(format!("could not read {}: {}", name, e)),
+        }
+    }
+    Ok(failures)
+}
+
+/// Compares the output of a program with the expected one.
+fn check_output(expect: &Expect, stdout: &str) -> Vec<String> {
+    let mut failures: Vec<String> = vec![];
+    if let Some(expected) = &expect.stdout {
+        if let Some(diff) = diff_lines(expected, stdout) {
+            failures.push(format!("stdout differs\n{}", diff));
+        }
+    }
+    let is_cgi = expect.status.is_some() || !expect.headers.is_empty() || expect.body.is_some();
+    if !is_cgi {
+        return failures;
+    }
+    let response = parse_response(stdout);
+    if let Some(status) = expect.status {
+        if status != response.status {
+            failures.push(format!(
+                "expected status {}, got {}",
+                status, response.status
+            ));
+        }
+    }
+    for (name, value) in &expect.headers {
+        match response.header(name) {
+            Some(actual) if actual == value => {}
+            Some(actual) => failures.push(format!(
+                "expected header {}: {}, got {}",
+                name, value, actual
+            )),
+            None => failures.push(format!("missing header {}", name)),
+        }
+    }
+    if let Some(expected) = &expect.body {
+        if let Some(diff) = diff_lines(expected, &response.body) {
+            failures.push(format!("body differs\n{}", diff));
+        }
+    }
+    failures
+}
+
+/// A CGI response as printed by a program.
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn parse_response(stdout: &str) -> Response {
+    let mut headers: Vec<(String, String)> = vec![];
+    let mut lines = stdout.lines();
+    for line in lines.by_ref() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let body: Vec<&str> = lines.collect();
+    let status = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Status"))
+        .and_then(|(_, value)| value.split_whitespace().next())
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(200);
+    Response {
+        status,
+        headers,
+        body: body.iter().map(|line| format!("{}\n", line)).collect(),
+    }
+}
+
+/// A copy of the files of a program's directory, removed on drop.
+///
+/// It is created next to the original with a random 8.3 name, e.g.
+/// `K3X9QZ0A.TST`, so that the program runs the same way but its data files
+/// are left untouched. The `gc` command removes the copies that a killed run
+/// left behind.
+struct IsolatedDir {
+    path: PathBuf,
+}
+
+impl IsolatedDir {
+    fn create(dir: &Path) -> io::Result<Self> {
+        let dir = fs::canonicalize(dir)?;
+        let parent = dir.parent().unwrap_or(&dir);
+        let path = loop {
+            let candidate = parent.join(format!(
+                "{}.{}",
+                make_random_name(8),
+                ISOLATED_DIR_EXTENSION
+            ));
+            match fs::create_dir(&candidate) {
+                Ok(()) => break candidate,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        let result = Self { path };
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                fs::copy(entry.path(), result.path.join(entry.file_name()))?;
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Drop for IsolatedDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn test_name(outcome: &Outcome) -> String {
+    format!("{}: {}", outcome.case_file.display(), outcome.name)
+}
+
+fn tap_report(outcomes: &[Outcome]) -> String {
+    let mut result = String::new();
+    writeln!(result, "TAP version 13").unwrap();
+    writeln!(result, "1..{}", outcomes.len()).unwrap();
+    for (i, outcome) in outcomes.iter().enumerate() {
+        if outcome.failures.is_empty() {
+            writeln!(result, "ok {} - {}", i + 1, test_name(outcome)).unwrap();
+        } else {
+            writeln!(result, "not ok {} - {}", i + 1, test_name(outcome)).unwrap();
+            for line in outcome.failures.iter().flat_map(|f| f.lines()) {
+                writeln!(result, "# {}", line).unwrap();
+            }
+        }
+    }
+    result
+}
+
+fn junit_report(outcomes: &[Outcome]) -> String {
+    let failed = outcomes.iter().filter(|o| !o.failures.is_empty()).count();
+    let total: Duration = outcomes.iter().map(|o| o.duration).sum();
+    let mut result = String::new();
+    writeln!(result, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+    writeln!(
+        result,
+        "<testsuite name=\"basic\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        outcomes.len(),
+        failed,
+        total.as_secs_f64()
+    )
+    .unwrap();
+    for outcome in outcomes {
+        write!(
+            result,
+            "  <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            xml_escape(&outcome.case_file.display().to_string()),
+            xml_escape(&outcome.name),
+            outcome.duration.as_secs_f64()
+        )
+        .unwrap();
+        if outcome.failures.is_empty() {
+            writeln!(result, "/>").unwrap();
+        } else {
+            writeln!(result, ">").unwrap();
+            writeln!(
+                result,
+                "    <failure message=\"{}\">{}</failure>",
+                xml_escape(outcome.failures[0].lines().next().unwrap_or_default()),
+                xml_escape(&outcome.failures.join("\n"))
+            )
+            .unwrap();
+            writeln!(result, "  </testcase>").unwrap();
+        }
+    }
+    writeln!(result, "</testsuite>").unwrap();
+    result
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_case_file() {
+        let parsed: CaseFile =
+            toml::from_str(include_str!("../../basic/rest/CREATE.case.toml")).unwrap();
+        let case = &parsed.cases[0];
+        assert_eq!(case.program, "CREATE.BAS");
+        assert_eq!(case.env["REQUEST_METHOD"], "POST");
+        assert_eq!(case.expect.status, Some(201));
+    }
+
+    #[test]
+    fn test_parse_case_file_unknown_field() {
+        let result: Result<CaseFile, _> =
+            toml::from_str("[[case]]\nname = \"x\"\nprogram = \"X.BAS\"\nstatus = 200\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_file_names_must_stay_in_the_directory() {
+        let parse = |files: &str| {
+            toml::from_str::<CaseFile>(&format!(
+                "[[case]]\nname = \"x\"\nprogram = \"X.BAS\"\n{}",
+                files
+            ))
+            .unwrap()
+            .validate()
+        };
+        assert_eq!(parse("files = { \"TODO.DAT\" = \"\" }\n"), Ok(()));
+        for name in ["../TODO.DAT", "/etc/passwd", "DATA/X", "C:X", ".."] {
+            let error = parse(&format!("files = {{ \"{}\" = \"\" }}\n", name)).unwrap_err();
+            assert!(error.contains(name), "{}", error);
+            let error = parse(&format!("expect.files = {{ \"{}\" = \"\" }}\n", name));
+            assert!(error.is_err(), "{}", name);
+        }
+        assert!(!is_plain_file_name("..\\TODO.DAT"));
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response =
+            parse_response("Status: 201 Created\r\nContent-Type: text/plain\r\n\r\nhi\r\n");
+        assert_eq!(response.status, 201);
+        assert_eq!(response.header("content-type"), Some("text/plain"));
+        assert_eq!(response.body, "hi\n");
+        assert_eq!(parse_response("X: y\n\n").status, 200);
+    }
+
+    #[test]
+    fn test_check_output() {
+        let expect = Expect {
+            status: Some(200),
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())]
+                .into_iter()
+                .collect(),
+            body: Some("[\n]\n".to_string()),
+            ..Expect::default()
+        };
+        assert!(
+            check_output(&expect, "Content-Type: application/json\r\n\r\n[\r\n]\r\n").is_empty()
+        );
+        assert_eq!(
+            check_output(&expect, "Status: 405 Nope\r\n\r\n"),
+            vec![
+                "expected status 200, got 405",
+                "missing header Content-Type",
+                "body differs\n-[\n-]\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_isolated_dir() {
+        let dir = std::env::temp_dir().join("blr-test-cases").join("DATA");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("TODO.DAT"), "a\r\n").unwrap();
+        let isolated = IsolatedDir::create(&dir).unwrap();
+        let path = isolated.path.clone();
+        assert_ne!(path, fs::canonicalize(&dir).unwrap());
+        fs::write(path.join("TODO.DAT"), "b\r\n").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("TODO.DAT")).unwrap(), "a\r\n");
+        drop(isolated);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_reports() {
+        let outcomes = vec![
+            Outcome {
+                case_file: PathBuf::from("rest/LIST.case.toml"),
+                name: "empty".to_string(),
+                duration: Duration::from_millis(5),
+                failures: vec![],
+            },
+            Outcome {
+                case_file: PathBuf::from("rest/LIST.case.toml"),
+                name: "<items>".to_string(),
+                duration: Duration::from_millis(5),
+                failures: vec!["body differs\n-a\n+b\n".to_string()],
+            },
+        ];
+        assert_eq!(
+            tap_report(&outcomes),
+            "TAP version 13\n1..2\nok 1 - rest/LIST.case.toml: empty\nnot ok 2 - rest/LIST.case.toml: <items>\n# body differs\n# -a\n# +b\n"
+        );
+        let junit = junit_report(&outcomes);
+        assert!(
+            junit.contains("<testsuite name=\"basic\" tests=\"2\" failures=\"1\" time=\"0.010\">")
+        );
+        assert!(junit.contains("name=\"&lt;items&gt;\""));
+        assert!(
+            junit.contains("<failure message=\"body differs\">body differs\n-a\n+b\n</failure>")
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+/// How long each phase of a launch took, in the order they ran.
+#[derive(Debug)]
+pub struct Timings {
+    start: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            phases: vec![],
+        }
+    }
+
+    /// Runs the given phase and records its duration.
+    pub fn phase<T, F: FnOnce() -> T>(&mut self, name: &'static str, f: F) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.phases.push((name, start.elapsed()));
+        result
+    }
+
+    /// Records a phase that was measured elsewhere, e.g. a part of another phase.
+    pub fn record(&mut self, name: &'static str, duration: Duration) {
+        self.phases.push((name, duration));
+    }
+
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    /// The time since the launch started, including the time between phases.
+    pub fn total(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Formats the phases and the total so far as the value of a `Server-Timing`
+/// header, with durations in milliseconds, e.g. `batch;dur=0.3, total;dur=912.8`.
+pub fn server_timing(timings: &Timings) -> String {
+    timings
+        .phases()
+        .iter()
+        .chain([("total", timings.total())].iter())
+        .map(|(name, duration)| format!("{};dur={:.1}", name, duration.as_secs_f64() * 1000.0))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase() {
+        let mut timings = Timings::new();
+        let result = timings.phase("first", || 42);
+        timings.phase("second", || std::thread::sleep(Duration::from_millis(2)));
+        assert_eq!(result, 42);
+        let names: Vec<&str> = timings.phases().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["first", "second"]);
+        assert!(timings.phases()[1].1 >= Duration::from_millis(2));
+        assert!(timings.total() >= timings.phases()[1].1);
+    }
+
+    #[test]
+    fn test_server_timing() {
+        let mut timings = Timings::new();
+        timings.record("batch", Duration::from_micros(300));
+        timings.record("dosbox", Duration::from_millis(912));
+        let header = server_timing(&timings);
+        assert!(
+            header.starts_with("batch;dur=0.3, dosbox;dur=912.0, total;dur="),
+            "{}",
+            header
+        );
+    }
+}
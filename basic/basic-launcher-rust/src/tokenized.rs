@@ -0,0 +1,1134 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+
+use crate::dialect::{PROTECTED_SIGNATURE, TOKENIZED_SIGNATURE};
+
+/// Marks the end of a tokenized file.
+const EOF_MARKER: u8 = 0x1A;
+
+/// The address of the first line. GW-Basic relinks a program when it loads
+/// it, so the addresses of the line links only need to be non-zero and
+/// consistent.
+const LINE_BASE_ADDRESS: u16 = 0x126F;
+
+const TOKEN_OCTAL: u8 = 0x0B;
+const TOKEN_HEX: u8 = 0x0C;
+const TOKEN_LINE_POINTER: u8 = 0x0D;
+const TOKEN_LINE_NUMBER: u8 = 0x0E;
+const TOKEN_BYTE: u8 = 0x0F;
+const TOKEN_SMALL_INT_0: u8 = 0x11;
+const TOKEN_SMALL_INT_10: u8 = 0x1B;
+const TOKEN_INT: u8 = 0x1C;
+const TOKEN_SINGLE: u8 = 0x1D;
+const TOKEN_DOUBLE: u8 = 0x1F;
+
+const TOKEN_COLON: u8 = b':';
+const TOKEN_DATA: u16 = 0x84;
+const TOKEN_REM: u16 = 0x8F;
+const TOKEN_ELSE: u16 = 0xA1;
+const TOKEN_APOSTROPHE: u16 = 0xD9;
+const TOKEN_FN: u16 = 0xD1;
+const TOKEN_MINUS: u16 = 0xEA;
+const TOKEN_PRINT: u16 = 0x91;
+
+/// Keywords, operators and functions with their token.
+/// Tokens above 0xFF are stored as two bytes, high byte first.
+const KEYWORDS: &[(u16, &str)] = &[
+    (0x81, "END"),
+    (0x82, "FOR"),
+    (0x83, "NEXT"),
+    (0x84, "DATA"),
+    (0x85, "INPUT"),
+    (0x86, "DIM"),
+    (0x87, "READ"),
+    (0x88, "LET"),
+    (0x89, "GOTO"),
+    (0x8A, "RUN"),
+    (0x8B, "IF"),
+    (0x8C, "RESTORE"),
+    (0x8D, "GOSUB"),
+    (0x8E, "RETURN"),
+    (0x8F, "REM"),
+    (0x90, "STOP"),
+    (0x91, "PRINT"),
+    (0x92, "CLEAR"),
+    (0x93, "LIST"),
+    (0x94, "NEW"),
+    (0x95, "ON"),
+    (0x96, "WAIT"),
+    (0x97, "DEF"),
+    (0x98, "POKE"),
+    (0x99, "CONT"),
+    (0x9C, "OUT"),
+    (0x9D, "LPRINT"),
+    (0x9E, "LLIST"),
+    (0xA0, "WIDTH"),
+    (0xA1, "ELSE"),
+    (0xA2, "TRON"),
+    (0xA3, "TROFF"),
+    (0xA4, "SWAP"),
+    (0xA5, "ERASE"),
+    (0xA6, "EDIT"),
+    (0xA7, "ERROR"),
+    (0xA8, "RESUME"),
+    (0xA9, "DELETE"),
+    (0xAA, "AUTO"),
+    (0xAB, "RENUM"),
+    (0xAC, "DEFSTR"),
+    (0xAD, "DEFINT"),
+    (0xAE, "DEFSNG"),
+    (0xAF, "DEFDBL"),
+    (0xB0, "LINE"),
+    (0xB1, "WHILE"),
+    (0xB2, "WEND"),
+    (0xB3, "CALL"),
+    (0xB7, "WRITE"),
+    (0xB8, "OPTION"),
+    (0xB9, "RANDOMIZE"),
+    (0xBA, "OPEN"),
+    (0xBB, "CLOSE"),
+    (0xBC, "LOAD"),
+    (0xBD, "MERGE"),
+    (0xBE, "SAVE"),
+    (0xBF, "COLOR"),
+    (0xC0, "CLS"),
+    (0xC1, "MOTOR"),
+    (0xC2, "BSAVE"),
+    (0xC3, "BLOAD"),
+    (0xC4, "SOUND"),
+    (0xC5, "BEEP"),
+    (0xC6, "PSET"),
+    (0xC7, "PRESET"),
+    (0xC8, "SCREEN"),
+    (0xC9, "KEY"),
+    (0xCA, "LOCATE"),
+    (0xCC, "TO"),
+    (0xCD, "THEN"),
+    (0xCE, "TAB("),
+    (0xCF, "STEP"),
+    (0xD0, "USR"),
+    (0xD1, "FN"),
+    (0xD2, "SPC("),
+    (0xD3, "NOT"),
+    (0xD4, "ERL"),
+    (0xD5, "ERR"),
+    (0xD6, "STRING$"),
+    (0xD7, "USING"),
+    (0xD8, "INSTR"),
+    (0xD9, "'"),
+    (0xDA, "VARPTR"),
+    (0xDB, "CSRLIN"),
+    (0xDC, "POINT"),
+    (0xDD, "OFF"),
+    (0xDE, "INKEY$"),
+    (0xE6, ">"),
+    (0xE7, "="),
+    (0xE8, "<"),
+    (0xE9, "+"),
+    (0xEA, "-"),
+    (0xEB, "*"),
+    (0xEC, "/"),
+    (0xED, "^"),
+    (0xEE, "AND"),
+    (0xEF, "OR"),
+    (0xF0, "XOR"),
+    (0xF1, "EQV"),
+    (0xF2, "IMP"),
+    (0xF3, "MOD"),
+    (0xF4, "\\"),
+    (0xFD81, "CVI"),
+    (0xFD82, "CVS"),
+    (0xFD83, "CVD"),
+    (0xFD84, "MKI$"),
+    (0xFD85, "MKS$"),
+    (0xFD86, "MKD$"),
+    (0xFD8B, "EXTERR"),
+    (0xFE81, "FILES"),
+    (0xFE82, "FIELD"),
+    (0xFE83, "SYSTEM"),
+    (0xFE84, "NAME"),
+    (0xFE85, "LSET"),
+    (0xFE86, "RSET"),
+    (0xFE87, "KILL"),
+    (0xFE88, "PUT"),
+    (0xFE89, "GET"),
+    (0xFE8A, "RESET"),
+    (0xFE8B, "COMMON"),
+    (0xFE8C, "CHAIN"),
+    (0xFE8D, "DATE$"),
+    (0xFE8E, "TIME$"),
+    (0xFE8F, "PAINT"),
+    (0xFE90, "COM"),
+    (0xFE91, "CIRCLE"),
+    (0xFE92, "DRAW"),
+    (0xFE93, "PLAY"),
+    (0xFE94, "TIMER"),
+    (0xFE95, "ERDEV"),
+    (0xFE96, "IOCTL"),
+    (0xFE97, "CHDIR"),
+    (0xFE98, "MKDIR"),
+    (0xFE99, "RMDIR"),
+    (0xFE9A, "SHELL"),
+    (0xFE9B, "ENVIRON"),
+    (0xFE9C, "VIEW"),
+    (0xFE9D, "WINDOW"),
+    (0xFE9E, "PMAP"),
+    (0xFE9F, "PALETTE"),
+    (0xFEA0, "LCOPY"),
+    (0xFEA1, "CALLS"),
+    (0xFEA4, "NOISE"),
+    (0xFEA5, "PCOPY"),
+    (0xFEA6, "TERM"),
+    (0xFEA7, "LOCK"),
+    (0xFEA8, "UNLOCK"),
+    (0xFF81, "LEFT$"),
+    (0xFF82, "RIGHT$"),
+    (0xFF83, "MID$"),
+    (0xFF84, "SGN"),
+    (0xFF85, "INT"),
+    (0xFF86, "ABS"),
+    (0xFF87, "SQR"),
+    (0xFF88, "RND"),
+    (0xFF89, "SIN"),
+    (0xFF8A, "LOG"),
+    (0xFF8B, "EXP"),
+    (0xFF8C, "COS"),
+    (0xFF8D, "TAN"),
+    (0xFF8E, "ATN"),
+    (0xFF8F, "FRE"),
+    (0xFF90, "INP"),
+    (0xFF91, "POS"),
+    (0xFF92, "LEN"),
+    (0xFF93, "STR$"),
+    (0xFF94, "VAL"),
+    (0xFF95, "ASC"),
+    (0xFF96, "CHR$"),
+    (0xFF97, "PEEK"),
+    (0xFF98, "SPACE$"),
+    (0xFF99, "OCT$"),
+    (0xFF9A, "HEX$"),
+    (0xFF9B, "LPOS"),
+    (0xFF9C, "CINT"),
+    (0xFF9D, "CSNG"),
+    (0xFF9E, "CDBL"),
+    (0xFF9F, "FIX"),
+    (0xFFA0, "PEN"),
+    (0xFFA1, "STICK"),
+    (0xFFA2, "STRIG"),
+    (0xFFA3, "EOF"),
+    (0xFFA4, "LOC"),
+    (0xFFA5, "LOF"),
+];
+
+/// Keywords that are followed by line numbers, which are stored as
+/// `TOKEN_LINE_NUMBER` so that RENUM can find them.
+const LINE_NUMBER_KEYWORDS: &[u16] = &[
+    0x89, 0x8A, 0x8C, 0x8D, 0x93, 0x9E, 0xA1, 0xA6, 0xA8, 0xA9, 0xAA, 0xAB, 0xCD,
+];
+
+/// Keys of the cipher used by `SAVE "FILE",P`.
+const KEY1: [u8; 13] = [
+    0xA9, 0x84, 0x8D, 0xCD, 0x75, 0x83, 0x43, 0x63, 0x24, 0x83, 0x19, 0xF7, 0x9A,
+];
+const KEY2: [u8; 11] = [
+    0x1E, 0x1D, 0xC4, 0x77, 0x26, 0x97, 0xE0, 0x74, 0x59, 0x88, 0x7C,
+];
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Checks if the contents are a tokenized (possibly protected) program.
+pub fn is_tokenized(contents: &[u8]) -> bool {
+    matches!(
+        contents.first(),
+        Some(&TOKENIZED_SIGNATURE) | Some(&PROTECTED_SIGNATURE)
+    )
+}
+
+/// Converts a tokenized or protected program into an ASCII listing
+/// with CRLF line endings.
+pub fn detokenize(contents: &[u8]) -> io::Result<Vec<u8>> {
+    let unprotected;
+    let contents = match contents.first() {
+        Some(&TOKENIZED_SIGNATURE) => contents,
+        Some(&PROTECTED_SIGNATURE) => {
+            unprotected = unprotect(contents);
+            &unprotected
+        }
+        _ => return Err(invalid_data("Not a tokenized GW-Basic file".to_string())),
+    };
+    let mut reader = Reader {
+        contents,
+        pos: 1,
+        result: vec![],
+    };
+    reader.program()?;
+    Ok(reader.result)
+}
+
+/// Converts an ASCII listing into a tokenized program.
+/// Every line must start with a line number. Lines are sorted by line number
+/// and a repeated line number replaces the earlier line, as GW-Basic does.
+pub fn tokenize(listing: &[u8]) -> io::Result<Vec<u8>> {
+    let mut lines: BTreeMap<u16, Vec<u8>> = BTreeMap::new();
+    for (index, raw_line) in listing.split(|b| *b == b'\n').enumerate() {
+        let line = trim_line(raw_line);
+        if line.is_empty() || line == [EOF_MARKER] {
+            continue;
+        }
+        let (line_number, rest) = split_line_number(line)
+            .ok_or_else(|| invalid_data(format!("Line {} has no line number", index + 1)))?;
+        let rest = rest.strip_prefix(b" ").unwrap_or(rest);
+        lines.insert(line_number, tokenize_line(rest)?);
+    }
+
+    let mut result: Vec<u8> = vec![TOKENIZED_SIGNATURE];
+    let mut address: u16 = LINE_BASE_ADDRESS;
+    for (line_number, tokens) in lines {
+        let next_address = address.wrapping_add(tokens.len() as u16 + 5);
+        result.extend_from_slice(&next_address.to_le_bytes());
+        result.extend_from_slice(&line_number.to_le_bytes());
+        result.extend_from_slice(&tokens);
+        result.push(0);
+        address = next_address;
+    }
+    result.extend_from_slice(&[0, 0, EOF_MARKER]);
+    Ok(result)
+}
+
+/// Decrypts a protected program. The result starts with the signature of
+/// tokenized programs.
+pub fn unprotect(contents: &[u8]) -> Vec<u8> {
+    apply_cipher(contents, TOKENIZED_SIGNATURE, |c, k1, k2, i13, i11| {
+        let c = c.wrapping_sub(11 - i11);
+        let c = c ^ k1 ^ k2;
+        c.wrapping_add(13 - i13)
+    })
+}
+
+/// Encrypts a tokenized program, as `SAVE "FILE",P` does.
+pub fn protect(contents: &[u8]) -> Vec<u8> {
+    apply_cipher(contents, PROTECTED_SIGNATURE, |c, k1, k2, i13, i11| {
+        let c = c.wrapping_sub(13 - i13);
+        let c = c ^ k1 ^ k2;
+        c.wrapping_add(11 - i11)
+    })
+}
+
+/// Applies the cipher to everything between the signature and the
+/// trailing end of file marker.
+fn apply_cipher<F>(contents: &[u8], signature: u8, f: F) -> Vec<u8>
+where
+    F: Fn(u8, u8, u8, u8, u8) -> u8,
+{
+    let mut body: &[u8] = contents.get(1..).unwrap_or_default();
+    let has_eof_marker = body.last() == Some(&EOF_MARKER);
+    if has_eof_marker {
+        body = &body[..body.len() - 1];
+    }
+    let mut result: Vec<u8> = vec![signature];
+    for (i, c) in body.iter().enumerate() {
+        let i13 = i % 13;
+        let i11 = i % 11;
+        result.push(f(*c, KEY1[i13], KEY2[i11], i13 as u8, i11 as u8));
+    }
+    if has_eof_marker {
+        result.push(EOF_MARKER);
+    }
+    result
+}
+
+struct Reader<'a> {
+    contents: &'a [u8],
+    pos: usize,
+    result: Vec<u8>,
+}
+
+impl<'a> Reader<'a> {
+    fn program(&mut self) -> io::Result<()> {
+        loop {
+            let link = self.read_u16()?;
+            if link == 0 {
+                return Ok(());
+            }
+            let line_number = self.read_u16()?;
+            write!(self.result, "{} ", line_number)?;
+            self.line()?;
+            self.result.extend_from_slice(b"\r\n");
+        }
+    }
+
+    fn line(&mut self) -> io::Result<()> {
+        loop {
+            let b = self.read_u8()?;
+            match b {
+                0 => return Ok(()),
+                b'"' => {
+                    self.result.push(b);
+                    self.string()?;
+                }
+                TOKEN_COLON if self.peek_is(&[TOKEN_ELSE as u8]) => {}
+                TOKEN_COLON if self.peek_is(&[TOKEN_REM as u8, TOKEN_APOSTROPHE as u8]) => {
+                    self.pos += 2;
+                    self.result.push(b'\'');
+                    self.literal_until(|_| false)?;
+                }
+                TOKEN_OCTAL => {
+                    let value = self.read_u16()?;
+                    write!(self.result, "&O{:o}", value)?;
+                }
+                TOKEN_HEX => {
+                    let value = self.read_u16()?;
+                    write!(self.result, "&H{:X}", value)?;
+                }
+                TOKEN_LINE_NUMBER => {
+                    let value = self.read_u16()?;
+                    write!(self.result, "{}", value)?;
+                }
+                TOKEN_LINE_POINTER => {
+                    return Err(invalid_data(format!(
+                        "Unexpected line pointer at offset {}",
+                        self.pos - 1
+                    )))
+                }
+                TOKEN_BYTE => {
+                    let value = self.read_u8()?;
+                    write!(self.result, "{}", value)?;
+                }
+                TOKEN_SMALL_INT_0..=TOKEN_SMALL_INT_10 => {
+                    write!(self.result, "{}", b - TOKEN_SMALL_INT_0)?;
+                }
+                TOKEN_INT => {
+                    let value = self.read_u16()? as i16;
+                    write!(self.result, "{}", value)?;
+                }
+                TOKEN_SINGLE => {
+                    let bytes = self.read_bytes(4)?;
+                    let value = mbf_single_to_f32([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    self.result
+                        .extend_from_slice(format_single(value).as_bytes());
+                }
+                TOKEN_DOUBLE => {
+                    let bytes = self.read_bytes(8)?;
+                    let mut mbf = [0u8; 8];
+                    mbf.copy_from_slice(bytes);
+                    let value = mbf_double_to_f64(mbf);
+                    self.result
+                        .extend_from_slice(format_double(value).as_bytes());
+                }
+                0x80..=0xFF => {
+                    let token = if b >= 0xFD {
+                        ((b as u16) << 8) | self.read_u8()? as u16
+                    } else {
+                        b as u16
+                    };
+                    let keyword = keyword_of(token).ok_or_else(|| {
+                        invalid_data(format!("Unknown token {:X} at offset {}", token, self.pos))
+                    })?;
+                    self.result.extend_from_slice(keyword.as_bytes());
+                    if token == TOKEN_REM || token == TOKEN_APOSTROPHE {
+                        self.literal_until(|_| false)?;
+                    } else if token == TOKEN_DATA {
+                        self.data()?;
+                    }
+                }
+                _ => self.result.push(b),
+            }
+        }
+    }
+
+    /// Copies the rest of a string literal, including the closing quote.
+    fn string(&mut self) -> io::Result<()> {
+        self.literal_until(|b| b == b'"')?;
+        if self.peek_is(b"\"") {
+            self.pos += 1;
+            self.result.push(b'"');
+        }
+        Ok(())
+    }
+
+    /// DATA is stored verbatim until the end of the statement.
+    fn data(&mut self) -> io::Result<()> {
+        loop {
+            self.literal_until(|b| b == b'"' || b == TOKEN_COLON)?;
+            if self.peek_is(b"\"") {
+                self.pos += 1;
+                self.result.push(b'"');
+                self.string()?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Copies bytes verbatim until the end of the line or until the
+    /// predicate matches. The matching byte is not consumed.
+    fn literal_until<F>(&mut self, predicate: F) -> io::Result<()>
+    where
+        F: Fn(u8) -> bool,
+    {
+        loop {
+            match self.contents.get(self.pos) {
+                Some(0) => return Ok(()),
+                Some(b) if predicate(*b) => return Ok(()),
+                Some(b) => {
+                    self.result.push(*b);
+                    self.pos += 1;
+                }
+                None => return Err(self.unexpected_eof()),
+            }
+        }
+    }
+
+    fn peek_is(&self, expected: &[u8]) -> bool {
+        self.contents[self.pos..].starts_with(expected)
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let contents: &'a [u8] = self.contents;
+        match contents.get(self.pos..self.pos + count) {
+            Some(bytes) => {
+                self.pos += count;
+                Ok(bytes)
+            }
+            None => Err(self.unexpected_eof()),
+        }
+    }
+
+    fn unexpected_eof(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Tokenized GW-Basic file is truncated",
+        )
+    }
+}
+
+fn keyword_of(token: u16) -> Option<&'static str> {
+    KEYWORDS
+        .iter()
+        .find(|(t, _)| *t == token)
+        .map(|(_, keyword)| *keyword)
+}
+
+fn token_of(keyword: &str) -> Option<u16> {
+    KEYWORDS
+        .iter()
+        .find(|(_, k)| *k == keyword)
+        .map(|(t, _)| *t)
+}
+
+fn push_token(tokens: &mut Vec<u8>, token: u16) {
+    if token > 0xFF {
+        tokens.extend_from_slice(&token.to_be_bytes());
+    } else {
+        tokens.push(token as u8);
+    }
+}
+
+fn trim_line(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let start = line.iter().position(|b| *b != b' ').unwrap_or(line.len());
+    &line[start..]
+}
+
+fn split_line_number(line: &[u8]) -> Option<(u16, &[u8])> {
+    let digits = line.iter().take_while(|b| b.is_ascii_digit()).count();
+    let line_number = std::str::from_utf8(&line[..digits]).ok()?.parse().ok()?;
+    Some((line_number, &line[digits..]))
+}
+
+fn tokenize_line(line: &[u8]) -> io::Result<Vec<u8>> {
+    let mut tokens: Vec<u8> = vec![];
+    let mut pos = 0;
+    let mut expect_line_number = false;
+    while pos < line.len() {
+        let b = line[pos];
+        if expect_line_number {
+            if b.is_ascii_digit() {
+                let digits = line[pos..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_digit())
+                    .count();
+                let (value, _) = split_line_number(&line[pos..])
+                    .ok_or_else(|| invalid_data("Line number out of range".to_string()))?;
+                tokens.push(TOKEN_LINE_NUMBER);
+                tokens.extend_from_slice(&value.to_le_bytes());
+                pos += digits;
+                continue;
+            }
+            expect_line_number = b == b' ' || b == b',' || b == b'-';
+        }
+        if b == b'"' {
+            let end = line[pos + 1..]
+                .iter()
+                .position(|b| *b == b'"')
+                .map_or(line.len(), |i| pos + i + 2);
+            tokens.extend_from_slice(&line[pos..end]);
+            pos = end;
+        } else if b == b'\'' {
+            tokens.extend_from_slice(&[TOKEN_COLON, TOKEN_REM as u8, TOKEN_APOSTROPHE as u8]);
+            tokens.extend_from_slice(&line[pos + 1..]);
+            pos = line.len();
+        } else if b.is_ascii_digit() || (b == b'.' && next_is_digit(line, pos)) {
+            pos += tokenize_number(&line[pos..], &mut tokens)?;
+        } else if b == b'?' {
+            push_token(&mut tokens, TOKEN_PRINT);
+            pos += 1;
+        } else if b == b'&' {
+            pos += tokenize_radix(&line[pos..], &mut tokens)?;
+        } else if b.is_ascii_alphabetic() {
+            let (token, len) = read_word(&line[pos..]);
+            match token {
+                Some(token) => {
+                    if token == TOKEN_ELSE {
+                        tokens.push(TOKEN_COLON);
+                    }
+                    push_token(&mut tokens, token);
+                    pos += len;
+                    expect_line_number = LINE_NUMBER_KEYWORDS.contains(&token);
+                    if token == TOKEN_REM {
+                        tokens.extend_from_slice(&line[pos..]);
+                        pos = line.len();
+                    } else if token == TOKEN_DATA {
+                        let len = data_length(&line[pos..]);
+                        tokens.extend_from_slice(&line[pos..pos + len]);
+                        pos += len;
+                    }
+                }
+                None => {
+                    tokens.extend(line[pos..pos + len].iter().map(u8::to_ascii_uppercase));
+                    pos += len;
+                }
+            }
+        } else {
+            match token_of(&(b as char).to_string()).filter(|_| b.is_ascii()) {
+                Some(token) => {
+                    push_token(&mut tokens, token);
+                    expect_line_number = expect_line_number && token == TOKEN_MINUS;
+                }
+                None => tokens.push(b),
+            }
+            pos += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+fn next_is_digit(line: &[u8], pos: usize) -> bool {
+    line.get(pos + 1).is_some_and(|b| b.is_ascii_digit())
+}
+
+/// Reads a word and returns its token (if it is a keyword) and its length.
+///
+/// `FN` is a keyword even when it prefixes the function name,
+/// keywords ending in `$` or `(` include that character.
+fn read_word(s: &[u8]) -> (Option<u16>, usize) {
+    let len = s
+        .iter()
+        .take_while(|b| b.is_ascii_alphanumeric() || **b == b'.')
+        .count();
+    let word = String::from_utf8_lossy(&s[..len]).to_ascii_uppercase();
+    if let Some(suffix) = s.get(len) {
+        if *suffix == b'$' || *suffix == b'(' {
+            let extended = format!("{}{}", word, *suffix as char);
+            if let Some(token) = token_of(&extended) {
+                return (Some(token), len + 1);
+            }
+        }
+    }
+    if let Some(token) = token_of(&word) {
+        return (Some(token), len);
+    }
+    if word.starts_with("FN") {
+        return (Some(TOKEN_FN), 2);
+    }
+    (None, len)
+}
+
+/// The length of a DATA statement's contents, up to the next colon
+/// that is not inside a string.
+fn data_length(s: &[u8]) -> usize {
+    let mut in_string = false;
+    for (i, b) in s.iter().enumerate() {
+        if *b == b'"' {
+            in_string = !in_string;
+        } else if *b == b':' && !in_string {
+            return i;
+        }
+    }
+    s.len()
+}
+
+/// Tokenizes `&H` (hex), `&O` and `&` (octal) literals.
+fn tokenize_radix(s: &[u8], tokens: &mut Vec<u8>) -> io::Result<usize> {
+    let (token, radix, skip) = match s.get(1).map(u8::to_ascii_uppercase) {
+        Some(b'H') => (TOKEN_HEX, 16, 2),
+        Some(b'O') => (TOKEN_OCTAL, 8, 2),
+        _ => (TOKEN_OCTAL, 8, 1),
+    };
+    let digits = s[skip..]
+        .iter()
+        .take_while(|b| (**b as char).is_digit(radix))
+        .count();
+    if digits == 0 {
+        tokens.push(b'&');
+        return Ok(1);
+    }
+    let text = std::str::from_utf8(&s[skip..skip + digits]).unwrap();
+    let value = u16::from_str_radix(text, radix)
+        .map_err(|_| invalid_data(format!("Overflow in &{}", text)))?;
+    tokens.push(token);
+    tokens.extend_from_slice(&value.to_le_bytes());
+    Ok(skip + digits)
+}
+
+/// Tokenizes a decimal number literal, picking the narrowest type like
+/// GW-Basic does: integers up to 32767, singles up to 7 significant digits
+/// and doubles otherwise. The suffixes `%`, `!`, `#` and the `D` exponent
+/// force the type.
+fn tokenize_number(s: &[u8], tokens: &mut Vec<u8>) -> io::Result<usize> {
+    let mut len = s.iter().take_while(|b| b.is_ascii_digit()).count();
+    let mut is_float = false;
+    let mut is_double = false;
+    if s.get(len) == Some(&b'.') {
+        is_float = true;
+        len += 1;
+        len += s[len..].iter().take_while(|b| b.is_ascii_digit()).count();
+    }
+    let mantissa_len = len;
+    if let Some(e) = s.get(len).map(u8::to_ascii_uppercase) {
+        if e == b'E' || e == b'D' {
+            let sign = matches!(s.get(len + 1), Some(b'+') | Some(b'-')) as usize;
+            let exp_digits = s[len + 1 + sign..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count();
+            if exp_digits > 0 {
+                is_float = true;
+                is_double = e == b'D';
+                len += 1 + sign + exp_digits;
+            }
+        }
+    }
+    let text = String::from_utf8_lossy(&s[..len])
+        .to_ascii_uppercase()
+        .replace('D', "E");
+    let suffix = s.get(len).copied();
+    let consumed = len + matches!(suffix, Some(b'%') | Some(b'!') | Some(b'#')) as usize;
+    match suffix {
+        Some(b'#') => is_double = true,
+        Some(b'!') => is_float = true,
+        Some(b'%') => {}
+        _ if !is_double => {
+            let digits: String = s[..mantissa_len]
+                .iter()
+                .filter(|b| b.is_ascii_digit())
+                .map(|b| *b as char)
+                .collect();
+            is_double = digits.trim_start_matches('0').len() > 7;
+        }
+        _ => {}
+    }
+
+    if !is_float && !is_double {
+        if let Ok(value) = text.parse::<u16>() {
+            if value <= 32767 {
+                push_int(tokens, value);
+                return Ok(consumed);
+            }
+        }
+        if suffix == Some(b'%') {
+            return Err(invalid_data(format!("Overflow in {}%", text)));
+        }
+    }
+    let error = |_| invalid_data(format!("Invalid number {}", text));
+    if is_double {
+        let value: f64 = text.parse().map_err(error)?;
+        tokens.push(TOKEN_DOUBLE);
+        tokens.extend_from_slice(&f64_to_mbf_double(value)?);
+    } else {
+        let value: f32 = text.parse().map_err(error)?;
+        tokens.push(TOKEN_SINGLE);
+        tokens.extend_from_slice(&f32_to_mbf_single(value)?);
+    }
+    Ok(consumed)
+}
+
+fn push_int(tokens: &mut Vec<u8>, value: u16) {
+    if value <= 10 {
+        tokens.push(TOKEN_SMALL_INT_0 + value as u8);
+    } else if value <= 0xFF {
+        tokens.push(TOKEN_BYTE);
+        tokens.push(value as u8);
+    } else {
+        tokens.push(TOKEN_INT);
+        tokens.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Converts a Microsoft Binary Format single to IEEE.
+/// MBF has the sign next to the mantissa and an exponent biased by 129.
+fn mbf_single_to_f32(mbf: [u8; 4]) -> f32 {
+    let exponent = mbf[3] as u32;
+    if exponent <= 2 {
+        return 0.0;
+    }
+    let sign = (mbf[2] as u32 & 0x80) << 24;
+    let mantissa = (mbf[2] as u32 & 0x7F) << 16 | (mbf[1] as u32) << 8 | mbf[0] as u32;
+    f32::from_bits(sign | (exponent - 2) << 23 | mantissa)
+}
+
+fn f32_to_mbf_single(value: f32) -> io::Result<[u8; 4]> {
+    let bits = value.to_bits();
+    let exponent = (bits >> 23) & 0xFF;
+    if exponent == 0 {
+        return Ok([0; 4]);
+    }
+    if exponent + 2 > 0xFF {
+        return Err(invalid_data(format!("Overflow in {}", value)));
+    }
+    let sign = ((bits >> 31) << 7) as u8;
+    Ok([
+        bits as u8,
+        (bits >> 8) as u8,
+        ((bits >> 16) as u8 & 0x7F) | sign,
+        (exponent + 2) as u8,
+    ])
+}
+
+/// Converts a Microsoft Binary Format double to IEEE. MBF doubles have
+/// three more bits of mantissa, which are rounded away.
+fn mbf_double_to_f64(mbf: [u8; 8]) -> f64 {
+    let exponent = mbf[7] as u64;
+    if exponent == 0 {
+        return 0.0;
+    }
+    let mut raw = [0u8; 8];
+    raw[..7].copy_from_slice(&mbf[..7]);
+    let raw = u64::from_le_bytes(raw);
+    let sign = (raw >> 55) << 63;
+    let mut mantissa = ((raw & ((1 << 55) - 1)) + 4) >> 3;
+    let mut exponent = exponent + 1023 - 129;
+    if mantissa >> 52 != 0 {
+        mantissa &= (1 << 52) - 1;
+        exponent += 1;
+    }
+    f64::from_bits(sign | exponent << 52 | mantissa)
+}
+
+fn f64_to_mbf_double(value: f64) -> io::Result<[u8; 8]> {
+    let bits = value.to_bits();
+    let exponent = (bits >> 52) & 0x7FF;
+    if exponent == 0 {
+        return Ok([0; 8]);
+    }
+    if exponent + 129 < 1023 + 1 || exponent + 129 > 1023 + 0xFF {
+        return Err(invalid_data(format!("Overflow in {}", value)));
+    }
+    let sign = (bits >> 63) << 55;
+    let mantissa = (bits & ((1 << 52) - 1)) << 3;
+    let mut result = [0u8; 8];
+    result[..7].copy_from_slice(&(sign | mantissa).to_le_bytes()[..7]);
+    result[7] = (exponent + 129 - 1023) as u8;
+    Ok(result)
+}
+
+/// Formats a single so that it tokenizes back to the same value:
+/// a `!` suffix is added if the text would otherwise be read as an
+/// integer or a double.
+fn format_single(value: f32) -> String {
+    let (text, digits, is_integer) = format_decimal(&format!("{:e}", value), 7, 'E');
+    if (is_integer && value.abs() <= 32767.0) || digits > 7 {
+        format!("{}!", text)
+    } else {
+        text
+    }
+}
+
+/// Formats a double so that it tokenizes back to the same value:
+/// a `#` suffix is added if the text would otherwise be read as a single.
+fn format_double(value: f64) -> String {
+    let (text, digits, _) = format_decimal(&format!("{:e}", value), 16, 'D');
+    if digits <= 7 && !text.contains('D') {
+        format!("{}#", text)
+    } else {
+        text
+    }
+}
+
+/// Converts Rust's shortest scientific notation (e.g. `1.5e-3`) into
+/// GW-Basic's style (`.0015`), falling back to an exponent for very small
+/// or large numbers. Returns the text, the number of significant digits
+/// and whether the text looks like an integer.
+fn format_decimal(
+    scientific: &str,
+    max_integer_digits: i32,
+    exponent_letter: char,
+) -> (String, usize, bool) {
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(m) => ("-", m),
+        None => ("", mantissa),
+    };
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let count = digits.len() as i32;
+    let text = if (0..max_integer_digits).contains(&exponent) {
+        if count <= exponent + 1 {
+            format!("{}{}", digits, "0".repeat((exponent + 1 - count) as usize))
+        } else {
+            let (int_part, frac_part) = digits.split_at(exponent as usize + 1);
+            format!("{}.{}", int_part, frac_part)
+        }
+    } else if (-7..0).contains(&exponent) {
+        format!(".{}{}", "0".repeat((-exponent - 1) as usize), digits)
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        format!(
+            "{}{}{}{}{:+03}",
+            first, point, rest, exponent_letter, exponent
+        )
+    };
+    let is_integer = !text.contains(['.', 'E', 'D']);
+    let significant = if is_integer { text.len() } else { digits.len() };
+    (format!("{}{}", sign, text), significant, is_integer)
+}
+
+/// Reads the input and output file names of the tokenize/detokenize
+/// commands. Without an output file, the result is written to stdout.
+fn parse_command_args(args: &[String], command: &str) -> (String, Option<String>, bool) {
+    let protect = args.iter().any(|a| a == "--protect");
+    let files: Vec<&String> = args.iter().filter(|a| *a != "--protect").collect();
+    match files.as_slice() {
+        [input] => (input.to_string(), None, protect),
+        [input, output] => (input.to_string(), Some(output.to_string()), protect),
+        _ => panic!("Usage: basic-launcher-rust {} INPUT [OUTPUT]", command),
+    }
+}
+
+fn write_output(output: Option<String>, contents: &[u8]) -> io::Result<()> {
+    match output {
+        Some(file) => fs::write(file, contents),
+        None => io::stdout().write_all(contents),
+    }
+}
+
+/// `detokenize INPUT [OUTPUT]`
+pub fn detokenize_command(args: &[String]) -> io::Result<()> {
+    let (input, output, _) = parse_command_args(args, "detokenize");
+    let listing = detokenize(&fs::read(input)?)?;
+    write_output(output, &listing)
+}
+
+/// `tokenize INPUT [OUTPUT] [--protect]`
+pub fn tokenize_command(args: &[String]) -> io::Result<()> {
+    let (input, output, protected) = parse_command_args(args, "tokenize");
+    let tokenized = tokenize(&fs::read(input)?)?;
+    if protected {
+        write_output(output, &protect(&tokenized))
+    } else {
+        write_output(output, &tokenized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &[(&[u8], &[u8])] = &[
+        (
+            include_bytes!("../fixtures/tokenized/HELLO.BAS"),
+            include_bytes!("../fixtures/listing/HELLO.BAS"),
+        ),
+        (
+            include_bytes!("../fixtures/tokenized/CREATE.BAS"),
+            include_bytes!("../fixtures/listing/CREATE.BAS"),
+        ),
+        (
+            include_bytes!("../fixtures/tokenized/NUMBERS.BAS"),
+            include_bytes!("../fixtures/listing/NUMBERS.BAS"),
+        ),
+    ];
+
+    const PROTECTED_HELLO: &[u8] = include_bytes!("../fixtures/tokenized/HELLOP.BAS");
+
+    /// Git may check out the listings with either line ending.
+    fn normalize(listing: &[u8]) -> String {
+        String::from_utf8_lossy(listing).replace("\r\n", "\n")
+    }
+
+    #[test]
+    fn test_detokenize_fixtures() {
+        for (tokenized, listing) in FIXTURES {
+            let result = detokenize(tokenized).unwrap();
+            assert_eq!(normalize(&result), normalize(listing));
+        }
+    }
+
+    #[test]
+    fn test_detokenize_uses_crlf() {
+        let result = detokenize(FIXTURES[0].0).unwrap();
+        assert!(result.ends_with(b"SYSTEM\r\n"));
+    }
+
+    #[test]
+    fn test_tokenize_fixtures() {
+        for (tokenized, listing) in FIXTURES {
+            assert_eq!(tokenize(listing).unwrap(), tokenized.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_fixtures() {
+        for (tokenized, _) in FIXTURES {
+            let listing = detokenize(tokenized).unwrap();
+            assert_eq!(tokenize(&listing).unwrap(), tokenized.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_unprotect() {
+        assert_eq!(unprotect(PROTECTED_HELLO), FIXTURES[0].0.to_vec());
+    }
+
+    #[test]
+    fn test_protect() {
+        assert_eq!(protect(FIXTURES[0].0), PROTECTED_HELLO.to_vec());
+    }
+
+    #[test]
+    fn test_detokenize_protected() {
+        let result = detokenize(PROTECTED_HELLO).unwrap();
+        assert_eq!(normalize(&result), normalize(FIXTURES[0].1));
+    }
+
+    #[test]
+    fn test_detokenize_plain_text_is_an_error() {
+        assert!(detokenize(b"10 PRINT\r\n").is_err());
+    }
+
+    #[test]
+    fn test_detokenize_truncated_is_an_error() {
+        let tokenized = FIXTURES[0].0;
+        assert!(detokenize(&tokenized[..20]).is_err());
+    }
+
+    #[test]
+    fn test_tokenize_without_line_number_is_an_error() {
+        assert!(tokenize(b"PRINT \"hi\"\r\n").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_sorts_and_replaces_lines() {
+        let tokenized = tokenize(b"20 SYSTEM\r\n10 PRINT 1\r\n20 END\r\n").unwrap();
+        let listing = detokenize(&tokenized).unwrap();
+        assert_eq!(normalize(&listing), "10 PRINT 1\n20 END\n");
+    }
+
+    #[test]
+    fn test_tokenize_normalizes_case_and_shortcuts() {
+        let tokenized = tokenize(b"10 ? a$:print \"x\"\r\n").unwrap();
+        let listing = detokenize(&tokenized).unwrap();
+        assert_eq!(normalize(&listing), "10 PRINT A$:PRINT \"x\"\n");
+    }
+
+    #[test]
+    fn test_tokenize_fn() {
+        let tokens = tokenize_line(b"PRINT FNA(1)").unwrap();
+        assert_eq!(tokens, vec![0x91, b' ', 0xD1, b'A', b'(', 0x12, b')']);
+    }
+
+    #[test]
+    fn test_tokenize_numbers() {
+        assert_eq!(tokenize_line(b"0").unwrap(), vec![0x11]);
+        assert_eq!(tokenize_line(b"10").unwrap(), vec![0x1B]);
+        assert_eq!(tokenize_line(b"11").unwrap(), vec![0x0F, 11]);
+        assert_eq!(tokenize_line(b"32767").unwrap(), vec![0x1C, 0xFF, 0x7F]);
+        assert_eq!(tokenize_line(b"5%").unwrap(), vec![0x16]);
+        assert_eq!(tokenize_line(b"5!").unwrap()[0], TOKEN_SINGLE);
+        assert_eq!(tokenize_line(b"5#").unwrap()[0], TOKEN_DOUBLE);
+        assert_eq!(tokenize_line(b"1D3").unwrap()[0], TOKEN_DOUBLE);
+        assert_eq!(tokenize_line(b"12345678").unwrap()[0], TOKEN_DOUBLE);
+        assert!(tokenize_line(b"40000%").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_line_number_ranges() {
+        let tokens = tokenize_line(b"LIST 10-20").unwrap();
+        assert_eq!(tokens, vec![0x93, b' ', 0x0E, 10, 0, 0xEA, 0x0E, 20, 0]);
+    }
+
+    #[test]
+    fn test_format_single() {
+        assert_eq!(format_single(1.5), "1.5");
+        assert_eq!(format_single(5.0), "5!");
+        assert_eq!(format_single(40000.0), "40000");
+        assert_eq!(format_single(0.25), ".25");
+        assert_eq!(format_single(1e-8), "1E-08");
+        assert_eq!(format_single(1e7), "1E+07");
+        assert_eq!(format_single(1234567.0), "1234567");
+        assert_eq!(format_single(32767.0), "32767!");
+        assert_eq!(format_single(0.1234567), ".1234567");
+        assert_eq!(format_single(16777215.0), "1.6777215E+07!");
+    }
+
+    #[test]
+    fn test_format_double() {
+        assert_eq!(format_double(1.5), "1.5#");
+        assert_eq!(format_double(123456789.0), "123456789");
+        assert_eq!(format_double(0.1), ".1#");
+        assert_eq!(format_double(1e20), "1D+20");
+    }
+
+    #[test]
+    fn test_number_round_trip() {
+        for text in &[
+            "1.5",
+            "5!",
+            ".001",
+            "1E+10",
+            "3.141593",
+            "1.5#",
+            ".1#",
+            "1D+20",
+            "3.14159265358979",
+        ] {
+            let tokens = tokenize_line(text.as_bytes()).unwrap();
+            let mut line = vec![];
+            line.extend_from_slice(&[0xFF, 1, 1, 10, 0]);
+            line.extend_from_slice(&tokens);
+            line.extend_from_slice(&[0, 0, 0]);
+            let listing = detokenize(&line).unwrap();
+            assert_eq!(normalize(&listing), format!("10 {}\n", text));
+        }
+    }
+
+    #[test]
+    fn test_mbf_single() {
+        assert_eq!(f32_to_mbf_single(1.5).unwrap(), [0x00, 0x00, 0x40, 0x81]);
+        assert_eq!(f32_to_mbf_single(-1.5).unwrap(), [0x00, 0x00, 0xC0, 0x81]);
+        assert_eq!(mbf_single_to_f32([0x00, 0x00, 0x40, 0x81]), 1.5);
+        assert_eq!(mbf_single_to_f32([0x00, 0x00, 0x00, 0x00]), 0.0);
+    }
+
+    #[test]
+    fn test_mbf_double() {
+        let mbf = f64_to_mbf_double(1.5).unwrap();
+        assert_eq!(mbf, [0, 0, 0, 0, 0, 0, 0x40, 0x81]);
+        assert_eq!(mbf_double_to_f64(mbf), 1.5);
+        assert_eq!(mbf_double_to_f64(f64_to_mbf_double(0.1).unwrap()), 0.1);
+    }
+}
@@ -0,0 +1,405 @@
+//! Runs the whole launcher pipeline against the built-in fake DOSBox:
+//! batch file generation, the environment, stdin and stdout, timeouts and
+//! the cleanup of the temporary files.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// A directory with a fake interpreter in `BIN` and programs in `SRC`.
+struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Self {
+        let root = env::temp_dir().join(format!("blr-it-{}", name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("BIN")).unwrap();
+        fs::create_dir_all(root.join("SRC")).unwrap();
+        fs::write(root.join("BIN").join("GWBASIC.EXE"), "").unwrap();
+        Self { root }
+    }
+
+    fn program(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.root.join("SRC").join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn launcher(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_basic-launcher-rust"));
+        command
+            .env_clear()
+            .env("PATH", env::var_os("PATH").unwrap_or_default())
+            .env("BLR_DOSBOX", "fake")
+            .env("BLR_GWBASIC", self.root.join("BIN").join("GWBASIC.EXE"))
+            .env("BLR_TIMEOUT", "10")
+            .current_dir(&self.root);
+        command
+    }
+
+    /// The files left in the root, i.e. the temporary files.
+    fn leftovers(&self) -> Vec<String> {
+        let mut result: Vec<String> = fs::read_dir(&self.root)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name != "BIN" && name != "SRC")
+            .collect();
+        result.sort();
+        result
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn run(mut command: Command, stdin: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "launcher failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn test_hello() {
+    let sandbox = Sandbox::new("hello");
+    let program = sandbox.program("HELLO.BAS", "10 PRINT \"Hello\"\r\n20 SYSTEM\r\n");
+    let mut command = sandbox.launcher();
+    command.arg(program);
+    assert_eq!(stdout(&run(command, "")), "Hello\n");
+    assert_eq!(sandbox.leftovers(), Vec::<String>::new());
+}
+
+#[test]
+fn test_env_forwarding() {
+    let sandbox = Sandbox::new("env");
+    let program = sandbox.program(
+        "ENV.BAS",
+        "10 PRINT ENVIRON$(\"REQUEST_METHOD\")\n20 PRINT ENVIRON$(\"QUERY_STRING\")\n30 PRINT \"[\"; ENVIRON$(\"HTTP_HOST\"); \"]\"\n40 SYSTEM\n",
+    );
+    let mut command = sandbox.launcher();
+    command
+        .arg(program)
+        .env("REQUEST_METHOD", "GET")
+        .env("QUERY_STRING", "q=100%25&m=%REQUEST_METHOD%")
+        .env("HTTP_HOST", "example.com");
+    // the fake expands % like COMMAND.COM, so this checks the escaping
+    assert_eq!(
+        stdout(&run(command, "")),
+        "GET\nq=100%25&m=%REQUEST_METHOD%\n[]\n"
+    );
+}
+
+#[test]
+fn test_env_forwarding_after_redirects() {
+    let sandbox = Sandbox::new("env-redirect");
+    let program = sandbox.program(
+        "ENV.BAS",
+        "10 PRINT ENVIRON$(\"REQUEST_METHOD\"); \"|\"; ENVIRON$(\"QUERY_STRING\")\n20 SYSTEM\n",
+    );
+    let run_with_depth = |depth: &str| {
+        let mut command = sandbox.launcher();
+        command
+            .arg(&program)
+            .env("REQUEST_METHOD", "GET")
+            .env("REDIRECT_REQUEST_METHOD", "POST")
+            .env("QUERY_STRING", "")
+            .env("REDIRECT_REDIRECT_REDIRECT_QUERY_STRING", "id=1")
+            .env("BLR_REDIRECT_DEPTH", depth);
+        stdout(&run(command, ""))
+    };
+    assert_eq!(run_with_depth("2"), "POST|\n");
+    assert_eq!(run_with_depth("3"), "POST|id=1\n");
+}
+
+#[test]
+fn test_stdin_and_stdout() {
+    let sandbox = Sandbox::new("stdin");
+    let program = sandbox.program(
+        "ECHO.BAS",
+        "10 OPEN ENVIRON$(\"STDIN\") FOR INPUT ACCESS READ AS #1\n20 WHILE NOT EOF(1)\n30 LINE INPUT #1, T$\n40 PRINT T$\n50 WEND\n60 CLOSE #1\n70 SYSTEM\n",
+    );
+    let mut command = sandbox.launcher();
+    command.arg(program).env("CONTENT_LENGTH", "8");
+    assert_eq!(stdout(&run(command, "one\ntwo\n")), "one\ntwo\n");
+}
+
+#[test]
+fn test_recording_keeps_the_body_as_sent() {
+    let sandbox = Sandbox::new("record");
+    let program = sandbox.program("HELLO.BAS", "10 PRINT \"Hello\"\n20 SYSTEM\n");
+    let record_dir = sandbox.root.join("RECORD");
+    let mut command = sandbox.launcher();
+    command
+        .arg(program)
+        .env("CONTENT_LENGTH", "7")
+        .env("BLR_RECORD_DIR", &record_dir);
+    assert_eq!(stdout(&run(command, "one\ntwo")), "Hello\n");
+    let recording = fs::read_dir(&record_dir).unwrap().next().unwrap().unwrap();
+    assert_eq!(
+        fs::read(recording.path().join("stdin.txt")).unwrap(),
+        b"one\ntwo"
+    );
+}
+
+#[test]
+fn test_stdin_is_empty_without_content_length() {
+    let sandbox = Sandbox::new("no-stdin");
+    let program = sandbox.program(
+        "ECHO.BAS",
+        "10 OPEN ENVIRON$(\"STDIN\") FOR INPUT AS #1\n20 PRINT EOF(1)\n30 SYSTEM\n",
+    );
+    let mut command = sandbox.launcher();
+    command.arg(program);
+    assert_eq!(stdout(&run(command, "ignored\n")), "-1\n");
+}
+
+#[test]
+fn test_program_arguments() {
+    let sandbox = Sandbox::new("args");
+    let program = sandbox.program(
+        "ARGS.BAS",
+        "10 PRINT ENVIRON$(\"ARGC\"); \"|\"; ENVIRON$(\"ARG1\")\n20 OPEN ENVIRON$(\"ARGS\") FOR INPUT AS #1\n30 WHILE NOT EOF(1)\n40 LINE INPUT #1, A$\n50 PRINT A$\n60 WEND\n70 SYSTEM\n",
+    );
+    let mut command = sandbox.launcher();
+    command.arg(program).args(["--", "a b", "x|y"]);
+    assert_eq!(stdout(&run(command, "")), "2|a b\na b\nx|y\n");
+}
+
+#[test]
+fn test_program_in_long_directory_is_mounted() {
+    let sandbox = Sandbox::new("mount");
+    let program = sandbox.program("long name/HELLO.BAS", "10 PRINT \"mounted\"\n20 SYSTEM\n");
+    let mut command = sandbox.launcher();
+    command.arg(program).env("BLR_NO_CLEANUP", "1");
+    assert_eq!(stdout(&run(command, "")), "mounted\n");
+    let batch = sandbox
+        .leftovers()
+        .into_iter()
+        .find(|name| name.ends_with(".BAT"))
+        .unwrap();
+    let batch = fs::read_to_string(sandbox.root.join(batch)).unwrap();
+    assert!(batch.starts_with("MOUNT D \""), "{}", batch);
+    assert!(batch.contains("\r\nD:\r\nCD D:\\\r\n"), "{}", batch);
+}
+
+#[test]
+fn test_no_cleanup_keeps_temp_files() {
+    let sandbox = Sandbox::new("no-cleanup");
+    let program = sandbox.program("HELLO.BAS", "10 PRINT \"Hello\"\n20 SYSTEM\n");
+    let mut command = sandbox.launcher();
+    command.arg(program).env("BLR_NO_CLEANUP", "1");
+    stdout(&run(command, ""));
+    let extensions: Vec<String> = sandbox
+        .leftovers()
+        .iter()
+        .map(|name| {
+            Path::new(name)
+                .extension()
+                .unwrap()
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+    assert_eq!(
+        extensions,
+        ["ARG", "BAT", "BOT", "ERR", "INP", "LOG", "LST", "OUT"]
+    );
+}
+
+#[test]
+fn test_json_log_file() {
+    let sandbox = Sandbox::new("log");
+    let program = sandbox.program("HELLO.BAS", "10 PRINT \"Hello\"\n20 SYSTEM\n");
+    let log_file = sandbox.root.join("BIN").join("blr.log");
+    let mut command = sandbox.launcher();
+    command
+        .arg(program)
+        .env("BLR_LOG", "info")
+        .env("BLR_LOG_FILE", &log_file)
+        .env("UNIQUE_ID", "abc123")
+        .env("CONTENT_LENGTH", "3");
+    let output = run(command, "hi\n");
+    assert_eq!(stdout(&output), "Hello\n");
+    assert!(output.stderr.is_empty());
+    let log = fs::read_to_string(&log_file).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", log);
+    for expected in [
+        "\"level\":\"info\"",
+        "\"message\":\"launch finished\"",
+        "\"request_id\":\"abc123\"",
+        "\"mode\":\"GWBasic\"",
+        "\"backend\":\"fake\"",
+        "\"exit_status\":0",
+        "\"stdin_bytes\":4",
+        "\"stdout_bytes\":7",
+        "\"dosbox_ms\":",
+    ] {
+        assert!(lines[0].contains(expected), "{} in {}", expected, lines[0]);
+    }
+}
+
+#[test]
+fn test_server_timing_header() {
+    let sandbox = Sandbox::new("server-timing");
+    let program = sandbox.program(
+        "CGI.BAS",
+        "10 PRINT \"Content-Type: text/plain\"\n20 PRINT\n30 PRINT \"ok\"\n40 SYSTEM\n",
+    );
+    let mut command = sandbox.launcher();
+    command
+        .arg(&program)
+        .env("GATEWAY_INTERFACE", "CGI/1.1")
+        .env("BLR_SERVER_TIMING", "1");
+    let output = stdout(&run(command, ""));
+    let header = output
+        .lines()
+        .find(|line| line.starts_with("Server-Timing: "))
+        .unwrap_or_else(|| panic!("no Server-Timing header in {}", output));
+    for phase in [
+        "batch;dur=",
+        "stdin;dur=",
+        "dosbox;dur=",
+        "dosbox_spawn;dur=",
+        "dosbox_boot;dur=",
+        "dosbox_run;dur=",
+        "total;dur=",
+    ] {
+        assert!(header.contains(phase), "{} in {}", phase, header);
+    }
+    assert!(output.ends_with("\nok\n"), "{}", output);
+
+    let mut command = sandbox.launcher();
+    command.arg(&program).env("GATEWAY_INTERFACE", "CGI/1.1");
+    assert!(!stdout(&run(command, "")).contains("Server-Timing"));
+}
+
+#[test]
+fn test_no_log_by_default() {
+    let sandbox = Sandbox::new("no-log");
+    let program = sandbox.program("HELLO.BAS", "10 PRINT \"Hello\"\n20 SYSTEM\n");
+    let mut command = sandbox.launcher();
+    command.arg(program);
+    assert!(run(command, "").stderr.is_empty());
+}
+
+#[test]
+fn test_timeout_kills_program_stuck_at_prompt() {
+    let sandbox = Sandbox::new("timeout");
+    // no SYSTEM, so the interpreter waits at its prompt forever
+    let program = sandbox.program("STUCK.BAS", "10 PRINT \"Hello\"\n20 END\n");
+    let mut command = sandbox.launcher();
+    command.arg(program).env("BLR_TIMEOUT", "0.5");
+    let start = Instant::now();
+    let output = run(command, "");
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("DOSBox did not finish within"));
+    assert_eq!(sandbox.leftovers(), Vec::<String>::new());
+}
+
+#[test]
+fn test_runtime_error_is_printed_like_the_interpreter() {
+    let sandbox = Sandbox::new("error");
+    let program = sandbox.program(
+        "ERROR.BAS",
+        "10 OPEN \"NOPE.DAT\" FOR INPUT AS #1\n20 SYSTEM\n",
+    );
+    let mut command = sandbox.launcher();
+    command
+        .arg(program)
+        .env("BLR_TIMEOUT", "0.5")
+        .env("BLR_NO_CLEANUP", "1");
+    let output = run(command, "");
+    assert!(!output.status.success());
+    let out = sandbox
+        .leftovers()
+        .into_iter()
+        .find(|name| name.ends_with(".OUT"))
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(sandbox.root.join(out)).unwrap(),
+        "File not found in 10\r\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_scripted_responder() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sandbox = Sandbox::new("script");
+    let program = sandbox.program("ANY.BAS", "10 SYSTEM\n");
+    let script = sandbox.root.join("BIN").join("respond.sh");
+    fs::write(
+        &script,
+        "#!/bin/sh\nprintf 'Status: 201 Created\\r\\n\\r\\n'\nprintf '%s %s ' \"$(basename \"$1\")\" \"$REQUEST_METHOD\"\ncat\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut command = sandbox.launcher();
+    command
+        .arg(program)
+        .env("BLR_FAKE_SCRIPT", &script)
+        .env("REQUEST_METHOD", "POST")
+        .env("CONTENT_LENGTH", "4");
+    assert_eq!(
+        stdout(&run(command, "body\n")),
+        "Status: 201 Created\n\nANY.BAS POST body\n"
+    );
+}
+
+/// The golden-output tests of the sample programs pass against the fake.
+#[test]
+fn test_sample_case_files() {
+    let sandbox = Sandbox::new("cases");
+    let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("../basic");
+    for dir in ["src", "rest"] {
+        for entry in fs::read_dir(samples.join(dir)).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if name.ends_with(".BAS") || name.ends_with(".case.toml") {
+                let target = sandbox.root.join("SRC").join(dir.to_uppercase()).join(name);
+                fs::create_dir_all(target.parent().unwrap()).unwrap();
+                fs::copy(&path, target).unwrap();
+            }
+        }
+    }
+    let mut command = sandbox.launcher();
+    command.arg("test").arg(sandbox.root.join("SRC"));
+    let output = run(command, "");
+    assert!(
+        output.status.success(),
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
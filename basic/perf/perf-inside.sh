@@ -1,23 +1,40 @@
 #!/bin/bash
 set -e
 
-COUNT=$1
+# Usage: perf-inside.sh PROGRAM COUNT WARMUP [--quiet]
+# Prints "elapsed_ns N" for each measured iteration on stdout, everything
+# else goes to stderr.
+PROGRAM=$1
+COUNT=$2
+WARMUP=$3
 
-if [[ "$BLR_BASIC_MODE" == "qbasic" ]]; then
-  PROGRAM=/basic/src/HELLOQB.BAS
-else
-  PROGRAM=/basic/src/HELLO.BAS
-fi
-
-if [[ "$2" == "--quiet" ]]; then
-  while [[ $COUNT -gt 0 ]]; do
-    printf '.'
+launch() {
+  if [[ "$QUIET" == "1" ]]; then
     /usr/local/bin/basic-launcher-rust $PROGRAM > /dev/null
-    COUNT=$((COUNT-1))
-  done
-else
-  while [[ $COUNT -gt 0 ]]; do
-    /usr/local/bin/basic-launcher-rust $PROGRAM
-    COUNT=$((COUNT-1))
-  done
+  else
+    /usr/local/bin/basic-launcher-rust $PROGRAM >&2
+  fi
+}
+
+if [[ "$4" == "--quiet" ]]; then
+  QUIET=1
 fi
+
+while [[ $WARMUP -gt 0 ]]; do
+  launch
+  WARMUP=$((WARMUP-1))
+done
+
+N=1
+while [[ $N -le $COUNT ]]; do
+  if [[ "$QUIET" == "1" ]]; then
+    printf '.' >&2
+  else
+    echo $N >&2
+  fi
+  START=$(date +%s%N)
+  launch
+  STOP=$(date +%s%N)
+  echo "elapsed_ns $((STOP-START))"
+  N=$((N+1))
+done
@@ -0,0 +1,395 @@
+use std::fmt::Write as _;
+use std::process::exit;
+
+use crate::results::{self, Results};
+use crate::stats::Stats;
+
+/// Differences with a lower p-value are considered significant.
+const SIGNIFICANCE_LEVEL: f64 = 0.05;
+
+/// How an experiment changed between two runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delta {
+    pub name: String,
+    pub baseline: f64,
+    pub current: f64,
+    /// The change of the mean, in percent of the baseline.
+    pub change: f64,
+    /// The two-sided p-value of Welch's t-test, `None` if either run has a
+    /// single iteration.
+    pub p_value: Option<f64>,
+}
+
+impl Delta {
+    pub fn is_significant(&self) -> bool {
+        self.p_value.is_some_and(|p| p < SIGNIFICANCE_LEVEL)
+    }
+
+    /// Slower by more than `max_regression` percent, and not by chance.
+    pub fn is_regression(&self, max_regression: f64) -> bool {
+        self.change > max_regression && self.is_significant()
+    }
+
+    /// Whether the t-test can tell a regression from noise, which takes at
+    /// least two iterations in both runs.
+    pub fn is_testable(&self) -> bool {
+        self.p_value.is_some()
+    }
+
+    fn verdict(&self, max_regression: Option<f64>) -> &'static str {
+        if max_regression.is_some_and(|max| self.is_regression(max)) {
+            "REGRESSION"
+        } else if max_regression.is_some() && !self.is_testable() {
+            "too few iterations"
+        } else if !self.is_significant() {
+            "no significant change"
+        } else if self.change > 0.0 {
+            "slower"
+        } else {
+            "faster"
+        }
+    }
+}
+
+/// The result of comparing two runs, matching experiments by name.
+#[derive(Debug, Default)]
+pub struct Comparison {
+    pub deltas: Vec<Delta>,
+    pub only_in_baseline: Vec<String>,
+    pub only_in_current: Vec<String>,
+}
+
+pub fn compare(baseline: &Results, current: &Results) -> Comparison {
+    let mut result = Comparison::default();
+    for b in &baseline.experiments {
+        match current.experiments.iter().find(|c| c.name == b.name) {
+            Some(c) => result.deltas.push(Delta {
+                name: b.name.clone(),
+                baseline: b.stats.mean,
+                current: c.stats.mean,
+                change: (c.stats.mean - b.stats.mean) / b.stats.mean * 100.0,
+                p_value: welch_t_test(&b.stats, &c.stats),
+            }),
+            None => result.only_in_baseline.push(b.name.clone()),
+        }
+    }
+    for c in &current.experiments {
+        if !baseline.experiments.iter().any(|b| b.name == c.name) {
+            result.only_in_current.push(c.name.clone());
+        }
+    }
+    result
+}
+
+impl Comparison {
+    pub fn regressions(&self, max_regression: f64) -> Vec<&Delta> {
+        self.deltas
+            .iter()
+            .filter(|d| d.is_regression(max_regression))
+            .collect()
+    }
+
+    /// The experiments that cannot be gated on, see `Delta::is_testable`.
+    pub fn untestable(&self) -> Vec<&Delta> {
+        self.deltas.iter().filter(|d| !d.is_testable()).collect()
+    }
+
+    /// Renders a markdown table with a row per experiment in both runs.
+    pub fn render(&self, max_regression: Option<f64>) -> String {
+        let mut result = String::new();
+        writeln!(
+            result,
+            "| Experiment           | Baseline | Current |  Change | p-value | Verdict"
+        )
+        .unwrap();
+        writeln!(
+            result,
+            "| -------------------- | -------: | ------: | ------: | ------: | -------"
+        )
+        .unwrap();
+        for d in &self.deltas {
+            writeln!(
+                result,
+                "| {:20} | {:8.1} | {:7.1} | {:+6.1}% | {:>7} | {}",
+                d.name,
+                d.baseline,
+                d.current,
+                d.change,
+                d.p_value
+                    .map(|p| format!("{:.3}", p))
+                    .unwrap_or_else(|| "-".to_string()),
+                d.verdict(max_regression)
+            )
+            .unwrap();
+        }
+        for name in &self.only_in_baseline {
+            writeln!(result, "Only in the baseline: {}", name).unwrap();
+        }
+        for name in &self.only_in_current {
+            writeln!(result, "Not in the baseline: {}", name).unwrap();
+        }
+        result
+    }
+}
+
+/// Parses a threshold like `10%` or `10`, in percent.
+pub fn parse_percent(value: &str) -> f64 {
+    value
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid percentage {}", value))
+}
+
+/// Prints the comparison and exits with 1 if an experiment regressed beyond
+/// `max_regression` percent, or if an experiment has too few iterations to
+/// tell.
+pub fn check(baseline: &Results, current: &Results, max_regression: Option<f64>) {
+    let comparison = compare(baseline, current);
+    print!("{}", comparison.render(max_regression));
+    if let Some(max_regression) = max_regression {
+        let untestable = comparison.untestable();
+        if !untestable.is_empty() {
+            let names: Vec<&str> = untestable.iter().map(|d| d.name.as_str()).collect();
+            eprintln!(
+                "Cannot check for regressions with fewer than 2 iterations in both runs, run with --count 2 or more: {}",
+                names.join(", ")
+            );
+            exit(1);
+        }
+        let regressions = comparison.regressions(max_regression);
+        if !regressions.is_empty() {
+            let names: Vec<&str> = regressions.iter().map(|d| d.name.as_str()).collect();
+            eprintln!(
+                "Regressed by more than {}%: {}",
+                max_regression,
+                names.join(", ")
+            );
+            exit(1);
+        }
+    }
+}
+
+/// Implements the `compare` command: `compare BASELINE CURRENT [--max-regression PERCENT]`.
+pub fn compare_command(args: &[String]) {
+    let mut files: Vec<&str> = vec![];
+    let mut max_regression: Option<f64> = None;
+    let mut iterator = args.iter();
+    while let Some(value) = iterator.next() {
+        if value == "--max-regression" {
+            max_regression = match iterator.next() {
+                Some(x) => Some(parse_percent(x)),
+                None => panic!("--max-regression requires an argument"),
+            };
+        } else {
+            files.push(value);
+        }
+    }
+    if files.len() != 2 {
+        panic!("Usage: perf compare BASELINE CURRENT [--max-regression PERCENT]");
+    }
+    check(
+        &results::read(files[0]),
+        &results::read(files[1]),
+        max_regression,
+    );
+}
+
+/// The two-sided p-value of Welch's t-test for the means of two runs, which
+/// doesn't assume that their variances are equal.
+pub fn welch_t_test(a: &Stats, b: &Stats) -> Option<f64> {
+    if a.count < 2 || b.count < 2 {
+        return None;
+    }
+    let va = a.stddev.powi(2) / a.count as f64;
+    let vb = b.stddev.powi(2) / b.count as f64;
+    if va + vb == 0.0 {
+        return Some(if a.mean == b.mean { 1.0 } else { 0.0 });
+    }
+    let t = (b.mean - a.mean) / (va + vb).sqrt();
+    let df =
+        (va + vb).powi(2) / (va.powi(2) / (a.count - 1) as f64 + vb.powi(2) / (b.count - 1) as f64);
+    Some(incomplete_beta(df / 2.0, 0.5, df / (df + t * t)))
+}
+
+/// The natural logarithm of the gamma function (Lanczos approximation).
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let mut y = x;
+    let mut series = 1.000000000190015;
+    for c in COEFFICIENTS {
+        y += 1.0;
+        series += c / y;
+    }
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+/// The regularized incomplete beta function `I_x(a, b)`.
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // the continued fraction converges quickly on this side
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Evaluates the continued fraction of the incomplete beta function with
+/// the modified Lentz's method.
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-30;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..200 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        let even = m * (b - m) * x / ((a + m2 - 1.0) * (a + m2));
+        d = 1.0 + even * d;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = 1.0 + even / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        h *= d * c;
+        let odd = -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0));
+        d = 1.0 + odd * d;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = 1.0 + odd / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::tests::sample_results;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn stats(samples: &[f64]) -> Stats {
+        Stats::from_samples(samples)
+    }
+
+    #[test]
+    fn test_incomplete_beta() {
+        assert_close(incomplete_beta(1.0, 1.0, 0.3), 0.3);
+        assert_close(incomplete_beta(2.0, 3.0, 0.4), 0.5248);
+        // two-sided p-value of t = 2 with 10 degrees of freedom
+        assert_close(incomplete_beta(5.0, 0.5, 10.0 / 14.0), 0.0734);
+    }
+
+    #[test]
+    fn test_welch_t_test() {
+        let a = stats(&[1000.0, 1010.0, 990.0, 1005.0, 995.0]);
+        let same = stats(&[1002.0, 1008.0, 992.0, 1003.0, 997.0]);
+        let slower = stats(&[1100.0, 1110.0, 1090.0, 1105.0, 1095.0]);
+        assert!(welch_t_test(&a, &same).unwrap() > 0.5);
+        assert!(welch_t_test(&a, &slower).unwrap() < 0.001);
+        assert_eq!(welch_t_test(&a, &stats(&[1000.0])), None);
+        assert_eq!(
+            welch_t_test(&stats(&[1.0, 1.0]), &stats(&[1.0, 1.0])),
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        let baseline = sample_results();
+        let mut current = sample_results();
+        current.experiments[0].stats = stats(&[1400.0, 1420.0, 1410.0]);
+        let mut extra = current.experiments[0].clone();
+        extra.name = "Extra".to_string();
+        current.experiments.push(extra);
+        let comparison = compare(&baseline, &current);
+        assert_eq!(comparison.deltas.len(), 1);
+        let delta = &comparison.deltas[0];
+        assert_close(delta.change, 28.1818);
+        assert!(delta.is_significant(), "{:?}", delta);
+        assert!(delta.is_regression(10.0));
+        assert!(!delta.is_regression(30.0));
+        assert_eq!(comparison.only_in_current, ["Extra"]);
+        let rendered = comparison.render(Some(10.0));
+        assert!(
+            rendered.contains("| DOS                  |   1100.0 |  1410.0 |  +28.2% |"),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains("REGRESSION"), "{}", rendered);
+        assert!(
+            rendered.contains("Not in the baseline: Extra"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn test_noise_is_not_a_regression() {
+        let baseline = sample_results();
+        let mut current = sample_results();
+        current.experiments[0].stats = stats(&[900.0, 1500.0, 1300.0]);
+        let delta = &compare(&baseline, &current).deltas[0];
+        assert!(delta.change > 10.0);
+        assert!(!delta.is_regression(10.0), "{:?}", delta);
+    }
+
+    #[test]
+    fn test_single_iteration_cannot_be_gated() {
+        let baseline = sample_results();
+        let mut current = sample_results();
+        current.experiments[0].stats = stats(&[2000.0]);
+        let comparison = compare(&baseline, &current);
+        assert!(!comparison.deltas[0].is_regression(10.0));
+        assert_eq!(comparison.untestable().len(), 1);
+        let rendered = comparison.render(Some(10.0));
+        assert!(rendered.contains("too few iterations"), "{}", rendered);
+        assert!(!comparison.render(None).contains("too few iterations"));
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("10%"), 10.0);
+        assert_eq!(parse_percent("2.5"), 2.5);
+    }
+}
@@ -0,0 +1,677 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// How long a request took, phase by phase.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timing {
+    /// Opening the connection, zero if an open one was reused.
+    pub connect: Duration,
+    /// Writing the request.
+    pub send: Duration,
+    /// From the request being written until the first byte of the response.
+    pub first_byte: Duration,
+    /// From the first byte until the end of the response.
+    pub receive: Duration,
+}
+
+impl Timing {
+    pub fn total(&self) -> Duration {
+        self.connect + self.send + self.first_byte + self.receive
+    }
+}
+
+/// The mean of each phase of the requests of an experiment, in milliseconds.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct PhaseSummary {
+    pub connect_ms: f64,
+    pub send_ms: f64,
+    pub first_byte_ms: f64,
+    pub receive_ms: f64,
+}
+
+impl PhaseSummary {
+    /// `None` if there are no timings.
+    pub fn from_timings(timings: &[Timing]) -> Option<Self> {
+        if timings.is_empty() {
+            return None;
+        }
+        let mean = |phase: fn(&Timing) -> Duration| {
+            timings
+                .iter()
+                .map(|t| phase(t).as_secs_f64() * 1000.0)
+                .sum::<f64>()
+                / timings.len() as f64
+        };
+        Some(Self {
+            connect_ms: mean(|t| t.connect),
+            send_ms: mean(|t| t.send),
+            first_byte_ms: mean(|t| t.first_byte),
+            receive_ms: mean(|t| t.receive),
+        })
+    }
+}
+
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub headers: Vec<(&'a str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl<'a> Request<'a> {
+    pub fn get(path: &'a str) -> Self {
+        Self {
+            method: "GET",
+            path,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    pub fn delete(path: &'a str) -> Self {
+        Self {
+            method: "DELETE",
+            ..Self::get(path)
+        }
+    }
+
+    pub fn post(path: &'a str, content_type: &str, body: &str) -> Self {
+        Self {
+            method: "POST",
+            path,
+            headers: vec![("Content-Type", content_type.to_string())],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub timing: Timing,
+}
+
+impl Response {
+    /// The value of a header, ignoring the case of its name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// A minimal HTTP/1.1 client for one server, e.g. `http://localhost:8080`.
+/// It can keep the connection open between requests.
+pub struct Client {
+    address: String,
+    keep_alive: bool,
+    timeout: Duration,
+    connection: Option<BufReader<TcpStream>>,
+}
+
+impl Client {
+    pub fn new(base_url: &str, keep_alive: bool) -> Self {
+        let address = base_url
+            .strip_prefix("http://")
+            .unwrap_or_else(|| panic!("Only http:// URLs are supported: {}", base_url))
+            .trim_end_matches('/')
+            .to_string();
+        Self {
+            address,
+            keep_alive,
+            timeout: Duration::from_secs(60),
+            connection: None,
+        }
+    }
+
+    /// How long to wait for the server to accept the connection, to take the
+    /// request and to answer, 60 seconds by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn send(&mut self, request: &Request) -> io::Result<Response> {
+        if self.connection.is_some() {
+            // the server may have closed an idle connection in the meantime
+            match self.send_on_connection(request, Duration::ZERO, true) {
+                Ok(response) => return Ok(response),
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let start = Instant::now();
+        let stream = self.connect()?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.set_nodelay(true)?;
+        self.connection = Some(BufReader::new(stream));
+        self.send_on_connection(request, start.elapsed(), false)
+    }
+
+    /// Connects to the first address of the server that accepts.
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No address for {}", self.address),
+            )
+        }))
+    }
+
+    /// When a `reused` connection turns out to be closed before the server
+    /// got the request, or before it answered a request that is safe to send
+    /// again, the failure is reported as `ConnectionAborted`, so that the
+    /// request can be sent again on a new connection.
+    fn send_on_connection(
+        &mut self,
+        request: &Request,
+        connect: Duration,
+        reused: bool,
+    ) -> io::Result<Response> {
+        let result = self.exchange(request, connect, reused);
+        match &result {
+            Ok(response) if self.keep_alive && !closes(response) => {}
+            _ => self.connection = None,
+        }
+        result
+    }
+
+    fn exchange(
+        &mut self,
+        request: &Request,
+        connect: Duration,
+        reused: bool,
+    ) -> io::Result<Response> {
+        let connection = self.connection.as_mut().unwrap();
+        let start = Instant::now();
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: {}\r\n",
+            request.method,
+            request.path,
+            self.address,
+            if self.keep_alive {
+                "keep-alive"
+            } else {
+                "close"
+            }
+        );
+        for (name, value) in &request.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !request.body.is_empty() || request.method == "POST" {
+            head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+        }
+        head.push_str("\r\n");
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&request.body);
+        match connection.get_mut().write_all(&bytes) {
+            // the server didn't get the whole request, so it didn't handle it
+            Err(_) if reused => return Err(closed_by_server()),
+            Err(e) => return Err(e),
+            Ok(()) => {}
+        }
+        let send = start.elapsed();
+
+        let start = Instant::now();
+        let mut status_line = String::new();
+        // the server may have handled the request before it closed the
+        // connection, so only a safe request can be sent again
+        let retry = reused && is_safe(request.method);
+        match connection.read_line(&mut status_line) {
+            Ok(0) if retry => return Err(closed_by_server()),
+            Err(e) if retry && status_line.is_empty() && is_reset(&e) => {
+                return Err(closed_by_server())
+            }
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "No response")),
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+        let first_byte = start.elapsed();
+
+        let start = Instant::now();
+        let status = parse_status_line(&status_line)?;
+        let mut headers: Vec<(String, String)> = vec![];
+        loop {
+            let mut line = String::new();
+            if connection.read_line(&mut line)? == 0 {
+                return Err(invalid_data("Incomplete headers"));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid_data(&format!("Invalid header {}", line)))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        };
+        let body = if request.method == "HEAD" || status == 204 || status == 304 {
+            vec![]
+        } else if header("Transfer-Encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked")) {
+            read_chunked(connection)?
+        } else if let Some(length) = header("Content-Length") {
+            let length: usize = length
+                .parse()
+                .map_err(|_| invalid_data("Invalid Content-Length"))?;
+            let mut body = vec![0; length];
+            connection.read_exact(&mut body)?;
+            body
+        } else {
+            let mut body = vec![];
+            connection.read_to_end(&mut body)?;
+            // the end of the body is the end of the connection
+            self.connection = None;
+            body
+        };
+        Ok(Response {
+            status,
+            headers,
+            body,
+            timing: Timing {
+                connect,
+                send,
+                first_byte,
+                receive: start.elapsed(),
+            },
+        })
+    }
+}
+
+/// Polls the path until the server answers, whatever the status, waiting
+/// longer and longer between the attempts: from 10 msec up to half a second.
+pub fn wait_until_ready(base_url: &str, path: &str, timeout: Duration) -> Result<(), String> {
+    let deadline = Instant::now() + timeout;
+    let mut delay = Duration::from_millis(10);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut client =
+            Client::new(base_url, false).with_timeout(remaining.max(Duration::from_millis(1)));
+        let error = match client.send(&Request::get(path)) {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+        if Instant::now() + delay >= deadline {
+            return Err(format!(
+                "{} did not answer within {:?}, the last attempt failed with: {}",
+                base_url, timeout, error
+            ));
+        }
+        thread::sleep(delay);
+        delay = (delay * 2).min(Duration::from_millis(500));
+    }
+}
+
+/// Whether sending the request twice is the same as sending it once. The
+/// todo API identifies items by their position, so not even `DELETE` is.
+fn is_safe(method: &str) -> bool {
+    method == "GET" || method == "HEAD"
+}
+
+fn is_reset(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted
+    )
+}
+
+fn closed_by_server() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "Connection closed by the server",
+    )
+}
+
+fn closes(response: &Response) -> bool {
+    response
+        .header("Connection")
+        .is_some_and(|v| v.eq_ignore_ascii_case("close"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Gets the status code from e.g. `HTTP/1.1 200 OK`.
+fn parse_status_line(line: &str) -> io::Result<u16> {
+    let mut parts = line.split_whitespace();
+    match (
+        parts.next(),
+        parts.next().and_then(|code| code.parse().ok()),
+    ) {
+        (Some(version), Some(code)) if version.starts_with("HTTP/") => Ok(code),
+        _ => Err(invalid_data(&format!(
+            "Invalid status line {}",
+            line.trim_end()
+        ))),
+    }
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut body = vec![];
+    loop {
+        let mut size_line = String::new();
+        reader.read_line(&mut size_line)?;
+        // chunk extensions follow a semicolon
+        let size = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| invalid_data(&format!("Invalid chunk size {}", size)))?;
+        if size == 0 {
+            // trailers, up to an empty line
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                    return Ok(body);
+                }
+            }
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        let mut crlf = [0; 2];
+        reader.read_exact(&mut crlf)?;
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    /// A request as the test server got it.
+    #[derive(Clone)]
+    pub struct Received {
+        /// The number of the connection it came on, counting from 0.
+        pub connection: usize,
+        pub method: String,
+        pub path: String,
+        pub body: String,
+        /// The request line, the headers and the body.
+        pub text: String,
+    }
+
+    /// Serves HTTP/1.1 on a free port, with a thread per connection, until
+    /// the test ends. `handle` returns the raw response to a request, or
+    /// `None` to close the connection without one. The connection is closed
+    /// after a response with `Connection: close`.
+    pub fn serve<F>(handle: F) -> SocketAddr
+    where
+        F: FnMut(&Received) -> Option<String> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = Arc::new(Mutex::new(handle));
+        thread::spawn(move || {
+            for (connection, stream) in listener.incoming().enumerate() {
+                let handle = Arc::clone(&handle);
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.unwrap());
+                    while let Some(request) = receive(&mut reader, connection) {
+                        let response = handle.lock().unwrap()(&request);
+                        match response {
+                            Some(response) => {
+                                reader.get_mut().write_all(response.as_bytes()).unwrap();
+                                if response.contains("Connection: close") {
+                                    break;
+                                }
+                            }
+                            None => break,
+                        }
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    /// Reads a request, `None` if the client closed the connection.
+    fn receive(reader: &mut BufReader<TcpStream>, connection: usize) -> Option<Received> {
+        let mut text = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return None;
+            }
+            let header = line.to_ascii_lowercase();
+            if let Some(value) = header.strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+            text.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).ok()?;
+        let body = String::from_utf8(body).unwrap();
+        text.push_str(&body);
+        let mut parts = text.split(' ');
+        Some(Received {
+            connection,
+            method: parts.next().unwrap().to_string(),
+            path: parts.next().unwrap().to_string(),
+            body,
+            text,
+        })
+    }
+
+    /// A response with a JSON body that closes the connection.
+    pub fn respond(status: u16, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {} X\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+
+    fn is_timeout(e: &io::Error) -> bool {
+        matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
+    }
+
+    /// An address where connections are accepted but never answered. Don't
+    /// drop the listener before the test ends.
+    pub fn unanswered() -> (TcpListener, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        (listener, address)
+    }
+
+    /// Serves the given raw responses in turn, recording the requests.
+    fn serve_all(responses: Vec<&'static str>) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = Arc::clone(&requests);
+        let mut responses = responses.into_iter();
+        let address = serve(move |request| {
+            recorded.lock().unwrap().push(request.clone());
+            responses.next().map(str::to_string)
+        });
+        (format!("http://{}", address), requests)
+    }
+
+    #[test]
+    fn test_post_with_content_length() {
+        let (url, requests) = serve_all(vec![
+            "HTTP/1.1 201 Created\r\nContent-Type: application/json\r\nContent-Length: 8\r\n\r\n{\"id\":1}",
+        ]);
+        let mut client = Client::new(&url, false);
+        let response = client
+            .send(&Request::post("/api/todo", "text/plain", "hello 1"))
+            .unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.header("content-type"), Some("application/json"));
+        assert_eq!(response.body_text(), "{\"id\":1}");
+        assert!(response.timing.connect > Duration::ZERO);
+        let requests = requests.lock().unwrap();
+        let text = &requests[0].text;
+        assert!(
+            text.starts_with("POST /api/todo HTTP/1.1\r\nHost: 127.0.0.1:"),
+            "{}",
+            text
+        );
+        assert!(text.contains("Connection: close\r\n"));
+        assert!(text.ends_with("Content-Length: 7\r\n\r\nhello 1"));
+    }
+
+    #[test]
+    fn test_keep_alive_reuses_the_connection() {
+        let (url, requests) = serve_all(vec![
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n[1,\r\n2;x=y\r\n2]\r\n0\r\n\r\n",
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+        ]);
+        let mut client = Client::new(&url, true);
+        let first = client.send(&Request::get("/api/todo")).unwrap();
+        assert_eq!(first.body_text(), "[1,2]");
+        let second = client.send(&Request::get("/api/todo/9")).unwrap();
+        assert_eq!(second.status, 404);
+        assert_eq!(second.timing.connect, Duration::ZERO);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[1].connection, 0);
+        assert!(requests[1].text.starts_with("GET /api/todo/9 HTTP/1.1\r\n"));
+        assert!(!requests[1].text.contains("Content-Length"));
+    }
+
+    #[test]
+    fn test_body_until_the_connection_closes() {
+        let (url, requests) = serve_all(vec![
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nno length",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+        ]);
+        let mut client = Client::new(&url, true);
+        assert_eq!(
+            client.send(&Request::get("/")).unwrap().body_text(),
+            "no length"
+        );
+        assert_eq!(client.send(&Request::get("/")).unwrap().body_text(), "ok");
+        assert_eq!(requests.lock().unwrap()[1].connection, 1);
+    }
+
+    /// Answers the first request on every connection with keep-alive, and
+    /// closes the connection at the second without answering, like a server
+    /// that closes idle connections. Returns how many requests it got.
+    fn serve_once_per_connection() -> (String, Arc<Mutex<usize>>) {
+        let count = Arc::new(Mutex::new(0));
+        let counted = Arc::clone(&count);
+        let mut answered = HashSet::new();
+        let address = serve(move |request| {
+            *counted.lock().unwrap() += 1;
+            answered
+                .insert(request.connection)
+                .then(|| "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".to_string())
+        });
+        (format!("http://{}", address), count)
+    }
+
+    #[test]
+    fn test_safe_request_is_sent_again_on_a_closed_connection() {
+        let (url, count) = serve_once_per_connection();
+        let mut client = Client::new(&url, true);
+        client.send(&Request::get("/")).unwrap();
+        let response = client.send(&Request::get("/")).unwrap();
+        assert!(response.timing.connect > Duration::ZERO);
+        assert_eq!(*count.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_post_is_not_sent_again_on_a_closed_connection() {
+        let (url, count) = serve_once_per_connection();
+        let mut client = Client::new(&url, true);
+        client.send(&Request::get("/")).unwrap();
+        let error = client
+            .send(&Request::post("/", "text/plain", "once"))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof, "{}", error);
+        assert_eq!(*count.lock().unwrap(), 2);
+        // the next request opens a new connection
+        assert_eq!(client.send(&Request::get("/")).unwrap().status, 200);
+    }
+
+    #[test]
+    fn test_unanswered_request_times_out() {
+        let (_listener, address) = unanswered();
+        let start = Instant::now();
+        let error = Client::new(&format!("http://{}", address), false)
+            .with_timeout(Duration::from_millis(100))
+            .send(&Request::get("/"))
+            .unwrap_err();
+        assert!(is_timeout(&error), "{}", error);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_parse_status_line() {
+        assert_eq!(
+            parse_status_line("HTTP/1.1 500 Internal Server Error\r\n").unwrap(),
+            500
+        );
+        assert!(parse_status_line("SSH-2.0\r\n").is_err());
+    }
+
+    #[test]
+    fn test_wait_until_ready() {
+        // the first attempts get no answer, as while Apache is starting
+        let mut attempts = 0;
+        let address = serve(move |_| {
+            attempts += 1;
+            (attempts > 2).then(|| respond(404, ""))
+        });
+        let url = format!("http://{}", address);
+        wait_until_ready(&url, "/", Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn test_wait_until_ready_times_out() {
+        let (_listener, address) = unanswered();
+        let url = format!("http://{}", address);
+        let start = Instant::now();
+        let error = wait_until_ready(&url, "/", Duration::from_millis(200)).unwrap_err();
+        assert!(error.contains("did not answer within 200ms"), "{}", error);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_phase_summary() {
+        let timing = |connect| Timing {
+            connect: Duration::from_millis(connect),
+            send: Duration::from_millis(1),
+            first_byte: Duration::from_millis(100),
+            receive: Duration::from_millis(2),
+        };
+        let summary = PhaseSummary::from_timings(&[timing(3), timing(0)]).unwrap();
+        assert_eq!(summary.connect_ms, 1.5);
+        assert_eq!(summary.first_byte_ms, 100.0);
+        assert_eq!(timing(3).total(), Duration::from_millis(106));
+        assert_eq!(PhaseSummary::from_timings(&[]), None);
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::http::Timing;
+
+/// How a request ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// A response with this status code.
+    Status(u16, Timing),
+    /// No response, e.g. the connection was refused.
+    Error(String),
+}
+
+/// How the load is generated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoadOptions {
+    /// How many clients send requests at the same time.
+    pub concurrency: u32,
+    /// Requests per second. Without a rate, every client sends its next
+    /// request as soon as it gets a response (closed loop).
+    pub rate: Option<f64>,
+    /// How long to send requests. Without a duration, `requests` are sent.
+    pub duration: Option<Duration>,
+    pub requests: i32,
+}
+
+/// What a load test saw, as written to the result files.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct LoadSummary {
+    pub concurrency: u32,
+    pub rate: Option<f64>,
+    /// Seconds from the first request until the last response.
+    pub elapsed_s: f64,
+    /// Responses per second, whatever their status.
+    pub throughput: f64,
+    /// How many responses had each status code, and how many requests got
+    /// no response (`error`).
+    pub statuses: BTreeMap<String, u32>,
+}
+
+impl LoadSummary {
+    pub fn errors(&self) -> u32 {
+        self.statuses
+            .iter()
+            .filter(|(status, _)| !status.starts_with('2'))
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// E.g. `200: 97, 500: 2, error: 1`.
+    pub fn describe_statuses(&self) -> String {
+        self.statuses
+            .iter()
+            .map(|(status, count)| format!("{}: {}", status, count))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+/// The result of a load test: the latency and the timing of every request
+/// that got a response, and the summary.
+pub struct LoadResult {
+    pub latencies: Vec<Duration>,
+    pub timings: Vec<Timing>,
+    pub summary: LoadSummary,
+}
+
+/// Sends requests from concurrent clients, numbering them from `first`.
+/// Every client gets its own sender from `new_client`.
+///
+/// With a rate, request `i` is due at `i / rate` seconds after the start and
+/// its latency is measured from then, not from when a client got to send
+/// it, so that a server that falls behind shows in the latencies instead of
+/// slowing the load down.
+pub fn run_load<F, C>(options: LoadOptions, first: i32, new_client: F) -> LoadResult
+where
+    F: Fn() -> C + Sync,
+    C: FnMut(i32) -> Outcome,
+{
+    let next = AtomicI32::new(0);
+    let latencies: Mutex<Vec<Duration>> = Mutex::new(vec![]);
+    let timings: Mutex<Vec<Timing>> = Mutex::new(vec![]);
+    let statuses: Mutex<BTreeMap<String, u32>> = Mutex::new(BTreeMap::new());
+    let start = Instant::now();
+    let deadline = options.duration.map(|duration| start + duration);
+    thread::scope(|scope| {
+        for _ in 0..options.concurrency {
+            scope.spawn(|| {
+                let mut send = new_client();
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if deadline.is_none() && i >= options.requests {
+                        break;
+                    }
+                    let due = match options.rate {
+                        Some(rate) => start + Duration::from_secs_f64(i as f64 / rate),
+                        None => Instant::now(),
+                    };
+                    if deadline.is_some_and(|deadline| due >= deadline) {
+                        break;
+                    }
+                    let now = Instant::now();
+                    if due > now {
+                        thread::sleep(due - now);
+                    }
+                    let outcome = send(first + i);
+                    let latency = due.elapsed();
+                    let status = match outcome {
+                        Outcome::Status(code, timing) => {
+                            latencies.lock().unwrap().push(latency);
+                            timings.lock().unwrap().push(timing);
+                            code.to_string()
+                        }
+                        Outcome::Error(_) => "error".to_string(),
+                    };
+                    *statuses.lock().unwrap().entry(status).or_insert(0) += 1;
+                }
+            });
+        }
+    });
+    let elapsed = start.elapsed();
+    let statuses = statuses.into_inner().unwrap();
+    // requests without a response are not throughput
+    let responses: u32 = statuses
+        .iter()
+        .filter(|(status, _)| status.as_str() != "error")
+        .map(|(_, count)| count)
+        .sum();
+    LoadResult {
+        latencies: latencies.into_inner().unwrap(),
+        timings: timings.into_inner().unwrap(),
+        summary: LoadSummary {
+            concurrency: options.concurrency,
+            rate: options.rate,
+            elapsed_s: elapsed.as_secs_f64(),
+            throughput: responses as f64 / elapsed.as_secs_f64(),
+            statuses,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    fn options() -> LoadOptions {
+        LoadOptions {
+            concurrency: 4,
+            rate: None,
+            duration: None,
+            requests: 20,
+        }
+    }
+
+    #[test]
+    fn test_closed_loop_sends_all_requests_concurrently() {
+        let running = AtomicU32::new(0);
+        let max_running = AtomicU32::new(0);
+        let result = run_load(options(), 1, || {
+            |i| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(5));
+                running.fetch_sub(1, Ordering::SeqCst);
+                if i % 10 == 0 {
+                    Outcome::Status(500, Timing::default())
+                } else if i == 7 {
+                    Outcome::Error("refused".to_string())
+                } else {
+                    Outcome::Status(200, Timing::default())
+                }
+            }
+        });
+        assert_eq!(result.latencies.len(), 19);
+        assert_eq!(result.timings.len(), 19);
+        assert_eq!(
+            result.summary.describe_statuses(),
+            "200: 17, 500: 2, error: 1"
+        );
+        assert_eq!(result.summary.errors(), 3);
+        assert!(max_running.load(Ordering::SeqCst) > 1);
+        let throughput = 19.0 / result.summary.elapsed_s;
+        assert!(
+            (result.summary.throughput - throughput).abs() < 1e-6,
+            "{:?}",
+            result.summary
+        );
+    }
+
+    #[test]
+    fn test_fixed_rate_spreads_requests() {
+        let result = run_load(
+            LoadOptions {
+                rate: Some(200.0),
+                ..options()
+            },
+            1,
+            || |_| Outcome::Status(200, Timing::default()),
+        );
+        // 20 requests at 200 per second take at least 95 msec
+        assert!(result.summary.elapsed_s >= 0.095, "{:?}", result.summary);
+        assert_eq!(result.summary.statuses["200"], 20);
+    }
+
+    #[test]
+    fn test_duration_stops_the_load() {
+        let result = run_load(
+            LoadOptions {
+                concurrency: 2,
+                rate: Some(100.0),
+                duration: Some(Duration::from_millis(100)),
+                requests: 0,
+            },
+            1,
+            || |_| Outcome::Status(204, Timing::default()),
+        );
+        let sent = result.summary.statuses["204"];
+        assert!((9..=11).contains(&sent), "{}", sent);
+    }
+}
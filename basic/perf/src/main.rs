@@ -1,24 +1,110 @@
+use std::collections::HashSet;
 use std::env;
 use std::io;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::{exit, Command, Stdio};
-use std::time::SystemTime;
+use std::time::{Duration, Instant};
 
-mod docker;
+mod compare;
+mod http;
+mod load;
+mod paths;
+mod report;
+mod results;
+mod runtime;
+mod scenario;
+mod stats;
+mod workload;
+
+use http::PhaseSummary;
+use load::{LoadSummary, Outcome};
+use results::{ExperimentResult, Metadata, Results};
+use runtime::{Container, Runtime};
+use scenario::{Backend, Experiment, Kind};
+use stats::Stats;
+use workload::Workload;
+
+/// How many percent slower than the baseline an experiment may get, unless
+/// `--max-regression` says otherwise.
+const DEFAULT_MAX_REGRESSION: f64 = 10.0;
+
+/// The port of the host where the httpd experiments publish Apache, unless
+/// `--port` says otherwise.
+const DEFAULT_PORT: u32 = 8080;
+
+/// How many seconds Apache may take to answer after its container started,
+/// unless `--startup-timeout` says otherwise.
+const DEFAULT_STARTUP_TIMEOUT: f64 = 30.0;
 
 #[derive(Debug)]
 struct Args {
     count: i32,
+    /// Iterations that run before the measured ones and are not counted.
+    warmup: i32,
     quiet: bool,
     qbasic: bool,
+    /// The scenario file with the experiments, the default ones if `None`.
+    scenario: Option<String>,
+    /// Names or kinds of the experiments to run, all if empty.
+    only: Vec<String>,
+    /// Names or kinds of the experiments not to run.
+    skip: Vec<String>,
+    /// Where the result files are written, `perf/results` under the root by
+    /// default.
+    output: Option<PathBuf>,
+    /// The `basic` folder of the repository, which relative paths are
+    /// resolved against.
+    root: PathBuf,
+    /// The launcher, found under the root by default.
+    launcher: Option<PathBuf>,
+    /// A result file to compare the results with.
+    baseline: Option<String>,
+    /// How many percent slower than the baseline an experiment may get.
+    max_regression: Option<f64>,
+    /// Overrides the concurrency of the httpd experiments.
+    concurrency: Option<u32>,
+    /// Overrides the rate of the httpd experiments.
+    rate: Option<f64>,
+    /// Overrides the duration of the httpd experiments.
+    duration: Option<f64>,
+    /// Makes the httpd experiments keep their connections open.
+    keep_alive: bool,
+    /// The container runtime, `docker` or `podman`.
+    runtime: String,
+    /// The port of the host where Apache is published.
+    port: u32,
+    /// How many seconds Apache may take to answer after its container started.
+    startup_timeout: f64,
 }
 
-fn copy_env(key: &str) -> String {
-    format!("{}={}", key, env::var(key).unwrap_or_default())
+/// The environment variables of an experiment: `BLR_BASIC_MODE` as given to
+/// perf, then the ones of the experiment.
+fn experiment_env(experiment: &Experiment) -> Vec<(String, String)> {
+    let mut result = vec![(
+        "BLR_BASIC_MODE".to_string(),
+        env::var("BLR_BASIC_MODE").unwrap_or_default(),
+    )];
+    for (key, value) in &experiment.env {
+        result.retain(|(k, _)| k != key);
+        result.push((key.clone(), value.clone()));
+    }
+    result
 }
 
-fn copy_basic_mode() -> String {
-    copy_env("BLR_BASIC_MODE")
+fn docker_env(experiment: &Experiment) -> Vec<String> {
+    experiment_env(experiment)
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect()
+}
+
+/// Splits comma separated names, e.g. `--only DOS,httpd`.
+fn parse_selectors(value: Option<String>, flag: &str) -> Vec<String> {
+    match value {
+        Some(x) => x.split(',').map(|s| s.trim().to_string()).collect(),
+        None => panic!("{} requires an argument", flag),
+    }
 }
 
 impl Args {
@@ -34,10 +120,84 @@ impl Args {
                             Some(x) => x.parse().unwrap(),
                             None => panic!("--count requires an argument"),
                         };
+                    } else if value == "--warmup" {
+                        let next2 = iterator.next();
+                        self.warmup = match next2 {
+                            Some(x) => x.parse().unwrap(),
+                            None => panic!("--warmup requires an argument"),
+                        };
+                    } else if value == "--scenario" {
+                        self.scenario = match iterator.next() {
+                            Some(x) => Some(x),
+                            None => panic!("--scenario requires an argument"),
+                        };
+                    } else if value == "--only" {
+                        let selectors = parse_selectors(iterator.next(), "--only");
+                        self.only.extend(selectors);
+                    } else if value == "--skip" {
+                        let selectors = parse_selectors(iterator.next(), "--skip");
+                        self.skip.extend(selectors);
+                    } else if value == "--output" {
+                        self.output = match iterator.next() {
+                            Some(x) => Some(PathBuf::from(x)),
+                            None => panic!("--output requires an argument"),
+                        };
+                    } else if value == "--root" {
+                        self.root = match iterator.next() {
+                            Some(x) => paths::absolute(Path::new(&x)),
+                            None => panic!("--root requires an argument"),
+                        };
+                    } else if value == "--launcher" {
+                        self.launcher = match iterator.next() {
+                            Some(x) => Some(paths::absolute(Path::new(&x))),
+                            None => panic!("--launcher requires an argument"),
+                        };
+                    } else if value == "--baseline" {
+                        self.baseline = match iterator.next() {
+                            Some(x) => Some(x),
+                            None => panic!("--baseline requires an argument"),
+                        };
+                    } else if value == "--max-regression" {
+                        self.max_regression = match iterator.next() {
+                            Some(x) => Some(compare::parse_percent(&x)),
+                            None => panic!("--max-regression requires an argument"),
+                        };
+                    } else if value == "--concurrency" {
+                        self.concurrency = match iterator.next() {
+                            Some(x) => Some(x.parse().unwrap()),
+                            None => panic!("--concurrency requires an argument"),
+                        };
+                    } else if value == "--rate" {
+                        self.rate = match iterator.next() {
+                            Some(x) => Some(x.parse().unwrap()),
+                            None => panic!("--rate requires an argument"),
+                        };
+                    } else if value == "--duration" {
+                        self.duration = match iterator.next() {
+                            Some(x) => Some(x.parse().unwrap()),
+                            None => panic!("--duration requires an argument"),
+                        };
+                    } else if value == "--runtime" {
+                        self.runtime = match iterator.next() {
+                            Some(x) => x,
+                            None => panic!("--runtime requires an argument"),
+                        };
+                    } else if value == "--port" {
+                        self.port = match iterator.next() {
+                            Some(x) => x.parse().unwrap(),
+                            None => panic!("--port requires an argument"),
+                        };
+                    } else if value == "--startup-timeout" {
+                        self.startup_timeout = match iterator.next() {
+                            Some(x) => x.parse().unwrap(),
+                            None => panic!("--startup-timeout requires an argument"),
+                        };
+                    } else if value == "--keep-alive" {
+                        self.keep_alive = true;
                     } else if value == "--quiet" {
                         self.quiet = true;
                     } else {
-                        panic!(format!("Unexpected parameter {}", value));
+                        panic!("Unexpected parameter {}", value);
                     }
                 }
                 None => {
@@ -48,21 +208,51 @@ impl Args {
     }
 }
 
-fn now() -> u128 {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_millis()
+/// Runs the warmup iterations, then times each of the measured ones.
+/// The iteration gets its number, counting the warmup ones too.
+/// Stops at the first iteration that fails.
+fn measure<F>(
+    name: &str,
+    count: i32,
+    args: &Args,
+    mut iteration: F,
+) -> Result<Vec<Duration>, String>
+where
+    F: FnMut(i32) -> Result<(), String>,
+{
+    println!("Running {} experiment", name);
+    if args.warmup > 0 {
+        println!("Warming up");
+        for n in 1..args.warmup + 1 {
+            iteration(n)?;
+        }
+    }
+    let mut durations: Vec<Duration> = vec![];
+    for n in 1..count + 1 {
+        progress(n, args);
+        let start = Instant::now();
+        iteration(args.warmup + n)?;
+        durations.push(start.elapsed());
+    }
+    Ok(report(name, durations, args))
 }
 
-fn run_standalone(args: &Args) {
-    let program = if args.qbasic {
-        "./basic/src/HELLOQB.BAS"
-    } else {
-        "./basic/src/HELLO.BAS"
-    };
-    let output = Command::new("./basic-launcher-rust/target/release/basic-launcher-rust.exe")
-        .args(&[program])
+fn report(name: &str, durations: Vec<Duration>, args: &Args) -> Vec<Duration> {
+    if args.quiet {
+        // end the line of progress dots
+        println!();
+    }
+    println!("{}: {}", name, Stats::from_durations(&durations));
+    durations
+}
+
+/// Runs the program with the launcher in the root, so that the relative paths
+/// of the program and of the environment variables resolve against it.
+fn run_standalone(experiment: &Experiment, launcher: &Path, args: &Args) -> Result<(), String> {
+    let output = Command::new(launcher)
+        .current_dir(&args.root)
+        .arg(&experiment.program)
+        .envs(experiment_env(experiment))
         .stdout(if args.quiet {
             Stdio::piped()
         } else {
@@ -70,11 +260,11 @@ fn run_standalone(args: &Args) {
         })
         .stderr(Stdio::inherit())
         .output()
-        .expect("Failed to execute command");
+        .map_err(|e| format!("Could not execute {}: {}", launcher.display(), e))?;
     if !output.status.success() {
-        eprintln!("Failed");
-        exit(output.status.code().unwrap_or(0));
+        return Err(format!("The launcher failed with {}", output.status));
     }
+    Ok(())
 }
 
 fn progress(n: i32, args: &Args) {
@@ -86,258 +276,712 @@ fn progress(n: i32, args: &Args) {
     }
 }
 
-fn dos_experiment(args: &Args) -> f64 {
-    let start = now();
-    println!("Running DOS experiment");
-    for n in 1..args.count + 1 {
-        progress(n, args);
-        run_standalone(args);
+/// Builds the image of the experiment, unless it was already built by this run.
+fn build_image(
+    runtime: &dyn Runtime,
+    experiment: &Experiment,
+    args: &Args,
+    built: &mut HashSet<String>,
+) -> Result<(), String> {
+    let image = experiment.image();
+    if !built.contains(image) {
+        println!("Building {} image {}", runtime.name(), image);
+        runtime.build(
+            image,
+            &args.root.join(experiment.dockerfile()),
+            &args.root,
+            args.quiet,
+        )?;
+        built.insert(image.to_string());
     }
-    let stop = now();
-    println!("{}", stop - start);
-    let average = ((stop - start) as f64) / (args.count as f64);
-    println!("average {}", average);
-    average
+    Ok(())
 }
 
-/// Gets the current directory, converting it to a path that Docker understands as a volume.
-fn current_dir_as_msys_path() -> String {
-    let original = format!("{}", env::current_dir().unwrap().display());
-    original.replace("C:\\", "/c/").replace("\\", "/")
+fn bin_volume(args: &Args) -> runtime::Volume {
+    runtime::Volume {
+        host: paths::volume_path(&args.root.join("bin")),
+        guest: "/basic/bin".to_string(),
+    }
 }
 
-fn build_image(args: &Args) {
-    println!("Building Docker image");
-    docker::build("basic", "Dockerfile.standalone", args.quiet);
+/// Mounts the directory of the program (or the directory itself for httpd)
+/// as `/basic/src`. A relative directory is under the root.
+fn src_volume(dir: &Path, args: &Args) -> runtime::Volume {
+    runtime::Volume {
+        host: paths::volume_path(&args.root.join(dir)),
+        guest: "/basic/src".to_string(),
+    }
 }
 
-fn run_docker_outside(args: &Args) {
-    let bin_volume_spec = docker::Volume {
-        host: format!("{}/bin", current_dir_as_msys_path()),
-        guest: "/basic/bin".to_string(),
-    };
-    let src_volume_spec = docker::Volume {
-        host: format!("{}/basic/src", current_dir_as_msys_path()),
-        guest: "/basic/src".to_string(),
-    };
-    let program = if args.qbasic {
-        "HELLOQB.BAS"
-    } else {
-        "HELLO.BAS"
-    };
-    let basic_mode = copy_basic_mode();
-    docker::run(
-        "basic",
-        docker::RunOptions {
-            args: vec![program.to_string()],
-            volumes: vec![bin_volume_spec, src_volume_spec],
-            env: vec![basic_mode],
+/// Splits the program of the experiment into its directory and file name.
+fn split_program(experiment: &Experiment) -> (&Path, String) {
+    let path = Path::new(&experiment.program);
+    let file_name = path
+        .file_name()
+        .unwrap_or_else(|| panic!("No file name in {}", experiment.program))
+        .to_string_lossy()
+        .into_owned();
+    (path.parent().unwrap_or_else(|| Path::new("")), file_name)
+}
+
+fn run_docker_outside(
+    runtime: &dyn Runtime,
+    experiment: &Experiment,
+    args: &Args,
+) -> Result<(), String> {
+    let (dir, file_name) = split_program(experiment);
+    runtime.run(
+        experiment.image(),
+        runtime::RunOptions {
+            args: vec![file_name],
+            volumes: vec![bin_volume(args), src_volume(dir, args)],
+            env: docker_env(experiment),
             entry_point: None,
             ports: vec![],
             detach: false,
             container_name: None,
         },
         args.quiet,
-    );
+    )?;
+    Ok(())
 }
 
-fn docker_outside_experiment(args: &Args) -> f64 {
-    build_image(args);
-    let start = now();
-    println!("Running Docker (outside) experiment");
-    for n in 1..args.count + 1 {
-        progress(n, args);
-        run_docker_outside(args);
-    }
-    let stop = now();
-    println!("{}", stop - start);
-    let average = ((stop - start) as f64) / (args.count as f64);
-    println!("average {}", average);
-    average
-}
-
-fn docker_inside_experiment(args: &Args) -> f64 {
-    build_image(args);
-    let start = now();
-    println!("Running Docker (inside) experiment");
-    let bin_volume_spec: docker::Volume = docker::Volume {
-        host: format!("{}/bin", current_dir_as_msys_path()),
-        guest: "/basic/bin".to_string(),
-    };
-    let src_volume_spec: docker::Volume = docker::Volume {
-        host: format!("{}/basic/src", current_dir_as_msys_path()),
-        guest: "/basic/src".to_string(),
-    };
-    let perf_volume_spec: docker::Volume = docker::Volume {
-        host: format!("{}/perf", current_dir_as_msys_path()),
+/// The loop runs inside the container, which prints how long each iteration
+/// took, measured there.
+fn docker_inside_experiment(
+    runtime: &dyn Runtime,
+    experiment: &Experiment,
+    count: i32,
+    args: &Args,
+) -> Result<Vec<Duration>, String> {
+    println!("Running {} experiment", experiment.name);
+    let (dir, file_name) = split_program(experiment);
+    let perf_volume_spec: runtime::Volume = runtime::Volume {
+        host: paths::volume_path(&args.root.join("perf")),
         guest: "/usr/local/perf/bin:ro".to_string(),
     };
-    let fmt_count = args.count.to_string();
-    let basic_mode = copy_basic_mode();
 
-    let mut run_args: Vec<String> =
-        vec!["/usr/local/perf/bin/perf-inside.sh".to_string(), fmt_count];
+    let mut run_args: Vec<String> = vec![
+        "/usr/local/perf/bin/perf-inside.sh".to_string(),
+        format!("/basic/src/{}", file_name),
+        count.to_string(),
+        args.warmup.to_string(),
+    ];
     if args.quiet {
         run_args.push("--quiet".to_string());
     }
-    docker::run(
-        "basic",
-        docker::RunOptions {
+    let output = runtime.run(
+        experiment.image(),
+        runtime::RunOptions {
             args: run_args,
-            volumes: vec![bin_volume_spec, src_volume_spec, perf_volume_spec],
-            env: vec![basic_mode],
+            volumes: vec![bin_volume(args), src_volume(dir, args), perf_volume_spec],
+            env: docker_env(experiment),
             entry_point: Some("bash".to_string()),
             ports: vec![],
             detach: false,
             container_name: None,
         },
-        false,
-    );
-    let stop = now();
-    println!("{}", stop - start);
-    let average = ((stop - start) as f64) / (args.count as f64);
-    println!("average {}", average);
-    average
+        true,
+    )?;
+    Ok(report(
+        &experiment.name,
+        parse_inside_durations(&output),
+        args,
+    ))
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-enum ApacheVariant {
-    Launcher,
-    Interpreter,
+/// Parses the `elapsed_ns` lines that `perf-inside.sh` prints per iteration.
+fn parse_inside_durations(output: &str) -> Vec<Duration> {
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix("elapsed_ns "))
+        .map(|ns| Duration::from_nanos(ns.trim().parse().unwrap()))
+        .collect()
 }
 
-impl ApacheVariant {
-    pub fn to_docker_image_name(&self) -> &str {
-        match self {
-            Self::Launcher => "basic-httpd",
-            Self::Interpreter => "basic-interpreter-httpd",
-        }
+fn start_httpd<'a>(
+    runtime: &'a dyn Runtime,
+    experiment: &Experiment,
+    args: &Args,
+) -> Result<Container<'a>, String> {
+    let mut volumes = vec![src_volume(Path::new(&experiment.program), args)];
+    if experiment.backend == Backend::Launcher {
+        volumes.push(bin_volume(args));
     }
-}
-
-fn build_httpd_image(args: &Args, flavour: ApacheVariant) {
-    println!("Building Docker HTTPD image");
-    docker::build(
-        flavour.to_docker_image_name(),
-        if flavour == ApacheVariant::Launcher {
-            "Dockerfile.httpd"
-        } else {
-            "Dockerfile.interpreter.httpd"
-        },
-        args.quiet,
-    );
-}
-
-fn start_httpd(args: &Args, flavour: ApacheVariant) {
-    println!("Starting HTTPD");
-    let bin_volume_spec: docker::Volume = docker::Volume {
-        host: format!("{}/bin", current_dir_as_msys_path()),
-        guest: "/basic/bin".to_string(),
-    };
-    let src_host_dir = if flavour == ApacheVariant::Interpreter {
-        format!("{}/basic/rest-qb-direct", current_dir_as_msys_path())
-    } else {
-        if args.qbasic {
-            format!("{}/basic/rest-qb", current_dir_as_msys_path())
-        } else {
-            format!("{}/basic/rest:/basic/src", current_dir_as_msys_path())
-        }
-    };
-    let src_volume_spec: docker::Volume = docker::Volume {
-        host: src_host_dir,
-        guest: "/basic/src".to_string(),
-    };
-    let basic_mode = copy_basic_mode();
-
-    let name = flavour.to_docker_image_name();
-    docker::run(
+    let name = experiment.image();
+    Container::start(
+        runtime,
+        name,
         name,
-        docker::RunOptions {
+        runtime::RunOptions {
             args: vec![],
-            volumes: vec![bin_volume_spec, src_volume_spec],
-            env: vec![basic_mode],
+            volumes,
+            env: docker_env(experiment),
             entry_point: None,
-            ports: vec![docker::Port {
-                host: 8080,
+            ports: vec![runtime::Port {
+                host: args.port,
                 guest: 80,
             }],
             detach: true,
             container_name: Some(name.to_string()),
         },
         args.quiet,
-    );
+    )
 }
 
-fn stop_httpd(args: &Args, flavour: ApacheVariant) {
-    println!("Stopping HTTPD");
-    docker::stop(flavour.to_docker_image_name(), args.quiet);
+/// Sends the request of the experiment to Apache, a POST unless its body is
+/// empty.
+fn send_request(
+    client: &mut http::Client,
+    experiment: &Experiment,
+    i: i32,
+) -> io::Result<http::Response> {
+    let payload = experiment.body(i);
+    let request = if payload.is_empty() {
+        http::Request::get(experiment.path())
+    } else {
+        http::Request::post(experiment.path(), "text/plain", &payload)
+    };
+    client.send(&request)
 }
 
-fn run_curl(i: i32, args: &Args) {
-    let payload = format!("hello {}", i);
-    let mut run_args = vec![
-        "-f",
-        "--data",
-        &payload,
-        "-H",
-        "Content-Type: text/plain",
-        "http://localhost:8080/api/todo",
-    ];
+/// Sends a request of a sequential experiment, printing the response unless
+/// quiet. Fails on an error status.
+fn run_request(
+    client: &mut http::Client,
+    experiment: &Experiment,
+    i: i32,
+    args: &Args,
+) -> Result<http::Timing, String> {
+    let response =
+        send_request(client, experiment, i).map_err(|e| format!("Request failed: {}", e))?;
+    if !args.quiet {
+        print!("{}", response.body_text());
+    }
+    if response.status >= 400 {
+        return Err(format!(
+            "Request failed with HTTP status {}",
+            response.status
+        ));
+    }
+    Ok(response.timing)
+}
 
-    if args.quiet {
-        run_args.insert(0, "--silent");
+/// Sends a request of a load test, keeping its response to itself.
+fn request_outcome(client: &mut http::Client, experiment: &Experiment, i: i32) -> Outcome {
+    match send_request(client, experiment, i) {
+        Ok(response) => Outcome::Status(response.status, response.timing),
+        Err(e) => Outcome::Error(e.to_string()),
     }
-    let output = Command::new("curl")
-        .args(run_args)
-        .stdout(if args.quiet {
-            Stdio::piped()
-        } else {
-            Stdio::inherit()
-        })
-        .stderr(Stdio::inherit())
-        .output()
-        .expect("Failed to execute command");
-    if !output.status.success() {
-        eprintln!("Failed");
-        exit(output.status.code().unwrap_or(1));
+}
+
+fn httpd_url(args: &Args) -> String {
+    format!("http://localhost:{}", args.port)
+}
+
+fn http_client(experiment: &Experiment, args: &Args) -> http::Client {
+    http::Client::new(&httpd_url(args), experiment.keep_alive)
+}
+
+/// Runs the warmup requests one by one, then the load test.
+fn load_experiment(
+    experiment: &Experiment,
+    options: load::LoadOptions,
+    workload: Option<&Workload>,
+    args: &Args,
+) -> Result<(Vec<Duration>, LoadSummary, Option<PhaseSummary>), String> {
+    println!("Running {} experiment", experiment.name);
+    if args.warmup > 0 {
+        println!("Warming up");
+        let mut client = http_client(experiment, args);
+        for n in 1..args.warmup + 1 {
+            match workload {
+                Some(workload) => {
+                    workload.run(&mut client, n);
+                }
+                None => {
+                    run_request(&mut client, experiment, n, args)?;
+                }
+            }
+        }
+    }
+    println!(
+        "Load: {} clients, {}, {}",
+        options.concurrency,
+        options
+            .rate
+            .map_or("closed loop".to_string(), |rate| format!(
+                "{} requests/sec",
+                rate
+            )),
+        options.duration.map_or(
+            format!("{} requests", options.requests),
+            |duration| format!("for {:?}", duration)
+        )
+    );
+    let result = load::run_load(options, args.warmup + 1, || {
+        let mut client = http_client(experiment, args);
+        move |n| match workload {
+            Some(workload) => workload.run(&mut client, n),
+            None => request_outcome(&mut client, experiment, n),
+        }
+    });
+    println!(
+        "{}: {:.1} requests/sec, {}",
+        experiment.name,
+        result.summary.throughput,
+        result.summary.describe_statuses()
+    );
+    if result.latencies.is_empty() {
+        return Err("No request got a response".to_string());
     }
+    Ok((
+        report(&experiment.name, result.latencies, args),
+        result.summary,
+        PhaseSummary::from_timings(&result.timings),
+    ))
 }
 
-fn apache_experiment(args: &Args, flavour: ApacheVariant) -> f64 {
-    build_httpd_image(args, flavour);
-    start_httpd(args, flavour);
-    let start = now();
-    println!("Running Apache {:?} experiment", flavour);
-    for n in 1..args.count + 1 {
-        progress(n, args);
-        run_curl(n, args);
-    }
-    let stop = now();
-    println!("{}", stop - start);
-    let average = ((stop - start) as f64) / (args.count as f64);
-    println!("average {}", average);
-    stop_httpd(args, flavour);
-    average
+/// Sends the requests of an experiment one by one.
+fn sequential_experiment(
+    experiment: &Experiment,
+    count: i32,
+    workload: Option<&Workload>,
+    args: &Args,
+) -> Result<(Vec<Duration>, Option<PhaseSummary>), String> {
+    let mut client = http_client(experiment, args);
+    let mut timings: Vec<http::Timing> = vec![];
+    let durations = measure(&experiment.name, count, args, |n| {
+        let timing = match workload {
+            Some(workload) => match workload.run(&mut client, n) {
+                Outcome::Status(_, timing) => Some(timing),
+                Outcome::Error(_) => None,
+            },
+            None => Some(run_request(&mut client, experiment, n, args)?),
+        };
+        if n > args.warmup {
+            timings.extend(timing);
+        }
+        Ok(())
+    })?;
+    Ok((durations, PhaseSummary::from_timings(&timings)))
+}
+
+fn httpd_experiment(
+    experiment: &Experiment,
+    count: i32,
+    args: &Args,
+) -> Result<ExperimentResult, String> {
+    // one client at a time, the workload knows exactly which items there are
+    let workload = experiment
+        .mix
+        .map(|mix| Workload::new(experiment, mix, experiment.concurrency == 1));
+    if let Some(workload) = &workload {
+        workload
+            .start(&mut http_client(experiment, args))
+            .map_err(|e| format!("Could not start the workload: {}", e))?;
+    }
+    let mut result = match experiment.load_options(count) {
+        Some(options) => {
+            let (durations, summary, phases) =
+                load_experiment(experiment, options, workload.as_ref(), args)?;
+            ExperimentResult::new(experiment, &durations, Some(summary), phases)
+        }
+        None => {
+            let (durations, phases) =
+                sequential_experiment(experiment, count, workload.as_ref(), args)?;
+            ExperimentResult::new(experiment, &durations, None, phases)
+        }
+    };
+    if let Some(workload) = workload {
+        let summary = workload.finish(&mut http_client(experiment, args));
+        println!("{}: {} failed checks", experiment.name, summary.failures);
+        result.workload = Some(summary);
+    }
+    Ok(result)
+}
+
+/// Runs an experiment. Apache is stopped even if the experiment fails.
+fn run_experiment(
+    runtime: &dyn Runtime,
+    experiment: &Experiment,
+    args: &Args,
+    built: &mut HashSet<String>,
+) -> Result<ExperimentResult, String> {
+    let count = experiment.count.unwrap_or(args.count);
+    let durations = match experiment.kind {
+        Kind::Local => {
+            let launcher = match &args.launcher {
+                Some(launcher) => launcher.clone(),
+                None => paths::find_launcher(&args.root)?,
+            };
+            measure(&experiment.name, count, args, |_| {
+                run_standalone(experiment, &launcher, args)
+            })?
+        }
+        Kind::DockerOutside => {
+            build_image(runtime, experiment, args, built)?;
+            measure(&experiment.name, count, args, |_| {
+                run_docker_outside(runtime, experiment, args)
+            })?
+        }
+        Kind::DockerInside => {
+            build_image(runtime, experiment, args, built)?;
+            docker_inside_experiment(runtime, experiment, count, args)?
+        }
+        Kind::Httpd => {
+            build_image(runtime, experiment, args, built)?;
+            println!("Starting HTTPD");
+            let start = Instant::now();
+            let httpd = start_httpd(runtime, experiment, args)?;
+            // whatever Apache answers, it is up
+            http::wait_until_ready(
+                &httpd_url(args),
+                "/",
+                Duration::from_secs_f64(args.startup_timeout),
+            )
+            .map_err(|e| format!("Apache did not start: {}", e))?;
+            let startup = start.elapsed();
+            println!(
+                "HTTPD ready after {:.1} msec",
+                startup.as_secs_f64() * 1000.0
+            );
+            let mut result = httpd_experiment(experiment, count, args)?;
+            result.startup_ms = Some(startup.as_secs_f64() * 1000.0);
+            println!("Stopping HTTPD");
+            httpd.stop()?;
+            return Ok(result);
+        }
+    };
+    Ok(ExperimentResult::new(experiment, &durations, None, None))
 }
 
 fn main() {
+    let all_args: Vec<String> = env::args().collect();
+    match all_args.get(1).map(String::as_str) {
+        Some("report") => {
+            report::report_command(&all_args[2..]);
+            return;
+        }
+        Some("compare") => {
+            compare::compare_command(&all_args[2..]);
+            return;
+        }
+        _ => {}
+    }
     let mut args = Args {
         count: 100,
+        warmup: 1,
         quiet: false,
         qbasic: env::var("BLR_BASIC_MODE").unwrap_or_default() == "qbasic",
+        scenario: None,
+        only: vec![],
+        skip: vec![],
+        output: None,
+        root: paths::absolute(Path::new(".")),
+        launcher: None,
+        baseline: None,
+        max_regression: None,
+        concurrency: None,
+        rate: None,
+        duration: None,
+        keep_alive: false,
+        runtime: env::var("PERF_RUNTIME").unwrap_or_else(|_| "docker".to_string()),
+        port: DEFAULT_PORT,
+        startup_timeout: DEFAULT_STARTUP_TIMEOUT,
     };
     args.parse();
-    let dos_average = dos_experiment(&args);
-    let docker_outside_average = docker_outside_experiment(&args);
-    let docker_inside_average = docker_inside_experiment(&args);
-    let apache_average = apache_experiment(&args, ApacheVariant::Launcher);
-    let apache_interpreter_average = apache_experiment(&args, ApacheVariant::Interpreter);
-    println!("Summary:");
-    println!("| DOS                  | {} |", dos_average);
-    println!("| Docker (outside)     | {} |", docker_outside_average);
-    println!("| Docker (inside)      | {} |", docker_inside_average);
-    println!("| Apache (launcher)    | {} |", apache_average);
-    println!("| Apache (interpreter) | {} |", apache_interpreter_average);
+    let runtime = runtime::by_name(&args.runtime).unwrap_or_else(|e| panic!("{}", e));
+    if args.max_regression.is_some() && args.baseline.is_none() {
+        panic!("--max-regression requires --baseline");
+    }
+    // fail early on a missing baseline, not after the experiments
+    let baseline = args.baseline.as_deref().map(results::read);
+    let experiments = match &args.scenario {
+        Some(path) => scenario::read_scenario(path),
+        None => scenario::default_experiments(args.qbasic),
+    };
+    let mut experiments = scenario::select(experiments, &args.only, &args.skip);
+    for experiment in experiments.iter_mut().filter(|e| e.kind == Kind::Httpd) {
+        experiment.concurrency = args.concurrency.unwrap_or(experiment.concurrency);
+        experiment.rate = args.rate.or(experiment.rate);
+        experiment.duration = args.duration.or(experiment.duration);
+        experiment.keep_alive |= args.keep_alive;
+    }
+    let metadata = Metadata::collect(
+        runtime.as_ref(),
+        args.qbasic,
+        args.count,
+        args.warmup,
+        args.scenario.as_deref(),
+    );
+    let mut built: HashSet<String> = HashSet::new();
+    let mut results = Results {
+        metadata,
+        experiments: vec![],
+    };
+    for e in &experiments {
+        match run_experiment(runtime.as_ref(), e, &args, &mut built) {
+            Ok(result) => results.experiments.push(result),
+            Err(error) => {
+                eprintln!("Experiment {} failed: {}", e.name, error);
+                exit(1);
+            }
+        }
+    }
+    println!("Summary (msec):");
+    println!("| Experiment           |    Mean | 95% CI          |  Median |     p90 |     p99 |     Min |     Max | Std dev |");
+    println!("| -------------------- | ------: | --------------- | ------: | ------: | ------: | ------: | ------: | ------: |");
+    for e in &results.experiments {
+        let stats = &e.stats;
+        let ci = match stats.ci95_range() {
+            Some((low, high)) => format!("{:.1}..{:.1}", low, high),
+            None => "-".to_string(),
+        };
+        println!(
+            "| {:20} | {:7.1} | {:15} | {:7.1} | {:7.1} | {:7.1} | {:7.1} | {:7.1} | {:7.1} |",
+            e.name,
+            stats.mean,
+            ci,
+            stats.median,
+            stats.p90,
+            stats.p99,
+            stats.min,
+            stats.max,
+            stats.stddev
+        );
+    }
+    let phases: Vec<(&str, &PhaseSummary)> = results
+        .experiments
+        .iter()
+        .filter_map(|e| e.http.as_ref().map(|h| (e.name.as_str(), h)))
+        .collect();
+    if !phases.is_empty() {
+        println!("Request phases, mean (msec):");
+        println!("| Experiment           | Connect |    Send | First byte | Receive |");
+        println!("| -------------------- | ------: | ------: | ---------: | ------: |");
+        for (name, h) in phases {
+            println!(
+                "| {:20} | {:7.2} | {:7.2} | {:10.2} | {:7.2} |",
+                name, h.connect_ms, h.send_ms, h.first_byte_ms, h.receive_ms
+            );
+        }
+    }
+    for e in results
+        .experiments
+        .iter()
+        .filter(|e| e.startup_ms.is_some())
+    {
+        println!(
+            "{}: Apache answered {:.1} msec after its container was started",
+            e.name,
+            e.startup_ms.unwrap()
+        );
+    }
+    for e in &results.experiments {
+        if let Some(workload) = &e.workload {
+            println!("{} workload:", e.name);
+            println!("| Operation | Requests | Failures | Mean (msec) |");
+            println!("| --------- | -------: | -------: | ----------: |");
+            for (name, o) in &workload.operations {
+                println!(
+                    "| {:9} | {:8} | {:8} | {:11.1} |",
+                    name, o.count, o.failures, o.mean_ms
+                );
+            }
+            for message in &workload.messages {
+                println!("Failed: {}", message);
+            }
+            if workload.failures as usize > workload.messages.len() {
+                println!(
+                    "... and {} more failures",
+                    workload.failures as usize - workload.messages.len()
+                );
+            }
+        }
+    }
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => args.root.join("perf").join("results"),
+    };
+    let (json_file, csv_file) = results::write(&results, &output);
+    println!(
+        "Results written to {} and {}",
+        json_file.display(),
+        csv_file.display()
+    );
+    if let Some(baseline) = baseline {
+        println!("Compared with {}:", args.baseline.as_deref().unwrap());
+        compare::check(
+            &baseline,
+            &results,
+            Some(args.max_regression.unwrap_or(DEFAULT_MAX_REGRESSION)),
+        );
+    }
 }
 
-// TODO make wrapper clients for launching curl, docker, etc
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runtime::tests::FakeRuntime;
+
+    fn args() -> Args {
+        Args {
+            count: 2,
+            warmup: 1,
+            quiet: true,
+            qbasic: false,
+            scenario: None,
+            only: vec![],
+            skip: vec![],
+            output: None,
+            root: PathBuf::from("/repo/basic"),
+            launcher: None,
+            baseline: None,
+            max_regression: None,
+            concurrency: None,
+            rate: None,
+            duration: None,
+            keep_alive: false,
+            runtime: "fake".to_string(),
+            port: DEFAULT_PORT,
+            startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+        }
+    }
+
+    fn experiment(name: &str) -> Experiment {
+        scenario::default_experiments(false)
+            .into_iter()
+            .find(|e| e.name == name)
+            .unwrap()
+    }
+
+    /// Answers every request with the given status, on the returned port.
+    fn serve(status: u16) -> u32 {
+        http::tests::serve(move |_| Some(http::tests::respond(status, ""))).port() as u32
+    }
+
+    #[test]
+    fn test_images_are_built_once() {
+        let runtime = FakeRuntime::default();
+        let mut built = HashSet::new();
+        let experiment = experiment("Docker (outside)");
+        build_image(&runtime, &experiment, &args(), &mut built).unwrap();
+        build_image(&runtime, &experiment, &args(), &mut built).unwrap();
+        assert_eq!(
+            runtime.calls(),
+            ["build basic /repo/basic/Dockerfile.standalone /repo/basic"]
+        );
+    }
+
+    #[test]
+    fn test_failed_build() {
+        let runtime = FakeRuntime {
+            fail: Some("build"),
+            ..FakeRuntime::default()
+        };
+        let mut built = HashSet::new();
+        let experiment = experiment("Docker (outside)");
+        assert!(run_experiment(&runtime, &experiment, &args(), &mut built).is_err());
+        assert!(build_image(&runtime, &experiment, &args(), &mut built).is_err());
+        assert_eq!(runtime.calls().len(), 2);
+    }
+
+    #[test]
+    fn test_docker_outside() {
+        let runtime = FakeRuntime::default();
+        let experiment = experiment("Docker (outside)");
+        let result = run_experiment(&runtime, &experiment, &args(), &mut HashSet::new()).unwrap();
+        assert_eq!(result.stats.count, 2);
+        let calls = runtime.calls();
+        // the warmup iteration, then the measured ones
+        assert_eq!(calls.len(), 4);
+        assert!(
+            calls[1].starts_with(
+                "run --rm -v /repo/basic/bin:/basic/bin -v /repo/basic/basic/src:/basic/src "
+            ),
+            "{}",
+            calls[1]
+        );
+        assert!(calls[1].ends_with(" basic HELLO.BAS"), "{}", calls[1]);
+    }
+
+    #[test]
+    fn test_local_without_launcher() {
+        let runtime = FakeRuntime::default();
+        let error =
+            run_experiment(&runtime, &experiment("DOS"), &args(), &mut HashSet::new()).unwrap_err();
+        assert!(error.contains("--launcher"), "{}", error);
+        assert!(runtime.calls().is_empty());
+    }
+
+    #[test]
+    fn test_docker_inside() {
+        let runtime = FakeRuntime {
+            output: "elapsed_ns 1000000\nelapsed_ns 3000000\n".to_string(),
+            ..FakeRuntime::default()
+        };
+        let experiment = experiment("Docker (inside)");
+        let result = run_experiment(&runtime, &experiment, &args(), &mut HashSet::new()).unwrap();
+        assert_eq!(result.samples_ms, [1.0, 3.0]);
+        assert!(
+            runtime.calls()[1].contains("--entrypoint bash basic /usr/local/perf/bin/perf-inside.sh /basic/src/HELLO.BAS 2 1 --quiet"),
+            "{:?}",
+            runtime.calls()
+        );
+    }
+
+    #[test]
+    fn test_httpd() {
+        let runtime = FakeRuntime::default();
+        let experiment = experiment("Apache (launcher)");
+        let args = Args {
+            port: serve(200),
+            ..args()
+        };
+        let result = run_experiment(&runtime, &experiment, &args, &mut HashSet::new()).unwrap();
+        assert_eq!(result.stats.count, 2);
+        assert!(result.http.is_some());
+        assert!(result.startup_ms.is_some());
+        let calls = runtime.calls();
+        assert!(
+            calls[1].starts_with("run --rm -d --name basic-httpd "),
+            "{}",
+            calls[1]
+        );
+        assert!(
+            calls[1].contains(&format!("-p {}:80 ", args.port)),
+            "{}",
+            calls[1]
+        );
+        assert_eq!(calls[2], "stop basic-httpd");
+    }
+
+    #[test]
+    fn test_httpd_is_stopped_when_the_experiment_fails() {
+        let runtime = FakeRuntime::default();
+        let experiment = experiment("Apache (launcher)");
+        let args = Args {
+            port: serve(500),
+            ..args()
+        };
+        let error = run_experiment(&runtime, &experiment, &args, &mut HashSet::new()).unwrap_err();
+        assert_eq!(error, "Request failed with HTTP status 500");
+        assert_eq!(runtime.calls().last().unwrap(), "stop basic-httpd");
+    }
+
+    #[test]
+    fn test_httpd_startup_timeout() {
+        let runtime = FakeRuntime::default();
+        let experiment = experiment("Apache (launcher)");
+        let (_listener, address) = http::tests::unanswered();
+        let args = Args {
+            port: address.port() as u32,
+            startup_timeout: 0.2,
+            ..args()
+        };
+        let error = run_experiment(&runtime, &experiment, &args, &mut HashSet::new()).unwrap_err();
+        assert!(error.starts_with("Apache did not start: "), "{}", error);
+        assert_eq!(runtime.calls().last().unwrap(), "stop basic-httpd");
+    }
+}
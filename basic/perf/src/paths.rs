@@ -0,0 +1,107 @@
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+/// The file name of the launcher on this platform.
+pub fn launcher_file_name() -> String {
+    format!("basic-launcher-rust{}", env::consts::EXE_SUFFIX)
+}
+
+/// Looks for the launcher where cargo builds it in the repository, preferring
+/// the release build.
+pub fn find_launcher(root: &Path) -> Result<PathBuf, String> {
+    let target = root.join("basic-launcher-rust").join("target");
+    let candidates: Vec<PathBuf> = ["release", "debug"]
+        .iter()
+        .map(|profile| target.join(profile).join(launcher_file_name()))
+        .collect();
+    candidates
+        .iter()
+        .find(|candidate| candidate.is_file())
+        .cloned()
+        .ok_or_else(|| {
+            format!(
+                "Could not find the launcher at {}, build it with `make build-launcher` or give --launcher",
+                candidates[0].display()
+            )
+        })
+}
+
+/// Makes a path absolute against the current directory, without resolving
+/// links (which would give `\\?\C:\` paths on Windows).
+pub fn absolute(path: &Path) -> PathBuf {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir()
+            .expect("Could not get the current directory")
+            .join(path)
+    };
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+/// The host side of a volume, the way the container runtime of this platform
+/// takes it.
+pub fn volume_path(path: &Path) -> String {
+    to_volume_path(&path.display().to_string(), cfg!(windows))
+}
+
+/// On Windows, converts e.g. `C:\Users\basic` to `/c/Users/basic`, which
+/// Docker understands from an MSYS shell. Other paths are left alone.
+fn to_volume_path(path: &str, windows: bool) -> String {
+    if !windows {
+        return path.to_string();
+    }
+    let path = path.replace('\\', "/");
+    let mut chars = path.chars();
+    match (chars.next(), chars.next()) {
+        (Some(drive), Some(':')) if drive.is_ascii_alphabetic() => {
+            format!("/{}{}", drive.to_ascii_lowercase(), chars.as_str())
+        }
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_to_volume_path() {
+        assert_eq!(
+            to_volume_path("C:\\Users\\ngeor\\basic\\bin", true),
+            "/c/Users/ngeor/basic/bin"
+        );
+        assert_eq!(to_volume_path("D:/basic", true), "/d/basic");
+        assert_eq!(
+            to_volume_path("/home/ngeor/basic", false),
+            "/home/ngeor/basic"
+        );
+        assert_eq!(to_volume_path("C:\\basic", false), "C:\\basic");
+    }
+
+    #[test]
+    fn test_absolute() {
+        let current = env::current_dir().unwrap();
+        assert_eq!(absolute(Path::new("./bin")), current.join("bin"));
+        assert_eq!(absolute(&current.join("src")), current.join("src"));
+    }
+
+    #[test]
+    fn test_find_launcher() {
+        let root = env::temp_dir().join(format!("perf-paths-{}", std::process::id()));
+        assert!(find_launcher(&root)
+            .unwrap_err()
+            .contains("Could not find the launcher"));
+        let debug = root.join("basic-launcher-rust/target/debug");
+        fs::create_dir_all(&debug).unwrap();
+        fs::write(debug.join(launcher_file_name()), "").unwrap();
+        assert_eq!(
+            find_launcher(&root).unwrap(),
+            debug.join(launcher_file_name())
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
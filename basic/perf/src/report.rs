@@ -0,0 +1,152 @@
+use std::fmt::Write as _;
+
+use crate::results::{self, Results};
+
+/// Formats a duration like the tables of Performance.md, without trailing
+/// zeros, e.g. `1756.8` or `1228`.
+fn format_msec(msec: f64) -> String {
+    let result = format!("{:.2}", msec);
+    result
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Renders a markdown table in the format of Performance.md.
+pub fn markdown_table(results: &Results) -> String {
+    let header = ("Experiment", "Average duration (msec)");
+    let rows: Vec<(String, String)> = results
+        .experiments
+        .iter()
+        .map(|e| (e.name.clone(), format_msec(e.stats.mean)))
+        .collect();
+    let name_width = rows
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain([header.0.len()])
+        .max()
+        .unwrap();
+    let value_width = rows
+        .iter()
+        .map(|(_, value)| value.len())
+        .chain([header.1.len()])
+        .max()
+        .unwrap();
+    let mut result = String::new();
+    writeln!(
+        result,
+        "| {:name_width$} | {:value_width$} |",
+        header.0, header.1
+    )
+    .unwrap();
+    writeln!(
+        result,
+        "| {} | {}: |",
+        "-".repeat(name_width),
+        "-".repeat(value_width - 1)
+    )
+    .unwrap();
+    for (name, value) in rows {
+        writeln!(result, "| {:name_width$} | {:>value_width$} |", name, value).unwrap();
+    }
+    result
+}
+
+/// A sentence about where the results come from.
+fn describe(results: &Results) -> String {
+    let m = &results.metadata;
+    let mut result = format!(
+        "{}, {} iterations after {} warmup",
+        if m.mode == "qbasic" {
+            "QBasic"
+        } else {
+            "GW-Basic"
+        },
+        m.count,
+        m.warmup
+    );
+    for (label, value) in [
+        ("commit", &m.git_commit),
+        ("host", &m.host),
+        ("Docker", &m.docker_version),
+        ("DOSBox", &m.dosbox_version),
+    ] {
+        if let Some(value) = value {
+            write!(result, ", {} {}", label, value).unwrap();
+        }
+    }
+    result.push('.');
+    result
+}
+
+/// Renders a section for Performance.md: a heading with the title and the
+/// date of the run, if a title is given, where the results come from and
+/// the table.
+pub fn render(results: &Results, title: Option<&str>) -> String {
+    let mut result = String::new();
+    if let Some(title) = title {
+        writeln!(result, "# {} ({})\n", title, results.metadata.date()).unwrap();
+    }
+    writeln!(result, "{}\n", describe(results)).unwrap();
+    result.push_str(&markdown_table(results));
+    result
+}
+
+/// Implements the `report` command: `report FILE [--title TITLE]`.
+pub fn report_command(args: &[String]) {
+    let mut file: Option<&str> = None;
+    let mut title: Option<&str> = None;
+    let mut iterator = args.iter();
+    while let Some(value) = iterator.next() {
+        if value == "--title" {
+            title = match iterator.next() {
+                Some(x) => Some(x),
+                None => panic!("--title requires an argument"),
+            };
+        } else if file.is_none() {
+            file = Some(value);
+        } else {
+            panic!("Unexpected parameter {}", value);
+        }
+    }
+    let file = file.expect("Usage: perf report FILE [--title TITLE]");
+    print!("{}", render(&results::read(file), title));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::tests::sample_results;
+
+    #[test]
+    fn test_format_msec() {
+        assert_eq!(format_msec(1756.8), "1756.8");
+        assert_eq!(format_msec(1228.0), "1228");
+        assert_eq!(format_msec(663.976), "663.98");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut results = sample_results();
+        let mut second = results.experiments[0].clone();
+        second.name = "Apache (interpreter)".to_string();
+        second.stats.mean = 265.4;
+        results.experiments.push(second);
+        assert_eq!(
+            render(&results, Some("v0.6.0")),
+            "# v0.6.0 (2020-07-27)\n\
+             \n\
+             GW-Basic, 3 iterations after 1 warmup, commit abc1234, host build, 1, DOSBox 0.74-3.\n\
+             \n\
+             | Experiment           | Average duration (msec) |\n\
+             | -------------------- | ----------------------: |\n\
+             | DOS                  |                    1100 |\n\
+             | Apache (interpreter) |                   265.4 |\n"
+        );
+    }
+
+    #[test]
+    fn test_render_without_title() {
+        assert!(render(&sample_results(), None).starts_with("GW-Basic, 3 iterations"));
+    }
+}
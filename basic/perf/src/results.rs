@@ -0,0 +1,401 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::http::PhaseSummary;
+use crate::load::LoadSummary;
+use crate::runtime::Runtime;
+use crate::scenario::{Backend, Experiment, Kind};
+use crate::stats::Stats;
+use crate::workload::WorkloadSummary;
+
+/// The results of a run of perf, as written to its JSON file.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Results {
+    pub metadata: Metadata,
+    pub experiments: Vec<ExperimentResult>,
+}
+
+/// Where and how the experiments ran. What could not be found out is `None`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Metadata {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub git_commit: Option<String>,
+    pub host: Option<String>,
+    /// The version of the container runtime, Docker or Podman.
+    pub docker_version: Option<String>,
+    pub dosbox_version: Option<String>,
+    /// `gwbasic` or `qbasic`.
+    pub mode: String,
+    pub count: i32,
+    pub warmup: i32,
+    pub scenario: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ExperimentResult {
+    pub name: String,
+    pub kind: Kind,
+    pub backend: Backend,
+    pub program: String,
+    pub stats: Stats,
+    /// The duration of every iteration, in milliseconds.
+    pub samples_ms: Vec<f64>,
+    /// For load tests, the throughput and the statuses of the responses.
+    #[serde(default)]
+    pub load: Option<LoadSummary>,
+    /// For httpd experiments, the mean duration of the phases of a request.
+    #[serde(default)]
+    pub http: Option<PhaseSummary>,
+    /// For CRUD workloads, the operations and the failed checks.
+    #[serde(default)]
+    pub workload: Option<WorkloadSummary>,
+    /// For httpd experiments, how long Apache took to answer after its
+    /// container was started.
+    #[serde(default)]
+    pub startup_ms: Option<f64>,
+}
+
+impl ExperimentResult {
+    pub fn new(
+        experiment: &Experiment,
+        durations: &[Duration],
+        load: Option<LoadSummary>,
+        http: Option<PhaseSummary>,
+    ) -> Self {
+        Self {
+            name: experiment.name.clone(),
+            kind: experiment.kind,
+            backend: experiment.backend,
+            program: experiment.program.clone(),
+            stats: Stats::from_durations(durations),
+            samples_ms: durations.iter().map(|d| d.as_secs_f64() * 1000.0).collect(),
+            load,
+            http,
+            workload: None,
+            startup_ms: None,
+        }
+    }
+}
+
+/// The first line a command prints, if it runs successfully.
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // DOSBox prints its version on stderr on some platforms
+    let text = if output.stdout.is_empty() {
+        output.stderr
+    } else {
+        output.stdout
+    };
+    String::from_utf8_lossy(&text)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+impl Metadata {
+    /// Finds out the environment of the current run.
+    pub fn collect(
+        runtime: &dyn Runtime,
+        qbasic: bool,
+        count: i32,
+        warmup: i32,
+        scenario: Option<&str>,
+    ) -> Self {
+        let dosbox = env::var("BLR_DOSBOX").unwrap_or_else(|_| "dosbox".to_string());
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            git_commit: command_output("git", &["rev-parse", "--short", "HEAD"]),
+            host: env::var("HOSTNAME")
+                .or_else(|_| env::var("COMPUTERNAME"))
+                .ok()
+                .or_else(|| command_output("hostname", &[])),
+            docker_version: runtime.version(),
+            dosbox_version: command_output(&dosbox, &["-version"])
+                .map(|line| parse_dosbox_version(&line)),
+            mode: if qbasic { "qbasic" } else { "gwbasic" }.to_string(),
+            count,
+            warmup,
+            scenario: scenario.map(str::to_string),
+        }
+    }
+
+    /// The day of the run, e.g. `2020-07-27`.
+    pub fn date(&self) -> String {
+        let (year, month, day) = civil_from_days((self.timestamp / 86400) as i64);
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+/// Gets e.g. `0.74-3` from `DOSBox version 0.74-3, copyright 2002-2019 DOSBox Team.`
+fn parse_dosbox_version(line: &str) -> String {
+    match line.split_once("version ") {
+        Some((_, rest)) => rest
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        None => line.to_string(),
+    }
+}
+
+/// Converts days since the Unix epoch to a date of the Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One row per experiment, with the metadata repeated in every row so that
+/// the rows of several runs can be concatenated.
+pub fn to_csv(results: &Results) -> String {
+    let mut result = String::from(
+        "timestamp,git_commit,host,docker_version,dosbox_version,mode,count,warmup,\
+         experiment,kind,backend,n,mean_ms,ci95_ms,median_ms,p90_ms,p99_ms,min_ms,max_ms,stddev_ms,\
+         concurrency,throughput_rps,errors,connect_ms,send_ms,first_byte_ms,receive_ms,\
+         failures,startup_ms\n",
+    );
+    let m = &results.metadata;
+    for e in &results.experiments {
+        let s = &e.stats;
+        let mut fields: Vec<String> = vec![
+            m.timestamp.to_string(),
+            m.git_commit.clone().unwrap_or_default(),
+            m.host.clone().unwrap_or_default(),
+            m.docker_version.clone().unwrap_or_default(),
+            m.dosbox_version.clone().unwrap_or_default(),
+            m.mode.clone(),
+            m.count.to_string(),
+            m.warmup.to_string(),
+            e.name.clone(),
+            e.kind.name().to_string(),
+            e.backend.name().to_string(),
+            s.count.to_string(),
+            format!("{:.3}", s.mean),
+            s.ci95.map(|c| format!("{:.3}", c)).unwrap_or_default(),
+            format!("{:.3}", s.median),
+            format!("{:.3}", s.p90),
+            format!("{:.3}", s.p99),
+            format!("{:.3}", s.min),
+            format!("{:.3}", s.max),
+            format!("{:.3}", s.stddev),
+            e.load.as_ref().map_or(1, |l| l.concurrency).to_string(),
+            e.load
+                .as_ref()
+                .map(|l| format!("{:.3}", l.throughput))
+                .unwrap_or_default(),
+            e.load
+                .as_ref()
+                .map(|l| l.errors().to_string())
+                .unwrap_or_default(),
+        ];
+        match &e.http {
+            Some(h) => fields.extend(
+                [h.connect_ms, h.send_ms, h.first_byte_ms, h.receive_ms]
+                    .iter()
+                    .map(|ms| format!("{:.3}", ms)),
+            ),
+            None => fields.extend(vec![String::new(); 4]),
+        }
+        fields.push(
+            e.workload
+                .as_ref()
+                .map(|w| w.failures.to_string())
+                .unwrap_or_default(),
+        );
+        fields.push(
+            e.startup_ms
+                .map(|ms| format!("{:.3}", ms))
+                .unwrap_or_default(),
+        );
+        let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        writeln!(result, "{}", fields.join(",")).unwrap();
+    }
+    result
+}
+
+/// Writes the results as `perf-TIMESTAMP.json` and `perf-TIMESTAMP.csv` in
+/// the given directory and returns their paths.
+pub fn write(results: &Results, dir: &Path) -> (PathBuf, PathBuf) {
+    fs::create_dir_all(dir).unwrap_or_else(|e| panic!("Could not create {}: {}", dir.display(), e));
+    let stem = format!("perf-{}", results.metadata.timestamp);
+    let json_file = dir.join(format!("{}.json", stem));
+    let csv_file = dir.join(format!("{}.csv", stem));
+    let json = serde_json::to_string_pretty(results).unwrap();
+    fs::write(&json_file, json + "\n")
+        .unwrap_or_else(|e| panic!("Could not write {}: {}", json_file.display(), e));
+    fs::write(&csv_file, to_csv(results))
+        .unwrap_or_else(|e| panic!("Could not write {}: {}", csv_file.display(), e));
+    (json_file, csv_file)
+}
+
+/// Reads a JSON result file, panicking if it is not valid.
+pub fn read(path: &str) -> Results {
+    let contents =
+        fs::read_to_string(path).unwrap_or_else(|e| panic!("Could not read {}: {}", path, e));
+    serde_json::from_str(&contents).unwrap_or_else(|e| panic!("{}: {}", path, e))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    pub fn sample_results() -> Results {
+        let experiment = crate::scenario::default_experiments(false).remove(0);
+        Results {
+            metadata: Metadata {
+                timestamp: 1595808000,
+                git_commit: Some("abc1234".to_string()),
+                host: Some("build, 1".to_string()),
+                docker_version: None,
+                dosbox_version: Some("0.74-3".to_string()),
+                mode: "gwbasic".to_string(),
+                count: 3,
+                warmup: 1,
+                scenario: None,
+            },
+            experiments: vec![ExperimentResult::new(
+                &experiment,
+                &[
+                    Duration::from_millis(1000),
+                    Duration::from_millis(1200),
+                    Duration::from_millis(1100),
+                ],
+                None,
+                None,
+            )],
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let results = sample_results();
+        let json = serde_json::to_string(&results).unwrap();
+        assert!(json.contains("\"kind\":\"local\""), "{}", json);
+        assert!(
+            json.contains("\"samples_ms\":[1000.0,1200.0,1100.0]"),
+            "{}",
+            json
+        );
+        let parsed: Results = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, results);
+    }
+
+    #[test]
+    fn test_csv() {
+        let csv = to_csv(&sample_results());
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("timestamp,git_commit,host,"));
+        assert!(
+            lines[1].starts_with(
+                "1595808000,abc1234,\"build, 1\",,0.74-3,gwbasic,3,1,DOS,local,launcher,3,1100.000,"
+            ),
+            "{}",
+            lines[1]
+        );
+        assert!(lines[1].ends_with(",1,,,,,,,,"), "{}", lines[1]);
+    }
+
+    #[test]
+    fn test_csv_of_load_test() {
+        let mut results = sample_results();
+        results.experiments[0].load = Some(LoadSummary {
+            concurrency: 4,
+            rate: None,
+            elapsed_s: 2.0,
+            throughput: 1.5,
+            statuses: BTreeMap::from([("200".to_string(), 2), ("500".to_string(), 1)]),
+        });
+        results.experiments[0].http = Some(PhaseSummary {
+            connect_ms: 0.1,
+            send_ms: 0.02,
+            first_byte_ms: 250.0,
+            receive_ms: 0.5,
+        });
+        let csv = to_csv(&results);
+        assert!(
+            csv.ends_with(",4,1.500,1,0.100,0.020,250.000,0.500,,\n"),
+            "{}",
+            csv
+        );
+    }
+
+    #[test]
+    fn test_csv_of_workload() {
+        let mut results = sample_results();
+        results.experiments[0].workload = Some(WorkloadSummary {
+            failures: 2,
+            ..WorkloadSummary::default()
+        });
+        results.experiments[0].startup_ms = Some(1500.0);
+        let csv = to_csv(&results);
+        assert!(csv.ends_with(",,,,,2,1500.000\n"), "{}", csv);
+    }
+
+    #[test]
+    fn test_results_without_load_can_be_read() {
+        let mut json = serde_json::to_value(sample_results()).unwrap();
+        json["experiments"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("load");
+        json["experiments"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("http");
+        json["experiments"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("workload");
+        let parsed: Results = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.experiments[0].load, None);
+        assert_eq!(parsed.experiments[0].http, None);
+        assert_eq!(parsed.experiments[0].workload, None);
+    }
+
+    #[test]
+    fn test_parse_dosbox_version() {
+        assert_eq!(
+            parse_dosbox_version("DOSBox version 0.74-3, copyright 2002-2019 DOSBox Team."),
+            "0.74-3"
+        );
+        assert_eq!(parse_dosbox_version("SVN-r4336"), "SVN-r4336");
+    }
+
+    #[test]
+    fn test_date() {
+        assert_eq!(sample_results().metadata.date(), "2020-07-27");
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+    }
+}
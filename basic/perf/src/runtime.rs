@@ -0,0 +1,373 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Builds images and runs containers, e.g. with the Docker CLI.
+pub trait Runtime {
+    /// The name of the runtime, e.g. `docker`.
+    fn name(&self) -> &str;
+
+    /// The version of the runtime, if it can be found out.
+    fn version(&self) -> Option<String>;
+
+    /// Builds the image from the directory `context`.
+    fn build(
+        &self,
+        tag: &str,
+        docker_file: &Path,
+        context: &Path,
+        quiet: bool,
+    ) -> Result<(), String>;
+
+    /// Returns what the container printed, if `quiet` (otherwise it is shown).
+    fn run(&self, image: &str, options: RunOptions, quiet: bool) -> Result<String, String>;
+
+    fn stop(&self, name: &str, quiet: bool) -> Result<(), String>;
+}
+
+pub struct Volume {
+    pub host: String,
+    pub guest: String,
+}
+
+pub struct Port {
+    pub host: u32,
+    pub guest: u32,
+}
+
+pub struct RunOptions {
+    pub args: Vec<String>,
+    pub volumes: Vec<Volume>,
+    pub ports: Vec<Port>,
+    pub env: Vec<String>,
+    pub entry_point: Option<String>,
+    pub detach: bool,
+    pub container_name: Option<String>,
+}
+
+impl RunOptions {
+    /// The arguments of `docker run` for the image, which Podman understands
+    /// too.
+    pub fn to_args(&self, image: &str) -> Vec<String> {
+        let mut run_args: Vec<String> = vec![];
+        run_args.push("run".to_string());
+        run_args.push("--rm".to_string());
+        if self.detach {
+            run_args.push("-d".to_string());
+        }
+        if let Some(name) = &self.container_name {
+            run_args.push("--name".to_string());
+            run_args.push(name.clone());
+        }
+        for volume in &self.volumes {
+            run_args.push("-v".to_string());
+            run_args.push(format!("{}:{}", volume.host, volume.guest));
+        }
+        for e in &self.env {
+            if !e.is_empty() {
+                run_args.push("-e".to_string());
+                run_args.push(e.clone());
+            }
+        }
+        for port in &self.ports {
+            run_args.push("-p".to_string());
+            run_args.push(format!("{}:{}", port.host, port.guest));
+        }
+        if let Some(entry_point) = &self.entry_point {
+            run_args.push("--entrypoint".to_string());
+            run_args.push(entry_point.clone());
+        }
+        run_args.push(image.to_string());
+        run_args.extend(self.args.iter().cloned());
+        run_args
+    }
+}
+
+/// A runtime with a Docker compatible command line: `docker` or `podman`.
+pub struct Cli {
+    program: &'static str,
+}
+
+impl Cli {
+    pub fn docker() -> Self {
+        Self { program: "docker" }
+    }
+
+    pub fn podman() -> Self {
+        Self { program: "podman" }
+    }
+
+    fn execute(&self, args: &[String], quiet: bool) -> Result<String, String> {
+        let output = Command::new(self.program)
+            .args(args)
+            .stdout(if quiet {
+                Stdio::piped()
+            } else {
+                Stdio::inherit()
+            })
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| format!("Could not execute {}: {}", self.program, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} {} failed with {}",
+                self.program,
+                args.first().map(String::as_str).unwrap_or_default(),
+                output.status
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl Runtime for Cli {
+    fn name(&self) -> &str {
+        self.program
+    }
+
+    fn version(&self) -> Option<String> {
+        let output = Command::new(self.program)
+            .args(["version", "--format", "{{.Server.Version}}"])
+            .output()
+            .ok()?;
+        let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        // Podman without a remote service has no server
+        if output.status.success() && !version.is_empty() && version != "<no value>" {
+            return Some(version);
+        }
+        let output = Command::new(self.program).arg("--version").output().ok()?;
+        let line = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        // e.g. `podman version 4.9.3`
+        line.rsplit(' ')
+            .next()
+            .filter(|v| output.status.success() && !v.is_empty())
+            .map(|v| v.trim_end_matches(',').to_string())
+    }
+
+    fn build(
+        &self,
+        tag: &str,
+        docker_file: &Path,
+        context: &Path,
+        quiet: bool,
+    ) -> Result<(), String> {
+        let args: Vec<String> = vec![
+            "build".to_string(),
+            "-t".to_string(),
+            tag.to_string(),
+            "-f".to_string(),
+            docker_file.display().to_string(),
+            context.display().to_string(),
+        ];
+        self.execute(&args, quiet)
+            .map(|_| ())
+            .map_err(|e| format!("Could not build image {}: {}", tag, e))
+    }
+
+    fn run(&self, image: &str, options: RunOptions, quiet: bool) -> Result<String, String> {
+        let run_args = options.to_args(image);
+        if !quiet {
+            println!("{} {}", self.program, run_args.join(" "));
+        }
+        self.execute(&run_args, quiet)
+    }
+
+    fn stop(&self, name: &str, quiet: bool) -> Result<(), String> {
+        self.execute(&["stop".to_string(), name.to_string()], quiet)
+            .map(|_| ())
+            .map_err(|e| format!("Could not stop container {}: {}", name, e))
+    }
+}
+
+/// The runtime with the given name, `docker` or `podman`.
+pub fn by_name(name: &str) -> Result<Box<dyn Runtime>, String> {
+    match name {
+        "docker" => Ok(Box::new(Cli::docker())),
+        "podman" => Ok(Box::new(Cli::podman())),
+        _ => Err(format!(
+            "Unknown container runtime {}, use docker or podman",
+            name
+        )),
+    }
+}
+
+/// A container that runs in the background until it is stopped, at the
+/// latest when the guard is dropped, so that it doesn't outlive a failed
+/// experiment.
+pub struct Container<'a> {
+    runtime: &'a dyn Runtime,
+    name: String,
+    quiet: bool,
+    running: bool,
+}
+
+impl<'a> Container<'a> {
+    /// Runs the image detached, as a container with the given name.
+    pub fn start(
+        runtime: &'a dyn Runtime,
+        image: &str,
+        name: &str,
+        options: RunOptions,
+        quiet: bool,
+    ) -> Result<Self, String> {
+        let options = RunOptions {
+            detach: true,
+            container_name: Some(name.to_string()),
+            ..options
+        };
+        runtime.run(image, options, quiet)?;
+        Ok(Self {
+            runtime,
+            name: name.to_string(),
+            quiet,
+            running: true,
+        })
+    }
+
+    pub fn stop(mut self) -> Result<(), String> {
+        self.running = false;
+        self.runtime.stop(&self.name, self.quiet)
+    }
+}
+
+impl Drop for Container<'_> {
+    fn drop(&mut self) {
+        if self.running {
+            if let Err(e) = self.runtime.stop(&self.name, self.quiet) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// A runtime that records what it is asked to do instead of doing it.
+    #[derive(Default)]
+    pub struct FakeRuntime {
+        /// The calls so far, e.g. `stop basic-httpd`.
+        pub calls: RefCell<Vec<String>>,
+        /// Calls that start with this fail.
+        pub fail: Option<&'static str>,
+        /// What `run` returns.
+        pub output: String,
+    }
+
+    impl FakeRuntime {
+        fn record(&self, call: String) -> Result<(), String> {
+            let failed = self.fail.is_some_and(|fail| call.starts_with(fail));
+            self.calls.borrow_mut().push(call.clone());
+            if failed {
+                return Err(format!("{} failed", call));
+            }
+            Ok(())
+        }
+
+        pub fn calls(&self) -> Vec<String> {
+            self.calls.borrow().clone()
+        }
+    }
+
+    impl Runtime for FakeRuntime {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        fn version(&self) -> Option<String> {
+            None
+        }
+
+        fn build(
+            &self,
+            tag: &str,
+            docker_file: &Path,
+            context: &Path,
+            _quiet: bool,
+        ) -> Result<(), String> {
+            self.record(format!(
+                "build {} {} {}",
+                tag,
+                docker_file.display(),
+                context.display()
+            ))
+        }
+
+        fn run(&self, image: &str, options: RunOptions, _quiet: bool) -> Result<String, String> {
+            self.record(options.to_args(image).join(" "))?;
+            Ok(self.output.clone())
+        }
+
+        fn stop(&self, name: &str, _quiet: bool) -> Result<(), String> {
+            self.record(format!("stop {}", name))
+        }
+    }
+
+    fn options() -> RunOptions {
+        RunOptions {
+            args: vec!["HELLO.BAS".to_string()],
+            volumes: vec![Volume {
+                host: "/c/basic/bin".to_string(),
+                guest: "/basic/bin".to_string(),
+            }],
+            ports: vec![Port {
+                host: 8080,
+                guest: 80,
+            }],
+            env: vec!["".to_string(), "BLR_BASIC_MODE=qbasic".to_string()],
+            entry_point: None,
+            detach: false,
+            container_name: None,
+        }
+    }
+
+    #[test]
+    fn test_run_args() {
+        assert_eq!(
+            options().to_args("basic").join(" "),
+            "run --rm -v /c/basic/bin:/basic/bin -e BLR_BASIC_MODE=qbasic -p 8080:80 basic HELLO.BAS"
+        );
+    }
+
+    #[test]
+    fn test_container_stops_when_dropped() {
+        let runtime = FakeRuntime::default();
+        {
+            let _container =
+                Container::start(&runtime, "basic-httpd", "perf-httpd", options(), true).unwrap();
+        }
+        let calls = runtime.calls();
+        assert!(
+            calls[0].starts_with("run --rm -d --name perf-httpd "),
+            "{}",
+            calls[0]
+        );
+        assert_eq!(calls[1], "stop perf-httpd");
+    }
+
+    #[test]
+    fn test_container_stops_once() {
+        let runtime = FakeRuntime::default();
+        let container =
+            Container::start(&runtime, "basic-httpd", "perf-httpd", options(), true).unwrap();
+        container.stop().unwrap();
+        assert_eq!(runtime.calls().len(), 2);
+    }
+
+    #[test]
+    fn test_failed_start() {
+        let runtime = FakeRuntime {
+            fail: Some("run"),
+            ..FakeRuntime::default()
+        };
+        assert!(Container::start(&runtime, "basic-httpd", "perf-httpd", options(), true).is_err());
+        assert_eq!(runtime.calls().len(), 1);
+    }
+
+    #[test]
+    fn test_by_name() {
+        assert_eq!(by_name("podman").unwrap().name(), "podman");
+        assert!(by_name("lxc").is_err());
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::load::LoadOptions;
+use crate::workload::Mix;
+
+/// A scenario file, holding one or more `[[experiment]]` tables.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    #[serde(rename = "experiment")]
+    experiments: Vec<Experiment>,
+}
+
+/// How the program of an experiment is run.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    /// The launcher on the host.
+    Local,
+    /// A new container per iteration.
+    DockerOutside,
+    /// The launcher in a loop inside one container.
+    DockerInside,
+    /// Requests to a container running Apache.
+    Httpd,
+}
+
+impl Kind {
+    /// The name used in scenario files, e.g. `docker-outside`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Local => "local",
+            Kind::DockerOutside => "docker-outside",
+            Kind::DockerInside => "docker-inside",
+            Kind::Httpd => "httpd",
+        }
+    }
+}
+
+/// What runs the program behind Apache.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// The launcher, with DOSBox.
+    #[default]
+    Launcher,
+    /// The rusty_basic interpreter, without DOSBox.
+    Interpreter,
+}
+
+impl Backend {
+    /// The name used in scenario files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Launcher => "launcher",
+            Backend::Interpreter => "interpreter",
+        }
+    }
+}
+
+/// An experiment: a program, how to run it and how often.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Experiment {
+    pub name: String,
+    pub kind: Kind,
+    #[serde(default)]
+    pub backend: Backend,
+    /// The program, relative to the current directory. For `httpd`, the
+    /// directory of the programs, which is mounted as `/basic/src`.
+    pub program: String,
+    /// The Docker image, by default the one of the kind and backend.
+    pub image: Option<String>,
+    /// The Dockerfile of the image, by default the one of the kind and backend.
+    pub dockerfile: Option<String>,
+    /// Environment variables for the launcher, on top of `BLR_BASIC_MODE`.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Overrides `--count`.
+    pub count: Option<i32>,
+    /// For `httpd`, how many clients send requests at the same time.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: u32,
+    /// For `httpd`, a fixed number of requests per second.
+    pub rate: Option<f64>,
+    /// For `httpd`, how many seconds to send requests, instead of `count`.
+    pub duration: Option<f64>,
+    /// For `httpd`, the path of the request, `/api/todo` by default.
+    pub path: Option<String>,
+    /// For `httpd`, the body of the request, where `{n}` is replaced by the
+    /// number of the iteration. `hello {n}` by default.
+    pub body: Option<String>,
+    /// For `httpd`, whether every client keeps its connection open between
+    /// requests instead of connecting for each one.
+    #[serde(default)]
+    pub keep_alive: bool,
+    /// For `httpd`, the weights of a workload of list, create, read, update
+    /// and delete requests under `path`, instead of POSTing `body`.
+    pub mix: Option<Mix>,
+}
+
+fn default_concurrency() -> u32 {
+    1
+}
+
+impl Experiment {
+    fn new(name: &str, kind: Kind, backend: Backend, program: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            backend,
+            program: program.to_string(),
+            image: None,
+            dockerfile: None,
+            env: BTreeMap::new(),
+            count: None,
+            concurrency: default_concurrency(),
+            rate: None,
+            duration: None,
+            path: None,
+            body: None,
+            keep_alive: false,
+            mix: None,
+        }
+    }
+
+    pub fn image(&self) -> &str {
+        match (&self.image, self.kind, self.backend) {
+            (Some(image), _, _) => image,
+            (None, Kind::Httpd, Backend::Launcher) => "basic-httpd",
+            (None, Kind::Httpd, Backend::Interpreter) => "basic-interpreter-httpd",
+            (None, _, _) => "basic",
+        }
+    }
+
+    pub fn dockerfile(&self) -> &str {
+        match (&self.dockerfile, self.kind, self.backend) {
+            (Some(dockerfile), _, _) => dockerfile,
+            (None, Kind::Httpd, Backend::Launcher) => "Dockerfile.httpd",
+            (None, Kind::Httpd, Backend::Interpreter) => "Dockerfile.interpreter.httpd",
+            (None, _, _) => "Dockerfile.standalone",
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or("/api/todo")
+    }
+
+    pub fn body(&self, n: i32) -> String {
+        self.body
+            .as_deref()
+            .unwrap_or("hello {n}")
+            .replace("{n}", &n.to_string())
+    }
+
+    /// Whether `--only` or `--skip` refer to this experiment, by its name or
+    /// its kind, ignoring case.
+    fn matches(&self, selector: &str) -> bool {
+        self.name.eq_ignore_ascii_case(selector)
+            || self.kind.name() == selector.to_ascii_lowercase()
+    }
+
+    /// How to load Apache, if the experiment is not a plain sequence of
+    /// `count` requests.
+    pub fn load_options(&self, count: i32) -> Option<LoadOptions> {
+        if self.concurrency == 1 && self.rate.is_none() && self.duration.is_none() {
+            return None;
+        }
+        Some(LoadOptions {
+            concurrency: self.concurrency,
+            rate: self.rate,
+            duration: self.duration.map(Duration::from_secs_f64),
+            requests: count,
+        })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.backend == Backend::Interpreter && self.kind != Kind::Httpd {
+            return Err("the interpreter backend is only available with httpd".to_string());
+        }
+        if self.count.is_some_and(|count| count < 1) {
+            return Err("count must be positive".to_string());
+        }
+        if self.concurrency < 1 {
+            return Err("concurrency must be positive".to_string());
+        }
+        if self.rate.is_some_and(|rate| rate <= 0.0) {
+            return Err("rate must be positive".to_string());
+        }
+        if self.duration.is_some_and(|duration| duration <= 0.0) {
+            return Err("duration must be positive".to_string());
+        }
+        if self.kind != Kind::Httpd
+            && (self.load_options(1).is_some() || self.keep_alive || self.mix.is_some())
+        {
+            return Err(
+                "concurrency, rate, duration, keep_alive and mix are only available with httpd"
+                    .to_string(),
+            );
+        }
+        if self.mix.is_some_and(|mix| mix.total() == 0) {
+            return Err("mix needs an operation with a positive weight".to_string());
+        }
+        if self.mix.is_some() && self.body(1).is_empty() {
+            return Err("mix needs a body for the items it creates".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The experiments that run without a scenario file, in the order of
+/// Performance.md.
+pub fn default_experiments(qbasic: bool) -> Vec<Experiment> {
+    let program = if qbasic {
+        "basic/src/HELLOQB.BAS"
+    } else {
+        "basic/src/HELLO.BAS"
+    };
+    let rest = if qbasic {
+        "basic/rest-qb"
+    } else {
+        "basic/rest"
+    };
+    vec![
+        Experiment::new("DOS", Kind::Local, Backend::Launcher, program),
+        Experiment::new(
+            "Docker (outside)",
+            Kind::DockerOutside,
+            Backend::Launcher,
+            program,
+        ),
+        Experiment::new(
+            "Docker (inside)",
+            Kind::DockerInside,
+            Backend::Launcher,
+            program,
+        ),
+        Experiment::new("Apache (launcher)", Kind::Httpd, Backend::Launcher, rest),
+        Experiment::new(
+            "Apache (interpreter)",
+            Kind::Httpd,
+            Backend::Interpreter,
+            "basic/rest-qb-direct",
+        ),
+    ]
+}
+
+pub fn parse_scenario(contents: &str) -> Result<Vec<Experiment>, String> {
+    let parsed: ScenarioFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+    for experiment in &parsed.experiments {
+        experiment
+            .validate()
+            .map_err(|e| format!("experiment {}: {}", experiment.name, e))?;
+    }
+    Ok(parsed.experiments)
+}
+
+/// Reads a scenario file, panicking if it is not valid.
+pub fn read_scenario(path: &str) -> Vec<Experiment> {
+    let contents =
+        fs::read_to_string(path).unwrap_or_else(|e| panic!("Could not read {}: {}", path, e));
+    parse_scenario(&contents).unwrap_or_else(|e| panic!("{}: {}", path, e))
+}
+
+/// Keeps the experiments selected by `--only` (all if empty) and not by
+/// `--skip`. Panics if a selector matches no experiment, as it is probably
+/// a typo.
+pub fn select(experiments: Vec<Experiment>, only: &[String], skip: &[String]) -> Vec<Experiment> {
+    for selector in only.iter().chain(skip) {
+        if !experiments.iter().any(|e| e.matches(selector)) {
+            let names: Vec<&str> = experiments.iter().map(|e| e.name.as_str()).collect();
+            panic!(
+                "No experiment matches {}, available: {}",
+                selector,
+                names.join(", ")
+            );
+        }
+    }
+    experiments
+        .into_iter()
+        .filter(|e| only.is_empty() || only.iter().any(|s| e.matches(s)))
+        .filter(|e| !skip.iter().any(|s| e.matches(s)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(experiments: &[Experiment]) -> Vec<&str> {
+        experiments.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_scenario() {
+        let experiments = parse_scenario(
+            r#"
+[[experiment]]
+name = "Fibonacci"
+kind = "local"
+program = "basic/src/FIB.BAS"
+env = { BLR_BASIC_MODE = "qbasic" }
+count = 10
+
+[[experiment]]
+name = "Interpreter"
+kind = "httpd"
+backend = "interpreter"
+program = "basic/rest-qb-direct"
+image = "my-httpd"
+path = "/api/todo/1"
+body = "item {n}"
+concurrency = 4
+rate = 10.5
+duration = 30
+keep_alive = true
+mix = { create = 1, read = 2 }
+"#,
+        )
+        .unwrap();
+        assert_eq!(names(&experiments), ["Fibonacci", "Interpreter"]);
+        let local = &experiments[0];
+        assert_eq!(local.kind, Kind::Local);
+        assert_eq!(local.backend, Backend::Launcher);
+        assert_eq!(local.env["BLR_BASIC_MODE"], "qbasic");
+        assert_eq!(local.count, Some(10));
+        assert_eq!(local.concurrency, 1);
+        assert_eq!(local.image(), "basic");
+        assert_eq!(local.dockerfile(), "Dockerfile.standalone");
+        let httpd = &experiments[1];
+        assert_eq!(httpd.image(), "my-httpd");
+        assert_eq!(httpd.dockerfile(), "Dockerfile.interpreter.httpd");
+        assert_eq!(httpd.path(), "/api/todo/1");
+        assert_eq!(httpd.body(3), "item 3");
+        assert!(httpd.keep_alive);
+        assert!(!local.keep_alive);
+        assert_eq!(
+            httpd.mix,
+            Some(Mix {
+                create: 1,
+                read: 2,
+                ..Mix::default()
+            })
+        );
+        assert_eq!(local.mix, None);
+        assert_eq!(local.load_options(10), None);
+        assert_eq!(
+            httpd.load_options(10),
+            Some(LoadOptions {
+                concurrency: 4,
+                rate: Some(10.5),
+                duration: Some(Duration::from_secs(30)),
+                requests: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn test_example_scenario() {
+        let experiments = parse_scenario(include_str!("../scenarios/default.toml")).unwrap();
+        assert_eq!(experiments, default_experiments(false));
+    }
+
+    #[test]
+    fn test_crud_scenario() {
+        let experiments = parse_scenario(include_str!("../scenarios/crud.toml")).unwrap();
+        assert!(experiments.iter().all(|e| e.mix.is_some()));
+        assert_eq!(experiments[1].concurrency, 4);
+    }
+
+    #[test]
+    fn test_invalid_scenarios() {
+        for (contents, expected) in [
+            ("[[experiment]]\nname = \"x\"\nkind = \"dos\"\nprogram = \"X.BAS\"\n", "unknown variant"),
+            ("[[experiment]]\nname = \"x\"\nkind = \"local\"\nprogram = \"X.BAS\"\ncolor = 1\n", "unknown field"),
+            ("[[experiment]]\nname = \"x\"\nkind = \"local\"\nbackend = \"interpreter\"\nprogram = \"X.BAS\"\n", "experiment x: the interpreter backend"),
+            ("[[experiment]]\nname = \"x\"\nkind = \"local\"\nprogram = \"X.BAS\"\ncount = 0\n", "count must be positive"),
+            ("[[experiment]]\nname = \"x\"\nkind = \"local\"\nprogram = \"X.BAS\"\nconcurrency = 2\n", "only available with httpd"),
+            ("[[experiment]]\nname = \"x\"\nkind = \"local\"\nprogram = \"X.BAS\"\nkeep_alive = true\n", "only available with httpd"),
+            ("[[experiment]]\nname = \"x\"\nkind = \"httpd\"\nprogram = \"rest\"\nrate = 0\n", "rate must be positive"),
+            ("[[experiment]]\nname = \"x\"\nkind = \"httpd\"\nprogram = \"rest\"\nmix = { list = 0 }\n", "positive weight"),
+            ("[[experiment]]\nname = \"x\"\nkind = \"httpd\"\nprogram = \"rest\"\nmix = { lists = 1 }\n", "unknown field"),
+            ("[[experiment]]\nname = \"x\"\nkind = \"httpd\"\nprogram = \"rest\"\nbody = \"\"\nmix = { create = 1 }\n", "needs a body"),
+        ] {
+            let error = parse_scenario(contents).unwrap_err();
+            assert!(error.contains(expected), "{} in {}", expected, error);
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let all = || default_experiments(false);
+        assert_eq!(names(&select(all(), &[], &[])).len(), 5);
+        assert_eq!(names(&select(all(), &["dos".to_string()], &[])), ["DOS"]);
+        assert_eq!(
+            names(&select(all(), &["httpd".to_string()], &[])),
+            ["Apache (launcher)", "Apache (interpreter)"]
+        );
+        assert_eq!(
+            names(&select(
+                all(),
+                &[],
+                &["docker-outside".to_string(), "Docker (inside)".to_string()]
+            )),
+            ["DOS", "Apache (launcher)", "Apache (interpreter)"]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "No experiment matches apache, available: DOS")]
+    fn test_select_unknown() {
+        select(default_experiments(false), &["apache".to_string()], &[]);
+    }
+
+    #[test]
+    fn test_default_experiments_for_qbasic() {
+        let experiments = default_experiments(true);
+        assert_eq!(experiments[0].program, "basic/src/HELLOQB.BAS");
+        assert_eq!(experiments[3].program, "basic/rest-qb");
+    }
+}
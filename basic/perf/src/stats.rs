@@ -0,0 +1,194 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Summary statistics of the iterations of an experiment, in milliseconds.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Stats {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p90: f64,
+    pub p99: f64,
+    /// Sample standard deviation.
+    pub stddev: f64,
+    /// Half-width of the 95% confidence interval of the mean, `None` for a
+    /// single iteration.
+    pub ci95: Option<f64>,
+}
+
+impl Stats {
+    pub fn from_durations(durations: &[Duration]) -> Self {
+        let samples: Vec<f64> = durations.iter().map(|d| d.as_secs_f64() * 1000.0).collect();
+        Self::from_samples(&samples)
+    }
+
+    /// Panics if there are no samples.
+    pub fn from_samples(samples: &[f64]) -> Self {
+        assert!(!samples.is_empty(), "No samples");
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let count = sorted.len();
+        let mean = sorted.iter().sum::<f64>() / count as f64;
+        let (stddev, ci95) = if count > 1 {
+            let variance =
+                sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1) as f64;
+            let stddev = variance.sqrt();
+            let ci95 = t_value_95(count - 1) * stddev / (count as f64).sqrt();
+            (stddev, Some(ci95))
+        } else {
+            (0.0, None)
+        };
+        Self {
+            count,
+            min: sorted[0],
+            max: sorted[count - 1],
+            mean,
+            median: percentile(&sorted, 50.0),
+            p90: percentile(&sorted, 90.0),
+            p99: percentile(&sorted, 99.0),
+            stddev,
+            ci95,
+        }
+    }
+
+    /// The 95% confidence interval of the mean.
+    pub fn ci95_range(&self) -> Option<(f64, f64)> {
+        self.ci95.map(|h| (self.mean - h, self.mean + h))
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "n={} mean {:.1}", self.count, self.mean)?;
+        if let Some((low, high)) = self.ci95_range() {
+            write!(f, " (95% CI {:.1}..{:.1})", low, high)?;
+        }
+        write!(
+            f,
+            " median {:.1} p90 {:.1} p99 {:.1} min {:.1} max {:.1} stddev {:.1} msec",
+            self.median, self.p90, self.p99, self.min, self.max, self.stddev
+        )
+    }
+}
+
+/// The `p`th percentile of sorted samples, interpolating linearly between the
+/// two closest ranks.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+/// Two-sided 95% critical values of Student's t distribution, by degrees of
+/// freedom.
+const T_TABLE_95: [(usize, f64); 34] = [
+    (1, 12.706),
+    (2, 4.303),
+    (3, 3.182),
+    (4, 2.776),
+    (5, 2.571),
+    (6, 2.447),
+    (7, 2.365),
+    (8, 2.306),
+    (9, 2.262),
+    (10, 2.228),
+    (11, 2.201),
+    (12, 2.179),
+    (13, 2.160),
+    (14, 2.145),
+    (15, 2.131),
+    (16, 2.120),
+    (17, 2.110),
+    (18, 2.101),
+    (19, 2.093),
+    (20, 2.086),
+    (21, 2.080),
+    (22, 2.074),
+    (23, 2.069),
+    (24, 2.064),
+    (25, 2.060),
+    (26, 2.056),
+    (27, 2.052),
+    (28, 2.048),
+    (29, 2.045),
+    (30, 2.042),
+    (40, 2.021),
+    (60, 2.000),
+    (120, 1.980),
+    (1000, 1.962),
+];
+
+/// Between the tabulated degrees of freedom, the value of the lower one is
+/// used, which makes the interval slightly wider.
+fn t_value_95(degrees_of_freedom: usize) -> f64 {
+    T_TABLE_95
+        .iter()
+        .rev()
+        .find(|(df, _)| *df <= degrees_of_freedom)
+        .map_or(T_TABLE_95[0].1, |(_, t)| *t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::from_samples(&[4.0, 1.0, 3.0, 2.0, 5.0]);
+        assert_eq!(stats.count, 5);
+        assert_close(stats.min, 1.0);
+        assert_close(stats.max, 5.0);
+        assert_close(stats.mean, 3.0);
+        assert_close(stats.median, 3.0);
+        assert_close(stats.p90, 4.6);
+        assert_close(stats.p99, 4.96);
+        assert_close(stats.stddev, 2.5f64.sqrt());
+        // t(4) = 2.776
+        assert_close(stats.ci95.unwrap(), 2.776 * 2.5f64.sqrt() / 5f64.sqrt());
+    }
+
+    #[test]
+    fn test_from_durations_is_in_milliseconds() {
+        let stats = Stats::from_durations(&[Duration::from_micros(1500)]);
+        assert_close(stats.mean, 1.5);
+        assert_eq!(stats.stddev, 0.0);
+        assert_eq!(stats.ci95, None);
+    }
+
+    #[test]
+    fn test_percentile_interpolates() {
+        let sorted = [10.0, 20.0];
+        assert_close(percentile(&sorted, 0.0), 10.0);
+        assert_close(percentile(&sorted, 50.0), 15.0);
+        assert_close(percentile(&sorted, 100.0), 20.0);
+    }
+
+    #[test]
+    fn test_t_value() {
+        assert_close(t_value_95(1), 12.706);
+        assert_close(t_value_95(30), 2.042);
+        assert_close(t_value_95(50), 2.021);
+        assert_close(t_value_95(200), 1.980);
+        assert_close(t_value_95(5000), 1.962);
+    }
+
+    #[test]
+    #[should_panic(expected = "No samples")]
+    fn test_no_samples() {
+        Stats::from_samples(&[]);
+    }
+}
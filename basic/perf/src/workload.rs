@@ -0,0 +1,542 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::http::{Client, Request, Response};
+use crate::load::Outcome;
+use crate::scenario::Experiment;
+
+/// How many failures are described in the results.
+const MAX_MESSAGES: usize = 10;
+
+/// The relative weights of the operations of a CRUD workload, e.g.
+/// `{ list = 2, create = 3, read = 3, update = 1, delete = 1 }`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mix {
+    pub list: u32,
+    pub create: u32,
+    pub read: u32,
+    pub update: u32,
+    pub delete: u32,
+}
+
+/// A call of the todo API of `basic/rest`, see its `.htaccess`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    /// `GET /todo`
+    List,
+    /// `POST /todo`
+    Create,
+    /// `GET /todo/ID`
+    Read,
+    /// `POST /todo/ID`
+    Update,
+    /// `DELETE /todo/ID`
+    Delete,
+}
+
+impl Operation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::List => "list",
+            Operation::Create => "create",
+            Operation::Read => "read",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+        }
+    }
+
+    fn needs_item(self) -> bool {
+        matches!(
+            self,
+            Operation::Read | Operation::Update | Operation::Delete
+        )
+    }
+}
+
+impl Mix {
+    fn weights(&self) -> [(Operation, u32); 5] {
+        [
+            (Operation::List, self.list),
+            (Operation::Create, self.create),
+            (Operation::Read, self.read),
+            (Operation::Update, self.update),
+            (Operation::Delete, self.delete),
+        ]
+    }
+
+    pub fn total(&self) -> u32 {
+        self.weights().iter().map(|(_, weight)| weight).sum()
+    }
+
+    /// The operation of request `n`. The operations are spread over the
+    /// requests by their weights, pseudo-randomly but the same in every run.
+    pub fn pick(&self, n: i32) -> Operation {
+        let mut x = spread(n as u64) % self.total() as u64;
+        for (operation, weight) in self.weights() {
+            if x < weight as u64 {
+                return operation;
+            }
+            x -= weight as u64;
+        }
+        unreachable!()
+    }
+}
+
+/// SplitMix64, to pick operations and items without a random generator.
+fn spread(n: u64) -> u64 {
+    let mut z = n.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct OperationSummary {
+    pub count: u32,
+    pub failures: u32,
+    /// The mean duration of the requests that got a response.
+    pub mean_ms: f64,
+}
+
+/// What the checks of a CRUD workload found, as written to the result files.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct WorkloadSummary {
+    /// By the name of the operation, including the warmup requests.
+    pub operations: BTreeMap<String, OperationSummary>,
+    /// How many checks failed, including the final one of the items.
+    pub failures: u32,
+    /// What the first failures were.
+    pub messages: Vec<String>,
+}
+
+#[derive(Default)]
+struct Counts {
+    count: u32,
+    failures: u32,
+    responses: u32,
+    duration: Duration,
+}
+
+#[derive(Default)]
+struct State {
+    /// The items the server should have, as far as the workload knows.
+    items: Vec<String>,
+    initial: usize,
+    created: usize,
+    deleted: usize,
+    operations: BTreeMap<&'static str, Counts>,
+    failures: u32,
+    messages: Vec<String>,
+}
+
+impl State {
+    fn fail(&mut self, message: String) {
+        self.failures += 1;
+        if self.messages.len() < MAX_MESSAGES {
+            self.messages.push(message);
+        }
+    }
+}
+
+/// Sends a mix of list, create, read, update and delete requests to the todo
+/// API at the path of an experiment, and checks the responses.
+///
+/// Items are identified by their position, so when several clients send
+/// requests at the same time the workload cannot know which item a request
+/// gets. Then it only checks the status codes and the JSON of the responses,
+/// accepts 404 for an item another client may have deleted, and checks at
+/// the end that no item got lost. One client at a time, it knows the items
+/// exactly and checks that every list and read returns what was created and
+/// updated.
+pub struct Workload<'a> {
+    experiment: &'a Experiment,
+    mix: Mix,
+    exact: bool,
+    state: Mutex<State>,
+}
+
+impl<'a> Workload<'a> {
+    pub fn new(experiment: &'a Experiment, mix: Mix, exact: bool) -> Self {
+        Self {
+            experiment,
+            mix,
+            exact,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Lists the items the server has before the workload starts.
+    pub fn start(&self, client: &mut Client) -> Result<(), String> {
+        let path = self.experiment.path();
+        let response = client
+            .send(&Request::get(path))
+            .map_err(|e| format!("GET {}: {}", path, e))?;
+        let items = parse_list(&response).map_err(|e| format!("GET {}: {}", path, e))?;
+        let mut state = self.state.lock().unwrap();
+        state.initial = items.len();
+        state.items = items;
+        Ok(())
+    }
+
+    /// Sends request `n` and checks its response.
+    pub fn run(&self, client: &mut Client, n: i32) -> Outcome {
+        let (operation, id) = {
+            let state = self.state.lock().unwrap();
+            match self.mix.pick(n) {
+                // nothing to read, update or delete
+                operation if operation.needs_item() && state.items.is_empty() => {
+                    (Operation::Create, 0)
+                }
+                operation if operation.needs_item() => (
+                    operation,
+                    1 + (spread(!(n as u64)) % state.items.len() as u64) as usize,
+                ),
+                operation => (operation, 0),
+            }
+        };
+        let text = self.experiment.body(n);
+        let path = self.experiment.path();
+        let item_path = format!("{}/{}", path, id);
+        let request = match operation {
+            Operation::List => Request::get(path),
+            Operation::Create => Request::post(path, "text/plain", &text),
+            Operation::Read => Request::get(&item_path),
+            Operation::Update => Request::post(&item_path, "text/plain", &text),
+            Operation::Delete => Request::delete(&item_path),
+        };
+        let result = client.send(&request);
+        let mut state = self.state.lock().unwrap();
+        let counts = state.operations.entry(operation.name()).or_default();
+        counts.count += 1;
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                counts.failures += 1;
+                state.fail(format!("{} {}: {}", request.method, request.path, e));
+                return Outcome::Error(e.to_string());
+            }
+        };
+        counts.responses += 1;
+        counts.duration += response.timing.total();
+        if let Err(message) = self.check(operation, id, &text, &response, &mut state) {
+            state.operations.get_mut(operation.name()).unwrap().failures += 1;
+            state.fail(format!("{} {}: {}", request.method, request.path, message));
+        }
+        Outcome::Status(response.status, response.timing)
+    }
+
+    /// Checks a response and updates the items the server should have.
+    fn check(
+        &self,
+        operation: Operation,
+        id: usize,
+        text: &str,
+        response: &Response,
+        state: &mut State,
+    ) -> Result<(), String> {
+        if operation.needs_item() && response.status == 404 && !self.exact {
+            // deleted by another client since
+            state.items.truncate(id - 1);
+            return Ok(());
+        }
+        match operation {
+            Operation::List => {
+                let items = parse_list(response)?;
+                if self.exact && items != state.items {
+                    return Err(format!("got {:?} instead of {:?}", items, state.items));
+                }
+            }
+            Operation::Create => {
+                expect_status(response, 201)?;
+                state.items.push(text.to_string());
+                state.created += 1;
+            }
+            Operation::Read => {
+                let item = parse_item(response)?;
+                if self.exact && item != state.items[id - 1] {
+                    return Err(format!(
+                        "got {:?} instead of {:?}",
+                        item,
+                        state.items[id - 1]
+                    ));
+                }
+            }
+            Operation::Update => {
+                expect_status(response, 200)?;
+                if let Some(item) = state.items.get_mut(id - 1) {
+                    *item = text.to_string();
+                }
+            }
+            Operation::Delete => {
+                expect_status(response, 200)?;
+                if id <= state.items.len() {
+                    state.items.remove(id - 1);
+                }
+                state.deleted += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the server has the items it should have, and sums up.
+    pub fn finish(&self, client: &mut Client) -> WorkloadSummary {
+        let path = self.experiment.path();
+        let listed = client
+            .send(&Request::get(path))
+            .map_err(|e| e.to_string())
+            .and_then(|response| parse_list(&response));
+        let mut state = self.state.lock().unwrap();
+        let expected = state.initial + state.created - state.deleted;
+        let failure = match listed {
+            Err(e) => Some(e),
+            Ok(items) if self.exact && items != state.items => {
+                Some(format!("got {:?} instead of {:?}", items, state.items))
+            }
+            Ok(items) if items.len() != expected => {
+                Some(format!("got {} items instead of {}", items.len(), expected))
+            }
+            Ok(_) => None,
+        };
+        if let Some(failure) = failure {
+            state.fail(format!("GET {} at the end: {}", path, failure));
+        }
+        WorkloadSummary {
+            operations: state
+                .operations
+                .iter()
+                .map(|(name, counts)| {
+                    let summary = OperationSummary {
+                        count: counts.count,
+                        failures: counts.failures,
+                        mean_ms: if counts.responses > 0 {
+                            counts.duration.as_secs_f64() * 1000.0 / counts.responses as f64
+                        } else {
+                            0.0
+                        },
+                    };
+                    (name.to_string(), summary)
+                })
+                .collect(),
+            failures: state.failures,
+            messages: state.messages.clone(),
+        }
+    }
+}
+
+fn expect_status(response: &Response, status: u16) -> Result<(), String> {
+    if response.status != status {
+        return Err(format!(
+            "expected status {}, got {}",
+            status, response.status
+        ));
+    }
+    Ok(())
+}
+
+fn parse_json(response: &Response) -> Result<Value, String> {
+    expect_status(response, 200)?;
+    let content_type = response.header("Content-Type").unwrap_or_default();
+    if !content_type.starts_with("application/json") {
+        return Err(format!("expected JSON, got {:?}", content_type));
+    }
+    serde_json::from_slice(&response.body).map_err(|e| format!("invalid JSON: {}", e))
+}
+
+/// The items of `GET /todo`, a JSON array of strings.
+fn parse_list(response: &Response) -> Result<Vec<String>, String> {
+    match parse_json(response)? {
+        Value::Array(values) => values
+            .into_iter()
+            .map(|value| match value {
+                Value::String(item) => Ok(item),
+                other => Err(format!("expected a string, got {}", other)),
+            })
+            .collect(),
+        other => Err(format!("expected an array, got {}", other)),
+    }
+}
+
+/// The item of `GET /todo/ID`, e.g. `{"item": "buy milk"}`.
+fn parse_item(response: &Response) -> Result<String, String> {
+    match parse_json(response)?.get("item") {
+        Some(Value::String(item)) => Ok(item.clone()),
+        _ => Err("expected an object with an item".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::tests::{respond, serve};
+    use crate::http::Timing;
+
+    fn response(status: u16, content_type: &str, body: &str) -> Response {
+        Response {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body.as_bytes().to_vec(),
+            timing: Timing::default(),
+        }
+    }
+
+    /// Serves the todo API from memory like `basic/rest`. If `lose` is set,
+    /// it forgets the item of every `lose`th create.
+    fn todo_server(lose: Option<usize>) -> String {
+        let mut items = vec!["buy milk".to_string()];
+        let mut creates = 0;
+        let address = serve(move |request| {
+            let id: Option<usize> = request
+                .path
+                .strip_prefix("/api/todo/")
+                .map(|id| id.parse().unwrap());
+            let (status, content) = match (request.method.as_str(), id) {
+                ("GET", None) => (200, serde_json::to_string(&items).unwrap()),
+                ("POST", None) => {
+                    creates += 1;
+                    if lose.is_none_or(|lose| creates % lose != 0) {
+                        items.push(request.body.clone());
+                    }
+                    (201, String::new())
+                }
+                (_, Some(id)) if id == 0 || id > items.len() => (404, String::new()),
+                ("GET", Some(id)) => (200, format!("{{\"item\": \"{}\"}}", items[id - 1])),
+                ("POST", Some(id)) => {
+                    items[id - 1] = request.body.clone();
+                    (200, String::new())
+                }
+                ("DELETE", Some(id)) => {
+                    items.remove(id - 1);
+                    (200, String::new())
+                }
+                _ => (405, String::new()),
+            };
+            Some(respond(status, &content))
+        });
+        format!("http://{}", address)
+    }
+
+    fn crud_experiment() -> Experiment {
+        let mut experiment = crate::scenario::default_experiments(false).remove(3);
+        experiment.mix = Some(Mix {
+            list: 2,
+            create: 3,
+            read: 3,
+            update: 1,
+            delete: 1,
+        });
+        experiment
+    }
+
+    fn run_workload(lose: Option<usize>, requests: i32) -> WorkloadSummary {
+        let url = todo_server(lose);
+        let experiment = crud_experiment();
+        let workload = Workload::new(&experiment, experiment.mix.unwrap(), true);
+        let mut client = Client::new(&url, false);
+        workload.start(&mut client).unwrap();
+        for n in 1..requests + 1 {
+            workload.run(&mut client, n);
+        }
+        workload.finish(&mut client)
+    }
+
+    #[test]
+    fn test_pick_follows_the_weights() {
+        let mix = Mix {
+            list: 1,
+            create: 3,
+            ..Mix::default()
+        };
+        let creates = (0..4000)
+            .filter(|n| mix.pick(*n) == Operation::Create)
+            .count();
+        assert!((2800..3200).contains(&creates), "{}", creates);
+        assert_eq!(mix.pick(17), mix.pick(17));
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            parse_list(&response(
+                200,
+                "application/json",
+                "[\r\n\"buy milk\"\r\n,\r\n\"walk the dog\"\r\n]\r\n"
+            )),
+            Ok(vec!["buy milk".to_string(), "walk the dog".to_string()])
+        );
+        assert_eq!(
+            parse_list(&response(200, "application/json", "[\n]\n")),
+            Ok(vec![])
+        );
+        assert_eq!(
+            parse_list(&response(200, "text/plain", "[]")),
+            Err("expected JSON, got \"text/plain\"".to_string())
+        );
+        assert_eq!(
+            parse_list(&response(500, "application/json", "")),
+            Err("expected status 200, got 500".to_string())
+        );
+        assert!(parse_list(&response(200, "application/json", "[\"a\" \"b\"]")).is_err());
+    }
+
+    #[test]
+    fn test_parse_item() {
+        assert_eq!(
+            parse_item(&response(
+                200,
+                "application/json",
+                "{\"item\": \"buy milk\"}"
+            )),
+            Ok("buy milk".to_string())
+        );
+        assert!(parse_item(&response(200, "application/json", "[]")).is_err());
+    }
+
+    #[test]
+    fn test_workload_against_a_correct_server() {
+        let summary = run_workload(None, 60);
+        assert_eq!(summary.failures, 0, "{:?}", summary.messages);
+        let total: u32 = summary.operations.values().map(|o| o.count).sum();
+        assert_eq!(total, 60);
+        assert!(summary.operations["create"].count > 0);
+        assert!(summary.operations["read"].count > 0);
+        assert!(summary.operations["delete"].count > 0);
+    }
+
+    #[test]
+    fn test_concurrent_workload() {
+        let url = todo_server(None);
+        let experiment = crud_experiment();
+        let workload = Workload::new(&experiment, experiment.mix.unwrap(), false);
+        workload.start(&mut Client::new(&url, false)).unwrap();
+        let options = crate::load::LoadOptions {
+            concurrency: 4,
+            rate: None,
+            duration: None,
+            requests: 80,
+        };
+        crate::load::run_load(options, 1, || {
+            let mut client = Client::new(&url, false);
+            let workload = &workload;
+            move |n| workload.run(&mut client, n)
+        });
+        let summary = workload.finish(&mut Client::new(&url, false));
+        assert_eq!(summary.failures, 0, "{:?}", summary.messages);
+    }
+
+    #[test]
+    fn test_workload_finds_lost_items() {
+        let summary = run_workload(Some(3), 60);
+        assert!(summary.failures > 0);
+        assert!(
+            summary.messages.iter().any(|m| m.contains("instead of")),
+            "{:?}",
+            summary.messages
+        );
+        assert!(summary.messages.len() <= MAX_MESSAGES);
+    }
+}